@@ -119,6 +119,112 @@ fn case_meta_long(c: &mut criterion::Criterion) {
     group.finish();
 }
 
+fn case_shift_and(c: &mut criterion::Criterion) {
+    let pattern = r"(a|b)*a(a|b)(a|b)(a|b)(a|b)(a|b)(a|b)(a|b)(a|b)(a|b)(a|b)\d";
+    let input = format!("{}a0", "ab".repeat(100_000));
+
+    let mut group = c.benchmark_group("case shift-and");
+    for method in ["shift-and", "dfa", "vm"] {
+        let engine = rustegex::Engine::new(pattern, method).unwrap();
+        group.bench_function(format!("rustegex/{method}"), |b| {
+            b.iter(|| engine.is_match(&input));
+        });
+    }
+
+    let re = regex::Regex::new(pattern).unwrap();
+    group.bench_function("regex", |b| {
+        b.iter(|| re.is_match(&input));
+    });
+    group.finish();
+}
+
+fn case_compile(c: &mut criterion::Criterion) {
+    let keywords: Vec<String> = (0..2000).map(|i| format!("keyword{i}(s|ed)?")).collect();
+    let nth = format!("(a|b)*a{}", "(a|b)".repeat(12));
+    let emails: Vec<String> = (0..100)
+        .map(|i| format!(r"user{i}@\w+\.(com|net)"))
+        .collect();
+    let patterns = [
+        ("keywords", keywords.join("|")),
+        ("nth-from-end", nth),
+        ("emails", emails.join("|")),
+    ];
+
+    let mut group = c.benchmark_group("case compile");
+    group.sample_size(10);
+    for (name, pattern) in &patterns {
+        for construction in [
+            rustegex::NfaConstruction::Thompson,
+            rustegex::NfaConstruction::Glushkov,
+        ] {
+            let builder = rustegex::EngineBuilder::new()
+                .method(rustegex::Method::Dfa)
+                .nfa_construction(construction)
+                .dfa_state_limit(1 << 20)
+                .prefilter(false);
+            group.bench_function(format!("rustegex/dfa/{construction}/{name}"), |b| {
+                b.iter(|| builder.build(pattern).unwrap());
+            });
+        }
+
+        group.bench_function(format!("regex/{name}"), |b| {
+            b.iter(|| regex::Regex::new(pattern).unwrap());
+        });
+    }
+    group.finish();
+}
+
+fn case_keywords(c: &mut criterion::Criterion) {
+    let keywords: Vec<String> = (0..2000).map(|i| format!("keyword{i}")).collect();
+    let pattern = keywords.join("|");
+    let input = format!("{}keyword1999", "lorem ipsum ".repeat(10_000));
+
+    let mut group = c.benchmark_group("case keywords");
+    group.bench_function("rustegex/aho-corasick/build", |b| {
+        b.iter(|| rustegex::Engine::with_method(&pattern, rustegex::Method::AhoCorasick).unwrap());
+    });
+
+    let ac = rustegex::Engine::with_method(&pattern, rustegex::Method::AhoCorasick).unwrap();
+    group.bench_function("rustegex/aho-corasick", |b| {
+        b.iter(|| ac.find(&input));
+    });
+
+    let vm = rustegex::Engine::new(&pattern, "vm").unwrap();
+    group.bench_function("rustegex/vm", |b| {
+        b.iter(|| vm.find(&input));
+    });
+
+    let re = regex::Regex::new(&pattern).unwrap();
+    group.bench_function("regex", |b| {
+        b.iter(|| re.find(&input));
+    });
+    group.finish();
+}
+
+fn case_captures(c: &mut criterion::Criterion) {
+    // Not one-pass (\w+ and \d+ overlap), so the VM side runs Pike VM captures.
+    let pattern = r"(\w+)(\d+)-(\d+)";
+    let input = format!("{}1234-5678", "user".repeat(1000));
+
+    let mut group = c.benchmark_group("case captures");
+    let tdfa = rustegex::Engine::new(pattern, "tdfa").unwrap();
+    group.bench_function("rustegex/tdfa", |b| {
+        b.iter(|| tdfa.captures(&input));
+    });
+
+    let vm = rustegex::Engine::new(pattern, "vm").unwrap();
+    assert_eq!(vm.captures(&input), tdfa.captures(&input));
+    group.bench_function("rustegex/vm", |b| {
+        b.iter(|| vm.captures(&input));
+    });
+
+    let re = regex::Regex::new(pattern).unwrap();
+    group.bench_function("regex", |b| {
+        b.iter(|| re.captures(&input));
+    });
+    group.finish();
+}
+
 criterion::criterion_group!(
     benches,
     case_1,
@@ -126,5 +232,9 @@ criterion::criterion_group!(
     case_long,
     case_meta,
     case_meta_long,
+    case_shift_and,
+    case_compile,
+    case_keywords,
+    case_captures,
 );
 criterion::criterion_main!(benches);
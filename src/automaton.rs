@@ -1,3 +1,8 @@
+pub mod aho_corasick;
+pub mod antimirov;
 pub mod dfa;
+pub mod glushkov;
 pub mod label;
+pub mod lazy;
 pub mod nfa;
+pub mod shift_and;
@@ -0,0 +1,222 @@
+const ROOT: usize = 0;
+
+#[derive(Debug)]
+struct State {
+    transitions: Vec<(u8, u32)>,
+    fail: usize,
+    depth: usize,
+    pattern: Option<usize>,
+    longest: Option<usize>,
+}
+
+impl State {
+    fn new(depth: usize) -> Self {
+        State {
+            transitions: Vec::new(),
+            fail: ROOT,
+            depth,
+            pattern: None,
+            longest: None,
+        }
+    }
+
+    fn goto(&self, byte: u8) -> Option<usize> {
+        self.transitions
+            .binary_search_by_key(&byte, |&(b, _)| b)
+            .ok()
+            .map(|i| self.transitions[i].1 as usize)
+    }
+}
+
+#[derive(Debug)]
+pub struct AhoCorasick {
+    kind: crate::MatchKind,
+    states: Vec<State>,
+    root: Box<[u32; 256]>,
+    max_len: usize,
+}
+
+impl AhoCorasick {
+    pub fn new(patterns: &[String], kind: crate::MatchKind) -> AhoCorasick {
+        let mut states = vec![State::new(0)];
+        for (id, pattern) in patterns.iter().enumerate() {
+            let mut state = ROOT;
+            for &byte in pattern.as_bytes() {
+                state = match states[state].goto(byte) {
+                    Some(next) => next,
+                    None => {
+                        let next = states.len();
+                        states.push(State::new(states[state].depth + 1));
+                        let transitions = &mut states[state].transitions;
+                        let at = transitions.partition_point(|&(b, _)| b < byte);
+                        transitions.insert(at, (byte, next as u32));
+                        next
+                    }
+                };
+            }
+            states[state].pattern.get_or_insert(id);
+        }
+
+        let mut root = Box::new([ROOT as u32; 256]);
+        for &(byte, next) in &states[ROOT].transitions {
+            root[byte as usize] = next;
+        }
+        states[ROOT].longest = states[ROOT].pattern.map(|_| 0);
+
+        let mut queue = std::collections::VecDeque::from([ROOT]);
+        while let Some(state) = queue.pop_front() {
+            for i in 0..states[state].transitions.len() {
+                let (byte, next) = states[state].transitions[i];
+                let next = next as usize;
+                let fail = if state == ROOT {
+                    ROOT
+                } else {
+                    let mut fail = states[state].fail;
+                    loop {
+                        if let Some(target) = states[fail].goto(byte) {
+                            break target;
+                        }
+                        if fail == ROOT {
+                            break ROOT;
+                        }
+                        fail = states[fail].fail;
+                    }
+                };
+                states[next].fail = fail;
+                states[next].longest = match states[next].pattern {
+                    Some(_) => Some(states[next].depth),
+                    None => states[fail].longest,
+                };
+                queue.push_back(next);
+            }
+        }
+
+        AhoCorasick {
+            kind,
+            states,
+            root,
+            max_len: patterns.iter().map(String::len).max().unwrap_or(0),
+        }
+    }
+
+    fn next_state(&self, mut state: usize, byte: u8) -> usize {
+        loop {
+            if state == ROOT {
+                return self.root[byte as usize] as usize;
+            }
+            if let Some(next) = self.states[state].goto(byte) {
+                return next;
+            }
+            state = self.states[state].fail;
+        }
+    }
+
+    pub fn find_start(&self, haystack: &str) -> Option<usize> {
+        let mut best = self.states[ROOT].longest.map(|_| 0);
+        let mut state = ROOT;
+        for (i, &byte) in haystack.as_bytes().iter().enumerate() {
+            if best.is_some_and(|start| i + 1 >= start + self.max_len) {
+                break;
+            }
+            state = self.next_state(state, byte);
+            if let Some(len) = self.states[state].longest {
+                let start = i + 1 - len;
+                best = Some(best.map_or(start, |best| best.min(start)));
+            }
+        }
+        best
+    }
+
+    pub fn find(&self, haystack: &str) -> Option<(usize, usize)> {
+        let start = self.find_start(haystack)?;
+        let mut state = ROOT;
+        let mut found = self.states[ROOT].pattern.map(|id| (id, start));
+        for (i, &byte) in haystack.as_bytes()[start..].iter().enumerate() {
+            let Some(next) = self.states[state].goto(byte) else {
+                break;
+            };
+            state = next;
+            let Some(id) = self.states[state].pattern else {
+                continue;
+            };
+            let replace = match self.kind {
+                crate::MatchKind::LeftmostLongest => true,
+                crate::MatchKind::LeftmostFirst => found.is_none_or(|(best, _)| id < best),
+            };
+            if replace {
+                found = Some((id, start + i + 1));
+            }
+        }
+        found.map(|(_, end)| (start, end))
+    }
+
+    pub fn is_match(&self, input: &str) -> bool {
+        let mut state = ROOT;
+        for &byte in input.as_bytes() {
+            match self.states[state].goto(byte) {
+                Some(next) => state = next,
+                None => return false,
+            }
+        }
+        self.states[state].pattern.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(patterns: &[&str], kind: crate::MatchKind) -> AhoCorasick {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        AhoCorasick::new(&patterns, kind)
+    }
+
+    #[test]
+    fn match_kinds() {
+        let longest = build(&["a", "ab", "abcd", "c"], crate::MatchKind::LeftmostLongest);
+        let first = build(&["a", "ab", "abcd", "c"], crate::MatchKind::LeftmostFirst);
+        assert_eq!(longest.find("xabcd"), Some((1, 5)));
+        assert_eq!(first.find("xabcd"), Some((1, 2)));
+        assert_eq!(longest.find("xxc"), Some((2, 3)));
+        assert_eq!(longest.find("xyz"), None);
+
+        let first = build(&["bcd", "abcde"], crate::MatchKind::LeftmostFirst);
+        assert_eq!(first.find("zabcdef"), Some((1, 6)));
+        let first = build(&["b", "abc", "ab"], crate::MatchKind::LeftmostFirst);
+        assert_eq!(first.find("ab"), Some((0, 2)));
+    }
+
+    #[test]
+    fn failure_links() {
+        let ac = build(
+            &["he", "she", "his", "hers"],
+            crate::MatchKind::LeftmostLongest,
+        );
+        assert_eq!(ac.find("ushers"), Some((1, 4)));
+        assert_eq!(ac.find("ahishe"), Some((1, 4)));
+        assert_eq!(ac.find("hhhhers"), Some((3, 7)));
+
+        let ac = build(&["太郎", "次郎", "郎"], crate::MatchKind::LeftmostLongest);
+        assert_eq!(ac.find("正規表現次郎"), Some((12, 18)));
+    }
+
+    #[test]
+    fn empty_pattern() {
+        let ac = build(&["abc", ""], crate::MatchKind::LeftmostLongest);
+        assert_eq!(ac.find("abc"), Some((0, 3)));
+        assert_eq!(ac.find("xabc"), Some((0, 0)));
+        let ac = build(&["", "abc"], crate::MatchKind::LeftmostFirst);
+        assert_eq!(ac.find("abc"), Some((0, 0)));
+        assert!(ac.is_match(""));
+    }
+
+    #[test]
+    fn full_match() {
+        let ac = build(&["foo", "foobar", "baz"], crate::MatchKind::LeftmostFirst);
+        assert!(ac.is_match("foo"));
+        assert!(ac.is_match("foobar"));
+        assert!(!ac.is_match("fooba"));
+        assert!(!ac.is_match("xbaz"));
+        assert!(!ac.is_match(""));
+    }
+}
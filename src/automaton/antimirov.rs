@@ -0,0 +1,188 @@
+const NONE: usize = usize::MAX;
+
+#[derive(Debug)]
+pub struct Threads {
+    current: Vec<u32>,
+    next: Vec<u32>,
+    starts: Vec<usize>,
+    next_starts: Vec<usize>,
+}
+
+impl Threads {
+    fn new(states: usize) -> Self {
+        Threads {
+            current: Vec::with_capacity(states),
+            next: Vec::with_capacity(states),
+            starts: vec![NONE; states],
+            next_starts: vec![NONE; states],
+        }
+    }
+
+    fn reset(&mut self) {
+        for &state in &self.current {
+            self.starts[state as usize] = NONE;
+        }
+        self.current.clear();
+    }
+}
+
+#[derive(Debug)]
+pub struct Antimirov {
+    nfa: crate::automaton::nfa::NfaEdges,
+    size: crate::stats::NfaSize,
+    pool: crate::pool::Pool<Threads>,
+}
+
+impl Antimirov {
+    pub fn new(ast: crate::parser::AstNode, config: &crate::config::Config) -> crate::Result<Self> {
+        let nfa = crate::automaton::nfa::Nfa::from_partial_derivatives(
+            &ast,
+            &mut crate::automaton::nfa::NfaState::with_limits(
+                config.max_nfa_states,
+                config.max_nfa_transitions,
+            ),
+        )?;
+        let size = nfa.size(crate::config::NfaConstruction::Antimirov);
+        let states = nfa.state_count();
+
+        Ok(Antimirov {
+            nfa: crate::automaton::nfa::NfaEdges::new(&nfa),
+            size,
+            pool: crate::pool::Pool::new(move || Threads::new(states)),
+        })
+    }
+
+    pub fn size(&self) -> crate::stats::NfaSize {
+        self.size
+    }
+
+    pub fn create_cache(&self) -> Threads {
+        Threads::new(self.size.states)
+    }
+
+    fn add_start(&self, threads: &mut Threads, at: usize) {
+        for &state in &self.nfa.start {
+            let start = &mut threads.starts[state as usize];
+            if *start == NONE {
+                threads.current.push(state);
+                *start = at;
+            }
+        }
+    }
+
+    fn step(&self, threads: &mut Threads, ch: char) {
+        let Threads {
+            current,
+            next,
+            starts,
+            next_starts,
+        } = threads;
+        for &state in current.iter() {
+            let start = std::mem::replace(&mut starts[state as usize], NONE);
+            for (label, targets) in &self.nfa.edges[state as usize] {
+                let matched = match label {
+                    crate::automaton::label::NfaLabel::Char(expected) => *expected == ch,
+                    crate::automaton::label::NfaLabel::Class(class) => class.matches(ch),
+                };
+                if !matched {
+                    continue;
+                }
+                for &to in targets {
+                    let slot = &mut next_starts[to as usize];
+                    if *slot == NONE {
+                        next.push(to);
+                    }
+                    *slot = (*slot).min(start);
+                }
+            }
+        }
+        current.clear();
+        std::mem::swap(current, next);
+        std::mem::swap(starts, next_starts);
+    }
+
+    pub fn is_match(&self, input: &str) -> bool {
+        self.is_match_with(&mut self.pool.get(), input)
+    }
+
+    pub fn is_match_with(&self, threads: &mut Threads, input: &str) -> bool {
+        threads.reset();
+        self.add_start(threads, 0);
+        for ch in input.chars() {
+            self.step(threads, ch);
+            if threads.current.is_empty() {
+                return false;
+            }
+        }
+        threads
+            .current
+            .iter()
+            .any(|&state| self.nfa.accept.contains(state as usize))
+    }
+
+    pub fn find(&self, input: &str) -> Option<(usize, usize)> {
+        self.find_with(&mut self.pool.get(), input)
+    }
+
+    pub fn find_with(&self, threads: &mut Threads, input: &str) -> Option<(usize, usize)> {
+        threads.reset();
+        let mut best: Option<(usize, usize)> = None;
+        let mut chars = input.char_indices();
+        let mut at = 0;
+        loop {
+            if best.is_none() {
+                self.add_start(threads, at);
+            }
+            for &state in &threads.current {
+                if !self.nfa.accept.contains(state as usize) {
+                    continue;
+                }
+                let start = threads.starts[state as usize];
+                best = match best {
+                    Some((best_start, _)) if best_start < start => best,
+                    _ => Some((start, at)),
+                };
+            }
+
+            let Some((index, ch)) = chars.next() else {
+                break;
+            };
+            self.step(threads, ch);
+            at = index + ch.len_utf8();
+            if threads.current.is_empty() && best.is_some() {
+                break;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn antimirov(pattern: &str) -> Antimirov {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let ast = crate::parser::Parser::new(&mut lexer).parse().unwrap();
+        Antimirov::new(ast, &crate::config::Config::default()).unwrap()
+    }
+
+    #[test]
+    fn simulation() {
+        let regex = antimirov("(a|ab)(c|bcd)(d*)");
+        assert!(regex.is_match("abcd"));
+        assert!(regex.is_match("abcdd"));
+        assert!(!regex.is_match("abd"));
+        assert_eq!(regex.find("xxabcdd"), Some((2, 7)));
+        assert_eq!(regex.find("xabx"), None);
+
+        let regex = antimirov(r"\d+|正規.");
+        assert_eq!(regex.find("ab 123 c"), Some((3, 6)));
+        assert_eq!(regex.find("正規表現"), Some((0, 9)));
+        assert_eq!(regex.find("a正規\n1"), Some((8, 9)));
+
+        let regex = antimirov("b*");
+        assert!(regex.is_match(""));
+        assert_eq!(regex.find("abb"), Some((0, 0)));
+    }
+}
@@ -1,23 +1,32 @@
+mod classes;
+mod layout;
+mod minimize;
+mod subset;
+mod tagged;
+
+pub use tagged::TaggedDfa;
+
 use foldhash::HashMapExt as _;
 
 pub type DfaStateID = u64;
-const DEAD: DfaStateID = DfaStateID::MAX;
-const ACCEL_MIN_REMAINING: usize = 32;
+pub const DEAD: DfaStateID = DfaStateID::MAX;
+pub const ACCEL_MIN_REMAINING: usize = 32;
+const STRIDE: usize = 256;
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
-struct Accel {
-    loop_byte: Option<u8>,
-    class_loop: Option<(crate::charclass::CharClass, DfaStateID)>,
+pub struct Accel {
+    pub loop_byte: Option<u8>,
+    pub class_loop: Option<(crate::charclass::CharClass, DfaStateID)>,
     needles: [u8; 3],
     needle_len: u8,
 }
 
 impl Accel {
-    fn is_enabled(self) -> bool {
+    pub fn is_enabled(self) -> bool {
         self.loop_byte.is_some() || self.class_loop.is_some() || self.needle_len > 0
     }
 
-    fn memchr_fwd(&self, haystack: &[u8], at: usize) -> Option<usize> {
+    pub fn memchr_fwd(&self, haystack: &[u8], at: usize) -> Option<usize> {
         if self.needle_len == 0 {
             return None;
         }
@@ -30,39 +39,329 @@ impl Accel {
         };
         Some(at + offset)
     }
+
+    fn encode(self) -> [u8; 8] {
+        [
+            self.loop_byte.is_some() as u8,
+            self.loop_byte.unwrap_or(0),
+            self.class_loop.map_or(u8::MAX, |(class, _)| class.id()),
+            self.needle_len,
+            self.needles[0],
+            self.needles[1],
+            self.needles[2],
+            0,
+        ]
+    }
+
+    fn decode(bytes: [u8; 8], state: DfaStateID) -> Option<Accel> {
+        let loop_byte = match bytes[0] {
+            0 => None,
+            1 => Some(bytes[1]),
+            _ => return None,
+        };
+        let class_loop = match bytes[2] {
+            u8::MAX => None,
+            id => Some((crate::charclass::CharClass::from_id(id)?, state)),
+        };
+        if bytes[3] > 3 {
+            return None;
+        }
+        Some(Accel {
+            loop_byte,
+            class_loop,
+            needles: [bytes[4], bytes[5], bytes[6]],
+            needle_len: bytes[3],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Utf8 {
+    Lead,
+    Continue(u8),
+    Trail(u8),
+}
+
+enum Utf8Step {
+    Fail,
+    Done,
+    Next(Utf8),
+}
+
+impl Utf8 {
+    fn step(self, byte: u8, reverse: bool) -> Utf8Step {
+        let width = match byte {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 0,
+        };
+        let continuation = (0x80..=0xBF).contains(&byte);
+        match self {
+            Utf8::Lead if !reverse && width > 0 => Utf8Step::Next(Utf8::Continue(width - 1)),
+            Utf8::Lead if reverse && continuation => Utf8Step::Next(Utf8::Trail(1)),
+            Utf8::Continue(1) if continuation => Utf8Step::Done,
+            Utf8::Continue(n) if continuation => Utf8Step::Next(Utf8::Continue(n - 1)),
+            Utf8::Trail(n) if continuation && n < 3 => Utf8Step::Next(Utf8::Trail(n + 1)),
+            Utf8::Trail(n) if width == n + 1 => Utf8Step::Done,
+            _ => Utf8Step::Fail,
+        }
+    }
+}
+
+struct Utf8Compiler {
+    base: usize,
+    reverse: bool,
+    rows: Vec<DfaStateID>,
+    ops: Vec<u32>,
+    interned: foldhash::HashMap<(Vec<DfaStateID>, Vec<u32>), DfaStateID>,
+    fallbacks: foldhash::HashMap<(Utf8, (DfaStateID, u32)), DfaStateID>,
+}
+
+impl Utf8Compiler {
+    fn fill(
+        &mut self,
+        row: &mut [DfaStateID],
+        ops: &mut [u32],
+        entries: &[(Vec<u8>, DfaStateID, u32)],
+        depth: usize,
+        utf8: Utf8,
+        any: Option<(DfaStateID, u32)>,
+    ) {
+        let mut groups = entries.chunk_by(|a, b| a.0[depth] == b.0[depth]).peekable();
+        for byte in 0x80..=0xFFu8 {
+            let group = match groups.peek() {
+                Some(group) if group[0].0[depth] == byte => groups.next().unwrap(),
+                _ => &[],
+            };
+            (row[byte as usize], ops[byte as usize]) = match (group, utf8.step(byte, self.reverse))
+            {
+                ([(bytes, target, op)], _) if bytes.len() == depth + 1 => (*target, *op),
+                ([], Utf8Step::Done) => any.unwrap_or((DEAD, 0)),
+                ([], Utf8Step::Next(_)) if any.is_none() => (DEAD, 0),
+                (group, Utf8Step::Next(next)) => {
+                    (self.intermediate(group, depth + 1, next, any), 0)
+                }
+                _ => (DEAD, 0),
+            };
+        }
+    }
+
+    fn intermediate(
+        &mut self,
+        entries: &[(Vec<u8>, DfaStateID, u32)],
+        depth: usize,
+        utf8: Utf8,
+        any: Option<(DfaStateID, u32)>,
+    ) -> DfaStateID {
+        let fallback = any.filter(|_| entries.is_empty()).map(|any| (utf8, any));
+        if let Some(id) = fallback.and_then(|key| self.fallbacks.get(&key)) {
+            return *id;
+        }
+        let mut row = vec![DEAD; STRIDE];
+        let mut ops = vec![0; STRIDE];
+        self.fill(&mut row, &mut ops, entries, depth, utf8, any);
+        let next = (self.base + self.interned.len()) as DfaStateID;
+        let id = *self
+            .interned
+            .entry((row.clone(), ops.clone()))
+            .or_insert_with(|| {
+                self.rows.extend_from_slice(&row);
+                self.ops.extend_from_slice(&ops);
+                next
+            });
+        if let Some(key) = fallback {
+            self.fallbacks.insert(key, id);
+        }
+        id
+    }
+}
+
+pub struct CharDfa {
+    pub start: usize,
+    pub accepts: Vec<bool>,
+    pub chars: Vec<(DfaStateID, char, DfaStateID)>,
+    pub any: Vec<(DfaStateID, DfaStateID)>,
+}
+
+struct Table {
+    start: usize,
+    accepts: Vec<bool>,
+    classes: [u8; 256],
+    stride: usize,
+    next: Vec<DfaStateID>,
+    ops: Vec<u32>,
+    finals: Vec<Box<[u32]>>,
+}
+
+impl Table {
+    fn state_count(&self) -> usize {
+        self.accepts.len()
+    }
+
+    fn row(&self, state: usize) -> Vec<DfaStateID> {
+        self.classes
+            .iter()
+            .map(|&class| self.next[state * self.stride + class as usize])
+            .collect()
+    }
+}
+
+macro_rules! dispatch {
+    ($dfa:expr, $table:ident => $body:expr) => {
+        match &$dfa.transitions {
+            layout::Transitions::Dense16($table) => $body,
+            layout::Transitions::Dense32($table) => $body,
+            layout::Transitions::Sparse16($table) => $body,
+            layout::Transitions::Sparse32($table) => $body,
+        }
+    };
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Dfa {
-    start: DfaStateID,
-    accepts: bit_set::BitSet,
+pub struct Dfa<'a> {
+    start: usize,
+    accept_from: usize,
     state_count: usize,
-    ascii_table: Vec<DfaStateID>,
-    unicode_table: Vec<foldhash::HashMap<char, DfaStateID>>,
-    unicode_class: Vec<Vec<(crate::charclass::CharClass, DfaStateID)>>,
-    accels: Vec<Accel>,
+    classes: std::borrow::Cow<'a, [u8]>,
+    transitions: layout::Transitions<'a>,
+    accels: std::borrow::Cow<'a, [[u8; 8]]>,
+    reverse: bool,
+    minimization: Option<crate::stats::Minimization>,
 }
 
-impl Dfa {
-    pub fn new(start: DfaStateID, accepts: bit_set::BitSet) -> Self {
-        Dfa {
-            start,
-            accepts,
-            state_count: 0,
-            ascii_table: Vec::new(),
-            unicode_table: Vec::new(),
-            unicode_class: Vec::new(),
-            accels: Vec::new(),
+impl Dfa<'_> {
+    fn encode(
+        table: &Table,
+        config: &crate::config::Config,
+        reverse: bool,
+    ) -> crate::Result<(Dfa<'static>, Vec<usize>)> {
+        let (rejecting, accepting): (Vec<usize>, Vec<usize>) =
+            (0..table.state_count()).partition(|&state| !table.accepts[state]);
+        let order: Vec<usize> = rejecting.iter().chain(&accepting).copied().collect();
+        let mut index = vec![0; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            index[old] = new;
+        }
+
+        let rows: Vec<Vec<usize>> = order
+            .iter()
+            .map(|&state| {
+                table.next[state * table.stride..(state + 1) * table.stride]
+                    .iter()
+                    .map(|&next| match next {
+                        DEAD => layout::DEAD,
+                        next => index[next as usize],
+                    })
+                    .collect()
+            })
+            .collect();
+        let (transitions, offsets) =
+            layout::Transitions::encode(config.dfa_layout, table.stride, &rows).ok_or(
+                crate::Error::LimitExceeded(crate::Limit::DfaBytes, config.max_dfa_bytes),
+            )?;
+        crate::config::Limit::DfaBytes.check(transitions.table_bytes(), config.max_dfa_bytes)?;
+
+        let accels = match (transitions.layout(), reverse) {
+            (crate::config::DfaLayout::Dense, false) => order
+                .iter()
+                .zip(&offsets)
+                .map(|(&state, &offset)| {
+                    let row: Vec<DfaStateID> = table
+                        .row(state)
+                        .into_iter()
+                        .map(|next| match next {
+                            DEAD => DEAD,
+                            next => offsets[index[next as usize]] as DfaStateID,
+                        })
+                        .collect();
+                    build_accel(offset, &row).encode()
+                })
+                .collect(),
+            (crate::config::DfaLayout::Dense, true) => {
+                vec![Accel::default().encode(); order.len()]
+            }
+            (crate::config::DfaLayout::Sparse, _) => Vec::new(),
+        };
+
+        let dfa = Dfa {
+            start: offsets[index[table.start]],
+            accept_from: offsets
+                .get(rejecting.len())
+                .copied()
+                .unwrap_or(transitions.table_bytes() / transitions.state_id_bytes()),
+            state_count: order.len(),
+            classes: table.classes.to_vec().into(),
+            transitions,
+            accels: accels.into(),
+            reverse,
+            minimization: None,
+        };
+        Ok((dfa, order))
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.state_count
+    }
+
+    pub fn stats(&self, reverse: Option<&Dfa>) -> crate::stats::Stats {
+        crate::stats::Stats::new(
+            self.minimization,
+            Some(self.memory()),
+            reverse.map(Dfa::memory),
+        )
+    }
+
+    pub fn memory(&self) -> crate::stats::Memory {
+        let table_bytes = self.transitions.table_bytes();
+        crate::stats::Memory {
+            layout: self.transitions.layout(),
+            states: self.state_count,
+            alphabet_len: classes::alphabet_len(&self.classes),
+            state_id_bytes: self.transitions.state_id_bytes(),
+            table_bytes,
+            total_bytes: table_bytes
+                + std::mem::size_of_val(&*self.classes)
+                + std::mem::size_of_val(&*self.accels),
         }
     }
 
+    #[inline]
+    fn is_accept(&self, state: usize) -> bool {
+        state >= self.accept_from
+    }
+
+    #[inline]
+    fn accel_at(&self, index: usize, state: usize) -> Accel {
+        Accel::decode(self.accels[index], state as DfaStateID).unwrap_or_default()
+    }
+
+    #[inline(always)]
+    fn next_state<T: layout::Table>(&self, table: &T, state: usize, byte: u8) -> usize {
+        table.next(state, *unsafe { self.classes.get_unchecked(byte as usize) })
+    }
+
+    #[cfg(test)]
+    fn offsets(&self) -> Vec<usize> {
+        self.transitions.offsets(self.state_count).unwrap()
+    }
+
+    #[cfg(test)]
+    fn index(&self, state: usize) -> DfaStateID {
+        match state {
+            layout::DEAD => DEAD,
+            state => self.offsets().binary_search(&state).unwrap() as DfaStateID,
+        }
+    }
+
+    #[cfg(test)]
     pub fn start(&self) -> DfaStateID {
-        self.start
+        self.index(self.start)
     }
 
     #[cfg(test)]
     pub fn accepts_contains(&self, state: DfaStateID) -> bool {
-        self.accepts.contains(state as usize)
+        self.is_accept(self.offsets()[state as usize])
     }
 
     #[cfg(test)]
@@ -70,7 +369,7 @@ impl Dfa {
         &self,
         state: DfaStateID,
     ) -> (Option<u8>, Option<crate::charclass::CharClass>, u8, [u8; 3]) {
-        let accel = self.accels[state as usize];
+        let accel = self.accel_at(state as usize, self.offsets()[state as usize]);
         (
             accel.loop_byte,
             accel.class_loop.map(|(class, _)| class),
@@ -80,205 +379,264 @@ impl Dfa {
     }
 
     #[cfg(test)]
-    pub fn transitions(&self) -> std::collections::BTreeSet<(DfaStateID, char, DfaStateID)> {
+    pub fn transitions(&self) -> std::collections::BTreeSet<(DfaStateID, u8, DfaStateID)> {
+        let offsets = self.offsets();
         let mut result = std::collections::BTreeSet::new();
-        for state in 0..self.state_count {
-            for byte in 0u8..128 {
-                let next = self.ascii_table[state * 128 + byte as usize];
-                if next != DEAD {
-                    result.insert((state as DfaStateID, byte as char, next));
+        for (state, &offset) in offsets.iter().enumerate() {
+            for byte in 0..=u8::MAX {
+                let next = dispatch!(self, table => self.next_state(table, offset, byte));
+                if next != layout::DEAD {
+                    result.insert((state as DfaStateID, byte, self.index(next)));
                 }
             }
-            for (&c, &next) in &self.unicode_table[state] {
-                result.insert((state as DfaStateID, c, next));
-            }
         }
         result
     }
 
-    pub fn from_nfa(nfa: &crate::automaton::nfa::Nfa) -> Self {
-        let mut dfa_states = foldhash::HashMap::new();
-        let mut queue = std::collections::VecDeque::new();
-
-        let mut start_bitset = bit_set::BitSet::new();
-        start_bitset.insert(nfa.start() as usize);
-        let start_closure_bitset = nfa.epsilon_closure_with_bitset(&start_bitset);
-
-        let start_states: std::collections::BTreeSet<_> = start_closure_bitset
-            .iter()
-            .map(|s| s as crate::automaton::nfa::NfaStateID)
-            .collect();
-
-        let start_id = dfa_states.len() as DfaStateID;
-        dfa_states.insert(start_states.clone(), start_id);
-        queue.push_back(start_states);
+    #[cfg(test)]
+    pub fn from_nfa(nfa: &crate::automaton::nfa::Nfa) -> Dfa<'static> {
+        Self::from_nfa_with_limits(nfa, &crate::config::Config::unlimited()).unwrap()
+    }
 
-        let mut dfa = Dfa::new(start_id, bit_set::BitSet::new());
-        let mut raw_transitions: Vec<(DfaStateID, char, DfaStateID)> = Vec::new();
-        let mut raw_class_transitions: Vec<(DfaStateID, crate::charclass::CharClass, DfaStateID)> =
-            Vec::new();
+    pub fn from_nfa_with_limits(
+        nfa: &crate::automaton::nfa::Nfa,
+        config: &crate::config::Config,
+    ) -> crate::Result<Dfa<'static>> {
+        Self::build(nfa, config, false)
+    }
 
-        while let Some(current) = queue.pop_front() {
-            let current_id = dfa_states[&current];
+    pub fn reverse_from_ast(
+        ast: &crate::parser::AstNode,
+        config: &crate::config::Config,
+    ) -> crate::Result<Option<Dfa<'static>>> {
+        let nfa = crate::automaton::nfa::Nfa::build(ast.reverse(), config)?;
+        optional(Self::build(&nfa, config, true))
+    }
 
-            if current.iter().any(|&state| nfa.accept().contains(&state)) {
-                dfa.accepts.insert(current_id as usize);
-            }
+    pub fn from_derivatives(
+        expr: &crate::derivative::Expr,
+        config: &crate::config::Config,
+    ) -> crate::Result<Dfa<'static>> {
+        Self::finish(
+            crate::derivative::explore(expr, config, false)?,
+            None,
+            config,
+            false,
+        )
+    }
 
-            let mut transitions_map: foldhash::HashMap<
-                char,
-                std::collections::BTreeSet<crate::automaton::nfa::NfaStateID>,
-            > = foldhash::HashMap::new();
+    pub fn reverse_from_derivatives(
+        expr: &crate::derivative::Expr,
+        config: &crate::config::Config,
+    ) -> crate::Result<Option<Dfa<'static>>> {
+        optional(
+            crate::derivative::explore(&expr.reverse(), config, true)
+                .and_then(|dfa| Self::finish(dfa, None, config, true)),
+        )
+    }
 
-            let mut class_transitions: Vec<(
-                crate::charclass::CharClass,
-                std::collections::BTreeSet<crate::automaton::nfa::NfaStateID>,
-            )> = Vec::new();
+    fn build(
+        nfa: &crate::automaton::nfa::Nfa,
+        config: &crate::config::Config,
+        reverse: bool,
+    ) -> crate::Result<Dfa<'static>> {
+        let classes = classes::from_nfa(nfa);
+        let stride = classes::alphabet_len(&classes);
+        let dfa = subset::build(nfa, stride, config, reverse)?;
+        Self::finish(dfa, Some(classes), config, reverse)
+    }
 
-            for &state in &current {
-                for &(from, label, to) in nfa.transitions() {
-                    if from != state {
-                        continue;
-                    }
+    fn finish(
+        dfa: CharDfa,
+        classes: Option<[u8; 256]>,
+        config: &crate::config::Config,
+        reverse: bool,
+    ) -> crate::Result<Dfa<'static>> {
+        Self::finish_with_tags(dfa, None, classes, config, reverse).map(|(dfa, _, _)| dfa)
+    }
 
-                    let closure = nfa.epsilon_closure([to].iter().cloned().collect());
-                    match label {
-                        crate::automaton::label::NfaLabel::Epsilon => {}
-                        crate::automaton::label::NfaLabel::Char(c) => {
-                            transitions_map.entry(c).or_default().extend(closure);
-                        }
-                        crate::automaton::label::NfaLabel::Class(class) => {
-                            for byte in 0u8..128 {
-                                let c = byte as char;
-                                if class.matches(c) {
-                                    transitions_map
-                                        .entry(c)
-                                        .or_default()
-                                        .extend(closure.clone());
-                                }
-                            }
-                            if let Some(entry) = class_transitions
-                                .iter_mut()
-                                .find(|(existing, _)| *existing == class)
-                            {
-                                entry.1.extend(closure);
-                            } else {
-                                class_transitions.push((class, closure));
-                            }
-                        }
-                    }
+    fn finish_with_tags(
+        dfa: CharDfa,
+        tags: Option<&tagged::Tags>,
+        classes: Option<[u8; 256]>,
+        config: &crate::config::Config,
+        reverse: bool,
+    ) -> crate::Result<(Dfa<'static>, Table, Vec<usize>)> {
+        let state_count = dfa.accepts.len();
+        let mut table = vec![DEAD; state_count * STRIDE];
+        let mut ops = vec![0; state_count * STRIDE];
+        let mut non_ascii = vec![(Vec::new(), None); state_count];
+
+        for (i, (from, c, to)) in dfa.chars.into_iter().enumerate() {
+            let op = tags.map_or(0, |tags| tags.chars[i]);
+            if c.is_ascii() {
+                table[from as usize * STRIDE + c as usize] = to;
+                ops[from as usize * STRIDE + c as usize] = op;
+            } else {
+                let mut bytes = c.to_string().into_bytes();
+                if reverse {
+                    bytes.reverse();
                 }
+                non_ascii[from as usize].0.push((bytes, to, op));
             }
+        }
+        for (i, (from, to)) in dfa.any.into_iter().enumerate() {
+            non_ascii[from as usize].1 = Some((to, tags.map_or(0, |tags| tags.any[i])));
+        }
 
-            for (c, next) in transitions_map {
-                if next.is_empty() {
-                    continue;
+        let mut utf8 = Utf8Compiler {
+            base: state_count,
+            reverse,
+            rows: Vec::new(),
+            ops: Vec::new(),
+            interned: foldhash::HashMap::new(),
+            fallbacks: foldhash::HashMap::new(),
+        };
+        for (state, (mut entries, any)) in non_ascii.into_iter().enumerate() {
+            entries.sort_unstable();
+            let row = state * STRIDE..(state + 1) * STRIDE;
+            utf8.fill(
+                &mut table[row.clone()],
+                &mut ops[row],
+                &entries,
+                0,
+                Utf8::Lead,
+                any,
+            );
+        }
+        let state_count = state_count + utf8.interned.len();
+        table.extend(utf8.rows);
+        ops.extend(utf8.ops);
+        let classes = classes.unwrap_or_else(|| classes::from_table(&table));
+        let stride = classes::alphabet_len(&classes);
+        check_size(state_count, stride, config)?;
+        let mut accepts = dfa.accepts;
+        accepts.resize(state_count, false);
+
+        let mut table = Table {
+            start: dfa.start,
+            accepts,
+            stride,
+            next: compress(&table, &classes),
+            ops: match tags {
+                Some(_) => compress(&ops, &classes),
+                None => Vec::new(),
+            },
+            finals: match tags {
+                Some(tags) => {
+                    let mut finals = tags.finals.clone();
+                    finals.resize(state_count, vec![tagged::NONE; tags.slots].into());
+                    finals
                 }
+                None => Vec::new(),
+            },
+            classes,
+        };
+        let minimization = config.minimize.then(|| {
+            table = minimize::minimize(&table);
+            crate::stats::Minimization {
+                states_before: state_count,
+                states_after: table.state_count(),
+            }
+        });
 
-                if !dfa_states.contains_key(&next) {
-                    let next_id = dfa_states.len() as DfaStateID;
-                    dfa_states.insert(next.clone(), next_id);
-                    queue.push_back(next.clone());
-                }
+        let (mut dfa, order) = Dfa::encode(&table, config, reverse)?;
+        dfa.minimization = minimization;
+        Ok((dfa, table, order))
+    }
 
-                let next_id = dfa_states[&next];
-                raw_transitions.push((current_id, c, next_id));
+    pub fn is_reverse(&self) -> bool {
+        self.reverse
+    }
+
+    pub fn find(&self, reverse: Option<&Dfa>, haystack: &str) -> Option<(usize, usize)> {
+        let start = match reverse {
+            Some(reverse) => reverse.rfind_start(haystack)?,
+            // Quadratic in the worst case; see the README.
+            None => {
+                return (0..=haystack.len())
+                    .filter(|&at| haystack.is_char_boundary(at))
+                    .find_map(|at| Some((at, self.longest_match_end(haystack, at)?)));
             }
+        };
+        Some((start, self.longest_match_end(haystack, start)?))
+    }
 
-            for (class, next) in class_transitions {
-                if next.is_empty() {
-                    continue;
-                }
-                if !dfa_states.contains_key(&next) {
-                    let next_id = dfa_states.len() as DfaStateID;
-                    dfa_states.insert(next.clone(), next_id);
-                    queue.push_back(next.clone());
+    fn rfind_start(&self, haystack: &str) -> Option<usize> {
+        debug_assert!(self.reverse);
+        dispatch!(self, table => self.rfind_start_in(table, haystack))
+    }
+
+    fn rfind_start_in<T: layout::Table>(&self, table: &T, haystack: &str) -> Option<usize> {
+        let bytes = haystack.as_bytes();
+        let mut state = self.start;
+        let mut found = self.is_accept(state).then_some(bytes.len());
+
+        let mut at = bytes.len();
+        while at > 0 {
+            at -= 1;
+            state = self.next_state(table, state, bytes[at]);
+            if state == layout::DEAD {
+                state = self.start;
+                while !haystack.is_char_boundary(at) {
+                    at -= 1;
                 }
-                let next_id = dfa_states[&next];
-                raw_class_transitions.push((current_id, class, next_id));
+            }
+            if self.is_accept(state) {
+                found = Some(at);
             }
         }
 
-        let state_count = dfa_states.len();
-        dfa.state_count = state_count;
-        dfa.ascii_table = vec![DEAD; state_count * 128];
-        dfa.unicode_table = vec![foldhash::HashMap::new(); state_count];
-        dfa.unicode_class = vec![Vec::new(); state_count];
+        found
+    }
 
-        for (from, class, to) in raw_class_transitions {
-            dfa.unicode_class[from as usize].push((class, to));
-        }
+    fn longest_match_end(&self, haystack: &str, start: usize) -> Option<usize> {
+        dispatch!(self, table => self.longest_match_end_in(table, haystack, start))
+    }
 
-        for (from, c, to) in raw_transitions {
-            if c.is_ascii() {
-                let idx = from as usize * 128 + c as usize;
-                dfa.ascii_table[idx] = to;
-            } else {
-                dfa.unicode_table[from as usize].insert(c, to);
+    fn longest_match_end_in<T: layout::Table>(
+        &self,
+        table: &T,
+        haystack: &str,
+        start: usize,
+    ) -> Option<usize> {
+        let mut state = self.start;
+        let mut end = self.is_accept(state).then_some(start);
+
+        for (at, &byte) in haystack.as_bytes()[start..].iter().enumerate() {
+            state = self.next_state(table, state, byte);
+            if state == layout::DEAD {
+                break;
+            }
+            if self.is_accept(state) {
+                end = Some(start + at + 1);
             }
         }
 
-        dfa.accels = (0..state_count)
-            .map(|state| build_accel(state, &dfa.ascii_table))
-            .collect();
-
-        dfa
+        end
     }
 
     pub fn is_match(&self, input: &str) -> bool {
-        let mut state = self.start();
-
-        if input.is_ascii() {
-            let bytes = input.as_bytes();
-            state = match if bytes.len() < ACCEL_MIN_REMAINING {
-                self.step_ascii(bytes, state)
-            } else {
-                self.step_ascii_accel(bytes, state)
-            } {
-                Ok(state) => state,
-                Err(()) => return false,
-            };
+        let bytes = input.as_bytes();
+        let state = dispatch!(self, table => if bytes.len() < ACCEL_MIN_REMAINING {
+            self.step(table, bytes, self.start)
         } else {
-            let table = &self.ascii_table;
-            let unicode = &self.unicode_table;
-            for c in input.chars() {
-                if c.is_ascii() {
-                    let next = *unsafe { table.get_unchecked(state as usize * 128 + c as usize) };
-                    if next == DEAD {
-                        return false;
-                    }
-                    state = next;
-                } else if let Some(&next) = unicode[state as usize].get(&c) {
-                    state = next;
-                } else if let Some(next) = Self::step_class(state, c, &self.unicode_class) {
-                    state = next;
-                } else {
-                    return false;
-                }
-            }
-        }
+            self.step_accel(table, bytes, self.start)
+        });
 
-        self.accepts.contains(state as usize)
+        state.is_ok_and(|state| self.is_accept(state))
     }
 
     #[inline]
-    fn step_class(
-        state: DfaStateID,
-        c: char,
-        unicode_class: &[Vec<(crate::charclass::CharClass, DfaStateID)>],
-    ) -> Option<DfaStateID> {
-        unicode_class[state as usize]
-            .iter()
-            .find(|(class, _)| class.matches(c))
-            .map(|&(_, next)| next)
-    }
-
-    #[inline]
-    fn step_ascii(&self, bytes: &[u8], mut state: DfaStateID) -> Result<DfaStateID, ()> {
-        let table = &self.ascii_table;
+    fn step<T: layout::Table>(
+        &self,
+        table: &T,
+        bytes: &[u8],
+        mut state: usize,
+    ) -> Result<usize, ()> {
         for &byte in bytes {
-            let next = *unsafe { table.get_unchecked(state as usize * 128 + byte as usize) };
-            if next == DEAD {
+            let next = self.next_state(table, state, byte);
+            if next == layout::DEAD {
                 return Err(());
             }
             state = next;
@@ -287,27 +645,34 @@ impl Dfa {
     }
 
     #[inline]
-    fn step_ascii_accel(&self, bytes: &[u8], mut state: DfaStateID) -> Result<DfaStateID, ()> {
-        let table = &self.ascii_table;
+    fn step_accel<T: layout::Table>(
+        &self,
+        table: &T,
+        bytes: &[u8],
+        mut state: usize,
+    ) -> Result<usize, ()> {
         let mut at = 0usize;
         let len = bytes.len();
 
         while at < len {
             let remaining = len - at;
-            if remaining >= ACCEL_MIN_REMAINING {
-                let accel = self.accels[state as usize];
+            if let Some(index) = table
+                .accel_index(state)
+                .filter(|_| remaining >= ACCEL_MIN_REMAINING)
+            {
+                let accel = self.accel_at(index, state);
                 if accel.is_enabled() {
                     if let Some((class, next_state)) = accel.class_loop {
                         let start = at;
-                        while at < len && class.matches(bytes[at] as char) {
+                        while at < len && bytes[at].is_ascii() && class.matches(bytes[at] as char) {
                             at += 1;
                         }
                         if at >= len {
-                            state = next_state;
+                            state = next_state as usize;
                             break;
                         }
                         if at > start {
-                            state = next_state;
+                            state = next_state as usize;
                             continue;
                         }
                     } else if let Some(loop_byte) = accel.loop_byte {
@@ -330,9 +695,8 @@ impl Dfa {
                 }
             }
 
-            let byte = bytes[at];
-            let next = *unsafe { table.get_unchecked(state as usize * 128 + byte as usize) };
-            if next == DEAD {
+            let next = self.next_state(table, state, bytes[at]);
+            if next == layout::DEAD {
                 return Err(());
             }
             state = next;
@@ -343,58 +707,155 @@ impl Dfa {
     }
 }
 
+impl<'a> Dfa<'a> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = crate::serialize::Writer::new(crate::serialize::Kind::Dfa);
+        writer.u64(self.start as u64);
+        writer.u64(self.accept_from as u64);
+        writer.u64(self.state_count as u64);
+        writer.u64(self.reverse as u64);
+        writer.slice(&self.classes);
+        self.transitions.write(&mut writer);
+        writer.slice(&self.accels);
+        writer.finish()
+    }
+
+    pub fn from_bytes(bytes: &'a [u8]) -> crate::Result<Dfa<'a>> {
+        let (dfa, rest) = Self::from_bytes_prefix(bytes)?;
+        if !rest.is_empty() {
+            return Err(crate::Error::Deserialize("trailing data"));
+        }
+        Ok(dfa)
+    }
+
+    pub fn from_bytes_prefix(bytes: &'a [u8]) -> crate::Result<(Dfa<'a>, &'a [u8])> {
+        let mut reader = crate::serialize::Reader::new(bytes, crate::serialize::Kind::Dfa)?;
+        let usize = |value: u64| {
+            usize::try_from(value).map_err(|_| crate::Error::Deserialize("offset too large"))
+        };
+        let start = usize(reader.u64()?)?;
+        let accept_from = usize(reader.u64()?)?;
+        let state_count = usize(reader.u64()?)?;
+        let reverse = match reader.u64()? {
+            0 => false,
+            1 => true,
+            _ => return Err(crate::Error::Deserialize("invalid direction")),
+        };
+        let dfa = Dfa {
+            start,
+            accept_from,
+            state_count,
+            classes: reader.slice()?.into(),
+            transitions: layout::Transitions::read(&mut reader)?,
+            accels: reader.slice()?.into(),
+            reverse,
+            minimization: None,
+        };
+        dfa.validate()?;
+        Ok((dfa, reader.rest()))
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        if self.classes.len() != 256
+            || self.classes[0] != 0
+            || self.classes.windows(2).any(|w| w[1].wrapping_sub(w[0]) > 1)
+        {
+            return Err(crate::Error::Deserialize("invalid byte classes"));
+        }
+        let offsets = self
+            .transitions
+            .offsets(self.state_count)
+            .filter(|offsets| offsets.len() == self.state_count)
+            .ok_or(crate::Error::Deserialize("invalid transition table"))?;
+        if !self
+            .transitions
+            .validate(&offsets, classes::alphabet_len(&self.classes))
+        {
+            return Err(crate::Error::Deserialize("invalid transition table"));
+        }
+        if offsets.binary_search(&self.start).is_err() {
+            return Err(crate::Error::Deserialize("start state out of range"));
+        }
+        let end = self.transitions.table_bytes() / self.transitions.state_id_bytes();
+        if self.accept_from != end && offsets.binary_search(&self.accept_from).is_err() {
+            return Err(crate::Error::Deserialize("invalid accepting states"));
+        }
+        let accels = match self.transitions.layout() {
+            crate::config::DfaLayout::Dense => self.state_count,
+            crate::config::DfaLayout::Sparse => 0,
+        };
+        if self.accels.len() != accels
+            || !self
+                .accels
+                .iter()
+                .zip(&offsets)
+                .all(|(&accel, &state)| Accel::decode(accel, state as DfaStateID).is_some())
+        {
+            return Err(crate::Error::Deserialize("invalid accelerators"));
+        }
+        Ok(())
+    }
+}
+
+fn optional(result: crate::Result<Dfa<'static>>) -> crate::Result<Option<Dfa<'static>>> {
+    match result {
+        Ok(dfa) => Ok(Some(dfa)),
+        Err(crate::Error::LimitExceeded(crate::Limit::DfaStates | crate::Limit::DfaBytes, _)) => {
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn compress<T: Copy>(table: &[T], classes: &[u8; 256]) -> Vec<T> {
+    table
+        .chunks(STRIDE)
+        .flat_map(|row| classes::representatives(classes).map(|byte| row[byte as usize]))
+        .collect()
+}
+
+fn check_size(states: usize, stride: usize, config: &crate::config::Config) -> crate::Result<()> {
+    crate::config::Limit::DfaStates.check(states, config.max_dfa_states)?;
+    crate::config::Limit::DfaBytes.check(
+        layout::min_table_bytes(config.dfa_layout, stride, states),
+        config.max_dfa_bytes,
+    )
+}
+
 fn detect_class_loop(
     state: usize,
-    table: &[DfaStateID],
+    row: &[DfaStateID],
 ) -> Option<(crate::charclass::CharClass, DfaStateID)> {
-    let base = state * 128;
-    for class in [
+    let self_id = state as DfaStateID;
+    [
         crate::charclass::CharClass::Digit,
         crate::charclass::CharClass::Word,
         crate::charclass::CharClass::Space,
         crate::charclass::CharClass::Any,
-    ] {
-        let mut target = None;
-        let mut matched = false;
-        for byte in 0u8..128 {
-            if !class.matches(byte as char) {
-                continue;
-            }
-            matched = true;
-            let next = table[base + byte as usize];
-            if next == DEAD {
-                target = None;
-                break;
-            }
-            match target {
-                None => target = Some(next),
-                Some(existing) if existing == next => {}
-                _ => {
-                    target = None;
-                    break;
-                }
-            }
-        }
-        if matched && let Some(next) = target {
-            return Some((class, next));
-        }
-    }
-    None
+    ]
+    .into_iter()
+    .find(|class| {
+        (0u8..128)
+            .filter(|&byte| class.matches(byte as char))
+            .all(|byte| row[byte as usize] == self_id)
+    })
+    .map(|class| (class, self_id))
 }
 
-fn build_accel(state: usize, table: &[DfaStateID]) -> Accel {
-    let base = state * 128;
+pub fn build_accel(state: usize, row: &[DfaStateID]) -> Accel {
     let self_id = state as DfaStateID;
 
     let mut loop_bytes = Vec::new();
     let mut exit_bytes: Vec<u8> = Vec::new();
+    let mut has_dead = false;
 
-    for byte in 0u8..128 {
-        let next = table[base + byte as usize];
+    for (byte, &next) in row.iter().enumerate() {
         if next == self_id {
-            loop_bytes.push(byte);
+            loop_bytes.push(byte as u8);
         } else if next != DEAD {
-            exit_bytes.push(byte);
+            exit_bytes.push(byte as u8);
+        } else {
+            has_dead = true;
         }
     }
 
@@ -405,20 +866,14 @@ fn build_accel(state: usize, table: &[DfaStateID]) -> Accel {
     };
 
     let class_loop = if loop_byte.is_none() {
-        detect_class_loop(state, table)
+        detect_class_loop(state, row)
     } else {
         None
     };
 
-    let mut unique_exits = Vec::new();
-    for byte in exit_bytes {
-        let next = table[base + byte as usize];
-        if !unique_exits.iter().any(|&(_, id)| id == next) {
-            unique_exits.push((byte, next));
-        }
-    }
-
-    if unique_exits.len() > 3 {
+    // memchr may only skip bytes that loop back to this state
+    let skippable = !has_dead || loop_byte.is_some() || class_loop.is_some();
+    if exit_bytes.len() > 3 || !skippable {
         return Accel {
             loop_byte,
             class_loop,
@@ -427,20 +882,18 @@ fn build_accel(state: usize, table: &[DfaStateID]) -> Accel {
         };
     }
 
-    if loop_byte.is_none() && class_loop.is_none() && unique_exits.is_empty() {
+    if loop_byte.is_none() && class_loop.is_none() && exit_bytes.is_empty() {
         return Accel::default();
     }
 
     let mut needles = [0u8; 3];
-    for (i, &(byte, _)) in unique_exits.iter().enumerate() {
-        needles[i] = byte;
-    }
+    needles[..exit_bytes.len()].copy_from_slice(&exit_bytes);
 
     Accel {
         loop_byte,
         class_loop,
         needles,
-        needle_len: unique_exits.len() as u8,
+        needle_len: exit_bytes.len() as u8,
     }
 }
 
@@ -448,7 +901,7 @@ fn build_accel(state: usize, table: &[DfaStateID]) -> Accel {
 mod tests {
     use super::*;
 
-    fn dfa_from_pattern(pattern: &str) -> Dfa {
+    fn dfa_from_pattern(pattern: &str) -> Dfa<'static> {
         let mut lexer = crate::lexer::Lexer::new(pattern);
         let mut parser = crate::parser::Parser::new(&mut lexer);
         let ast = parser.parse().unwrap();
@@ -460,6 +913,28 @@ mod tests {
         Dfa::from_nfa(&nfa)
     }
 
+    fn minimized_from_pattern(pattern: &str) -> Dfa<'static> {
+        dfa_with_config(
+            pattern,
+            crate::config::Config {
+                minimize: true,
+                ..crate::config::Config::unlimited()
+            },
+        )
+    }
+
+    fn dfa_with_config(pattern: &str, config: crate::config::Config) -> Dfa<'static> {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let mut parser = crate::parser::Parser::new(&mut lexer);
+        let ast = parser.parse().unwrap();
+        let nfa = crate::automaton::nfa::Nfa::new_from_node(
+            ast,
+            &mut crate::automaton::nfa::NfaState::new(),
+        )
+        .unwrap();
+        Dfa::from_nfa_with_limits(&nfa, &config).unwrap()
+    }
+
     #[test]
     fn class_digit_matches() {
         let dfa = dfa_from_pattern(r"\d");
@@ -477,6 +952,66 @@ mod tests {
         assert!(!dfa.is_match("a\nb"));
     }
 
+    #[test]
+    fn plus_requires_one_iteration() {
+        let dfa = dfa_from_pattern("a+");
+        assert!(!dfa.is_match(""));
+        assert!(dfa.is_match("a"));
+        assert!(dfa.is_match("aaa"));
+        let dfa = dfa_from_pattern("(ab)+c");
+        assert!(!dfa.is_match("c"));
+        assert!(dfa.is_match("abc"));
+        assert!(dfa.is_match("ababc"));
+    }
+
+    #[test]
+    fn utf8_byte_sequences() {
+        let dfa = dfa_from_pattern("(éa|.b)|正規表現.+");
+        assert!(dfa.is_match("éa"));
+        assert!(dfa.is_match("éb"));
+        assert!(dfa.is_match("😀b"));
+        assert!(!dfa.is_match("éc"));
+        assert!(dfa.is_match("正規表現太郎x"));
+        assert!(!dfa.is_match("正規表現"));
+        assert!(!dfa.is_match("正規表"));
+
+        let mut lexer = crate::lexer::Lexer::new("表.a|é+");
+        let ast = crate::parser::Parser::new(&mut lexer).parse().unwrap();
+        let config = crate::config::Config::unlimited();
+        let reverse = Dfa::reverse_from_ast(&ast, &config).unwrap().unwrap();
+        let forward = Dfa::from_nfa_with_limits(
+            &crate::automaton::nfa::Nfa::new_from_node(
+                ast,
+                &mut crate::automaton::nfa::NfaState::new(),
+            )
+            .unwrap(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(forward.find(Some(&reverse), "x表現a"), Some((1, 8)));
+        assert_eq!(forward.find(Some(&reverse), "aéé"), Some((1, 5)));
+        assert_eq!(forward.find(Some(&reverse), "表a"), None);
+    }
+
+    #[test]
+    fn large_alternation() {
+        let words: Vec<String> = (0..3000).map(|i| format!("w{i}(x|y)")).collect();
+        let pattern = words.join("|");
+        let dfa = dfa_with_config(&pattern, crate::config::Config::default());
+        assert!(dfa.is_match("w0x"));
+        assert!(dfa.is_match("w2999y"));
+        assert!(!dfa.is_match("w3000x"));
+        assert!(!dfa.is_match("w12"));
+
+        let mut lexer = crate::lexer::Lexer::new(&pattern);
+        let ast = crate::parser::Parser::new(&mut lexer).parse().unwrap();
+        let reverse = Dfa::reverse_from_ast(&ast, &crate::config::Config::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(dfa.find(Some(&reverse), "-- w2999y w1x"), Some((3, 9)));
+        assert_eq!(dfa.find(Some(&reverse), "w30w12x"), Some((3, 7)));
+    }
+
     #[test]
     fn test_dfa_from_nfa() {
         let nfa = crate::automaton::nfa::Nfa::new_from_node(
@@ -487,7 +1022,7 @@ mod tests {
         let dfa = Dfa::from_nfa(&nfa);
         assert_eq!(dfa.start(), 0);
         assert!(dfa.accepts_contains(1));
-        assert_eq!(dfa.transitions(), [(0, 'a', 1)].iter().cloned().collect());
+        assert_eq!(dfa.transitions(), [(0, b'a', 1)].iter().cloned().collect());
 
         let nfa = crate::automaton::nfa::Nfa::new_from_node(
             crate::parser::AstNode::Or(
@@ -504,9 +1039,9 @@ mod tests {
 
         let transitions = dfa.transitions();
         assert_eq!(transitions.len(), 2);
-        assert!(transitions.contains(&(0, 'a', 1)) || transitions.contains(&(0, 'a', 2)));
-        assert!(transitions.contains(&(0, 'b', 1)) || transitions.contains(&(0, 'b', 2)));
-        assert!(transitions.contains(&(0, 'a', 1)) != transitions.contains(&(0, 'b', 1)));
+        assert!(transitions.contains(&(0, b'a', 1)) || transitions.contains(&(0, b'a', 2)));
+        assert!(transitions.contains(&(0, b'b', 1)) || transitions.contains(&(0, b'b', 2)));
+        assert!(transitions.contains(&(0, b'a', 1)) != transitions.contains(&(0, b'b', 1)));
 
         let nfa = crate::automaton::nfa::Nfa::new_from_node(
             crate::parser::AstNode::Or(
@@ -529,11 +1064,11 @@ mod tests {
 
         let a_transitions: Vec<_> = transitions
             .iter()
-            .filter(|(from, c, _)| *from == 0 && *c == 'a')
+            .filter(|(from, c, _)| *from == 0 && *c == b'a')
             .collect();
         let b_transitions: Vec<_> = transitions
             .iter()
-            .filter(|(from, c, _)| *from == 0 && *c == 'b')
+            .filter(|(from, c, _)| *from == 0 && *c == b'b')
             .collect();
 
         assert_eq!(a_transitions.len(), 1);
@@ -542,12 +1077,93 @@ mod tests {
         let b_state = b_transitions[0].2;
         let b_loops: Vec<_> = transitions
             .iter()
-            .filter(|(from, c, to)| *from == b_state && *c == 'b' && *to == b_state)
+            .filter(|(from, c, to)| *from == b_state && *c == b'b' && *to == b_state)
             .collect();
 
         assert_eq!(b_loops.len(), 1);
     }
 
+    #[test]
+    fn derivative_construction() {
+        let config = crate::config::Config::unlimited();
+        let inputs = [
+            "",
+            "a",
+            "ab",
+            "aab",
+            "abab",
+            "b",
+            "x0",
+            "a\nb",
+            "é",
+            "éa",
+            "😀b",
+            "正規表現",
+            "a_1",
+        ];
+        for pattern in [
+            "a+b",
+            "(a|b)*a(a|b)",
+            r"\w+\d?",
+            ".b|é.",
+            "(éa|.b)|正規表現.*",
+            "(a*)*|b",
+        ] {
+            let mut lexer = crate::lexer::Lexer::new(pattern);
+            let ast = crate::parser::Parser::new(&mut lexer).parse().unwrap();
+            let expr = crate::derivative::Expr::Ast(ast);
+            let derivative = Dfa::from_derivatives(&expr, &config).unwrap();
+            let reverse = Dfa::reverse_from_derivatives(&expr, &config)
+                .unwrap()
+                .unwrap();
+            let subset = dfa_from_pattern(pattern);
+            for input in inputs {
+                assert_eq!(
+                    derivative.is_match(input),
+                    subset.is_match(input),
+                    "{pattern} {input:?}"
+                );
+                let haystack = format!("-{input}-");
+                assert_eq!(
+                    derivative.find(Some(&reverse), &haystack),
+                    subset.find(None, &haystack),
+                    "{pattern} {haystack:?}"
+                );
+            }
+        }
+
+        let ast = |pattern| {
+            let mut lexer = crate::lexer::Lexer::new(pattern);
+            crate::derivative::Expr::Ast(crate::parser::Parser::new(&mut lexer).parse().unwrap())
+        };
+        let words_without_digits = crate::derivative::Expr::And(
+            Box::new(ast(r"\w+")),
+            Box::new(crate::derivative::Expr::Not(Box::new(ast(r".*\d.*")))),
+        );
+        let dfa = Dfa::from_derivatives(&words_without_digits, &config).unwrap();
+        assert!(dfa.is_match("abc_"));
+        assert!(!dfa.is_match("ab1"));
+        assert!(!dfa.is_match(""));
+
+        let not_a = crate::derivative::Expr::Not(Box::new(ast("a")));
+        let dfa = Dfa::from_derivatives(&not_a, &config).unwrap();
+        for input in ["", "b", "aa", "\n", "é", "😀"] {
+            assert!(dfa.is_match(input), "{input:?}");
+        }
+        assert!(!dfa.is_match("a"));
+
+        assert!(matches!(
+            Dfa::from_derivatives(
+                &ast("(a|b)*a(a|b)(a|b)(a|b)"),
+                &crate::config::Config {
+                    max_dfa_states: 8,
+                    ..crate::config::Config::default()
+                }
+            ),
+            Err(crate::Error::LimitExceeded(crate::Limit::DfaStates, 8))
+        ));
+    }
+
     #[test]
     fn accel_a_plus_b() {
         let dfa = dfa_from_pattern("a+b");
@@ -597,4 +1213,234 @@ mod tests {
         assert!(dfa.is_match(""));
         assert!(dfa.is_match(&"b".repeat(1000)));
     }
+
+    #[test]
+    fn accel_does_not_skip_dead_bytes() {
+        let dfa = dfa_from_pattern("a(b|c)");
+        assert!(!dfa.is_match(&format!("a{}b", "x".repeat(40))));
+
+        let dfa = dfa_from_pattern(r"\d\da*");
+        assert!(dfa.is_match(&format!("12{}", "a".repeat(40))));
+        assert!(!dfa.is_match(&format!("123{}", "a".repeat(40))));
+    }
+
+    #[test]
+    fn minimize_preserves_language() {
+        let cases = [
+            (
+                "(p(erl|ython|hp)|ruby)",
+                &["perl", "python", "php", "ruby", "rust", "p", ""][..],
+            ),
+            (
+                "ab(cd|)ef|g*|h+",
+                &["abcdef", "abef", "", "ggg", "h", "gh", "abcef"][..],
+            ),
+            (
+                r"a\db|\s\w+|.\d",
+                &["a1b", " foo", "x9", "é9", "a1", "  "][..],
+            ),
+            (
+                "正規表現(太郎|次郎).",
+                &["正規表現太郎x", "正規表現次郎é", "正規表現三郎x"][..],
+            ),
+            ("(.a|éb)c", &["éac", "ébc", "xac", "xbc"][..]),
+        ];
+        for (pattern, inputs) in cases {
+            let dfa = dfa_from_pattern(pattern);
+            let minimized = minimized_from_pattern(pattern);
+            assert!(minimized.state_count() <= dfa.state_count());
+            for input in inputs {
+                assert_eq!(
+                    dfa.is_match(input),
+                    minimized.is_match(input),
+                    "{pattern} {input}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn minimize_is_canonical() {
+        let a = minimized_from_pattern("(a|b)*abb");
+        let b = minimized_from_pattern("(a|b)*(abb|abb)");
+        assert_eq!(a.state_count(), 4);
+        assert_eq!(a.transitions(), b.transitions());
+        assert_eq!(
+            minimized_from_pattern("xx*").transitions(),
+            minimized_from_pattern("x+").transitions()
+        );
+        assert_eq!(minimized_from_pattern("a(b|c)").state_count(), 3);
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        for pattern in [
+            "(p(erl|ython|hp)|ruby)",
+            r"a\db|\s\w+|.\d",
+            "正規表現(太郎|次郎).",
+            "a+b",
+        ] {
+            let dfa = dfa_from_pattern(pattern);
+            let bytes = crate::serialize::leak_aligned(&dfa.to_bytes());
+            let loaded = Dfa::from_bytes(bytes).unwrap();
+            assert_eq!(loaded, dfa);
+            let (borrowed, table) = loaded.transitions.as_bytes();
+            assert!(borrowed);
+            assert!(bytes.as_ptr_range().contains(&table));
+            assert_eq!(loaded.to_bytes(), dfa.to_bytes());
+        }
+    }
+
+    #[test]
+    fn layouts() {
+        let words: Vec<char> = ('一'..).take(900).collect();
+        let long = words
+            .chunks(3)
+            .map(|word| word.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("|");
+        for pattern in [
+            "(p(erl|ython|hp)|ruby)",
+            r"a\db|\s\w+|.\d",
+            "正規表現(太郎|次郎).",
+            "a+b",
+            &long,
+        ] {
+            let dense = minimized_from_pattern(pattern);
+            let sparse = dfa_with_config(
+                pattern,
+                crate::config::Config {
+                    minimize: true,
+                    dfa_layout: crate::config::DfaLayout::Sparse,
+                    ..crate::config::Config::unlimited()
+                },
+            );
+            assert_eq!(sparse.transitions(), dense.transitions());
+            assert_eq!(sparse.start(), dense.start());
+            for input in [
+                "perl",
+                "a1b",
+                " foo",
+                "正規表現次郎x",
+                "ab",
+                "aab",
+                &long,
+                "",
+            ] {
+                assert_eq!(
+                    sparse.is_match(input),
+                    dense.is_match(input),
+                    "{pattern} {input}"
+                );
+            }
+            let bytes = crate::serialize::leak_aligned(&sparse.to_bytes());
+            assert_eq!(
+                Dfa::from_bytes(bytes).unwrap().to_bytes(),
+                sparse.to_bytes()
+            );
+        }
+
+        let memory = dfa_from_pattern("a+b").memory();
+        assert_eq!((memory.alphabet_len, memory.state_id_bytes), (4, 2));
+        assert_eq!(memory.table_bytes, memory.states * 4 * 2);
+
+        let dense = dfa_from_pattern(&long);
+        assert_eq!(dense.memory().state_id_bytes, 4);
+        let stride = dense.memory().alphabet_len.next_power_of_two();
+        assert!(dense.start.is_multiple_of(stride));
+        assert!(dense.is_match(&long[..9]));
+        assert!(dense.is_match(&long[long.len() - 9..]));
+        assert!(!dense.is_match(&long[3..9]));
+        let bytes = crate::serialize::leak_aligned(&dense.to_bytes());
+        assert_eq!(Dfa::from_bytes(bytes).unwrap(), dense);
+    }
+
+    #[test]
+    fn byte_limit_follows_layout() {
+        let build = |config: &crate::config::Config| {
+            let mut lexer = crate::lexer::Lexer::new("(ab|cd)*e");
+            let ast = crate::parser::Parser::new(&mut lexer).parse().unwrap();
+            let nfa = crate::automaton::nfa::Nfa::new_from_node(
+                ast,
+                &mut crate::automaton::nfa::NfaState::new(),
+            )
+            .unwrap();
+            Dfa::from_nfa_with_limits(&nfa, config)
+        };
+        for layout in [
+            crate::config::DfaLayout::Dense,
+            crate::config::DfaLayout::Sparse,
+        ] {
+            let config = crate::config::Config {
+                dfa_layout: layout,
+                ..crate::config::Config::unlimited()
+            };
+            let memory = build(&config).unwrap().memory();
+            assert_eq!(memory.state_id_bytes, 2);
+            let max_dfa_bytes = memory.table_bytes;
+            assert!(
+                build(&crate::config::Config {
+                    max_dfa_bytes,
+                    ..config.clone()
+                })
+                .is_ok()
+            );
+            assert!(matches!(
+                build(&crate::config::Config {
+                    max_dfa_bytes: max_dfa_bytes - 1,
+                    ..config
+                }),
+                Err(crate::Error::LimitExceeded(crate::Limit::DfaBytes, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn deserialize_rejects_invalid_data() {
+        let dfa = dfa_from_pattern("ab");
+        let bytes = dfa.to_bytes();
+
+        let mut corrupt = bytes.clone();
+        corrupt[344..346].copy_from_slice(&7u16.to_ne_bytes());
+        assert!(Dfa::from_bytes(crate::serialize::leak_aligned(&corrupt)).is_err());
+        corrupt[344..346].copy_from_slice(&u16::MAX.to_ne_bytes());
+        assert!(Dfa::from_bytes(crate::serialize::leak_aligned(&corrupt)).is_ok());
+
+        let mut corrupt = bytes.clone();
+        corrupt[65] = 5;
+        assert!(Dfa::from_bytes(crate::serialize::leak_aligned(&corrupt)).is_err());
+
+        let mut corrupt = bytes.clone();
+        corrupt[8] += 1;
+        assert!(Dfa::from_bytes(crate::serialize::leak_aligned(&corrupt)).is_err());
+
+        let mut corrupt = bytes.clone();
+        corrupt[40..48].copy_from_slice(&100u64.to_ne_bytes());
+        assert!(Dfa::from_bytes(crate::serialize::leak_aligned(&corrupt)).is_err());
+
+        let mut corrupt = bytes.clone();
+        corrupt[32..40].copy_from_slice(&1u64.to_ne_bytes());
+        assert!(Dfa::from_bytes(crate::serialize::leak_aligned(&corrupt)).is_err());
+
+        let shift = dfa
+            .memory()
+            .alphabet_len
+            .next_power_of_two()
+            .trailing_zeros();
+        let wrapped = dfa.state_count as u64 + (1 << (u64::BITS - shift));
+        let mut corrupt = bytes.clone();
+        corrupt[40..48].copy_from_slice(&wrapped.to_ne_bytes());
+        assert!(matches!(
+            Dfa::from_bytes(crate::serialize::leak_aligned(&corrupt)),
+            Err(crate::Error::Deserialize(_))
+        ));
+
+        let mut shifted = vec![0u8];
+        shifted.extend_from_slice(&bytes);
+        let shifted = crate::serialize::leak_aligned(&shifted);
+        assert!(Dfa::from_bytes(&shifted[1..]).is_err());
+        assert!(
+            Dfa::from_bytes(&crate::serialize::leak_aligned(&bytes)[..bytes.len() - 8]).is_err()
+        );
+    }
 }
@@ -0,0 +1,120 @@
+pub fn from_nfa(nfa: &crate::automaton::nfa::Nfa) -> [u8; 256] {
+    let mut boundaries = [false; 256];
+    let mut set_range = |start: u8, end: u8| {
+        if start > 0 {
+            boundaries[start as usize - 1] = true;
+        }
+        boundaries[end as usize] = true;
+    };
+
+    let mut utf8 = false;
+    for label in nfa.labels() {
+        match label {
+            crate::automaton::label::NfaLabel::Char(c) => {
+                let mut buf = [0; 4];
+                for &byte in c.encode_utf8(&mut buf).as_bytes() {
+                    set_range(byte, byte);
+                }
+                utf8 |= !c.is_ascii();
+            }
+            crate::automaton::label::NfaLabel::Class(class) => {
+                let table = class.expand_ascii();
+                let matches = |byte: usize| byte < 128 && table[byte];
+                let mut byte = 0;
+                while byte < 256 {
+                    let start = byte;
+                    while byte < 256 && matches(byte) == matches(start) {
+                        byte += 1;
+                    }
+                    set_range(start as u8, (byte - 1) as u8);
+                }
+                utf8 |= class == crate::charclass::CharClass::Any;
+            }
+        }
+    }
+    if utf8 {
+        for (start, end) in [(0x80, 0xBF), (0xC0, 0xDF), (0xE0, 0xEF), (0xF0, 0xF7)] {
+            set_range(start, end);
+        }
+    }
+
+    let mut classes = [0; 256];
+    let mut class = 0u8;
+    for byte in 0..256 {
+        classes[byte] = class;
+        if boundaries[byte] && byte < 255 {
+            class += 1;
+        }
+    }
+    classes
+}
+
+pub fn from_table(table: &[crate::automaton::dfa::DfaStateID]) -> [u8; 256] {
+    let mut classes = [0; 256];
+    let mut class = 0u8;
+    for byte in 1..256 {
+        if table.chunks(256).any(|row| row[byte] != row[byte - 1]) {
+            class += 1;
+        }
+        classes[byte] = class;
+    }
+    classes
+}
+
+pub fn alphabet_len(classes: &[u8]) -> usize {
+    classes[255] as usize + 1
+}
+
+pub fn representatives(classes: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    (0..=255u8).filter(|&byte| byte == 0 || classes[byte as usize] != classes[byte as usize - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classes(pattern: &str) -> [u8; 256] {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let ast = crate::parser::Parser::new(&mut lexer).parse().unwrap();
+        let nfa = crate::automaton::nfa::Nfa::new_from_node(
+            ast,
+            &mut crate::automaton::nfa::NfaState::new(),
+        )
+        .unwrap();
+        from_nfa(&nfa)
+    }
+
+    #[test]
+    fn equivalence_classes() {
+        let map = classes("a+b");
+        assert_eq!(alphabet_len(&map), 4);
+        assert_eq!(map[b'a' as usize], 1);
+        assert_eq!(map[b'b' as usize], 2);
+        assert_eq!(map[b'c' as usize], map[0xFF]);
+        assert_eq!(
+            representatives(&map).collect::<Vec<_>>(),
+            [0, b'a', b'b', b'c']
+        );
+
+        let map = classes(r"\d+");
+        assert_eq!(alphabet_len(&map), 3);
+        assert_eq!(map[b'0' as usize], map[b'9' as usize]);
+
+        let map = classes("é.");
+        assert_ne!(map[0xC3], map[0xC4]);
+        assert_eq!(map[0xC4], map[0xDF]);
+        assert_ne!(map[0xA9], map[0x80]);
+        assert_ne!(map[b'\n' as usize], map[b'a' as usize]);
+        assert_eq!(map[0xF8], map[0xFF]);
+
+        let mut table = vec![crate::automaton::dfa::DEAD; 512];
+        table[b'a' as usize] = 1;
+        table[256 + b'b' as usize] = 0;
+        table[256 + b'c' as usize] = 0;
+        let map = from_table(&table);
+        assert_eq!(alphabet_len(&map), 4);
+        assert_eq!(map[b'b' as usize], map[b'c' as usize]);
+        assert_eq!(map[0], map[b'`' as usize]);
+        assert_ne!(map[0], map[b'd' as usize]);
+    }
+}
@@ -0,0 +1,350 @@
+pub const DEAD: usize = usize::MAX;
+
+pub trait StateID: crate::serialize::Pod + Eq + std::fmt::Debug {
+    const DEAD: Self;
+    const LIMIT: usize;
+
+    fn raw(self) -> usize;
+    fn to_usize(self) -> usize;
+    fn from_usize(id: usize) -> Self;
+}
+
+macro_rules! state_id {
+    ($($ty:ty),*) => {$(
+        impl StateID for $ty {
+            const DEAD: Self = <$ty>::MAX;
+            const LIMIT: usize = <$ty>::MAX as usize;
+
+            #[inline(always)]
+            fn raw(self) -> usize {
+                self as usize
+            }
+
+            #[inline(always)]
+            fn to_usize(self) -> usize {
+                if self == Self::DEAD { DEAD } else { self as usize }
+            }
+
+            fn from_usize(id: usize) -> Self {
+                if id == DEAD { Self::DEAD } else { id as $ty }
+            }
+        }
+    )*};
+}
+
+state_id!(u16, u32);
+
+pub trait Table {
+    fn next(&self, state: usize, class: u8) -> usize;
+    fn accel_index(&self, state: usize) -> Option<usize>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dense<'a, T: StateID> {
+    shift: u32,
+    table: std::borrow::Cow<'a, [T]>,
+}
+
+impl<T: StateID> Table for Dense<'_, T> {
+    #[inline(always)]
+    fn next(&self, state: usize, class: u8) -> usize {
+        unsafe { self.table.get_unchecked(state + class as usize) }.to_usize()
+    }
+
+    #[inline]
+    fn accel_index(&self, state: usize) -> Option<usize> {
+        Some(state >> self.shift)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sparse<'a, T: StateID> {
+    table: std::borrow::Cow<'a, [T]>,
+}
+
+impl<T: StateID> Table for Sparse<'_, T> {
+    #[inline]
+    fn next(&self, state: usize, class: u8) -> usize {
+        let len = self.table[state].raw();
+        let ranges = &self.table[state + 1..state + 1 + len];
+        ranges
+            .iter()
+            .position(|&range| {
+                let range = range.raw();
+                range & 0xFF <= class as usize && class as usize <= range >> 8
+            })
+            .map_or(DEAD, |i| self.table[state + 1 + len + i].to_usize())
+    }
+
+    fn accel_index(&self, _: usize) -> Option<usize> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transitions<'a> {
+    Dense16(Dense<'a, u16>),
+    Dense32(Dense<'a, u32>),
+    Sparse16(Sparse<'a, u16>),
+    Sparse32(Sparse<'a, u32>),
+}
+
+impl<'a> Transitions<'a> {
+    pub fn encode(
+        layout: crate::config::DfaLayout,
+        stride: usize,
+        rows: &[Vec<usize>],
+    ) -> Option<(Transitions<'static>, Vec<usize>)> {
+        match layout {
+            crate::config::DfaLayout::Dense => {
+                let shift = stride.next_power_of_two().trailing_zeros();
+                let offsets: Vec<usize> = (0..rows.len()).map(|state| state << shift).collect();
+                let mut table = vec![DEAD; rows.len() << shift];
+                for (row, &at) in rows.iter().zip(&offsets) {
+                    for (class, &next) in row.iter().enumerate() {
+                        table[at + class] = if next == DEAD { DEAD } else { offsets[next] };
+                    }
+                }
+                let transitions = if table.len() < u16::LIMIT {
+                    Transitions::Dense16(Dense {
+                        shift,
+                        table: convert(table).into(),
+                    })
+                } else if table.len() < u32::LIMIT {
+                    Transitions::Dense32(Dense {
+                        shift,
+                        table: convert(table).into(),
+                    })
+                } else {
+                    return None;
+                };
+                Some((transitions, offsets))
+            }
+            crate::config::DfaLayout::Sparse => {
+                let mut ranges = Vec::with_capacity(rows.len());
+                let mut offsets = Vec::with_capacity(rows.len());
+                let mut len = 0;
+                for row in rows {
+                    let mut state_ranges: Vec<(usize, usize, usize)> = Vec::new();
+                    for (class, &next) in row.iter().enumerate() {
+                        match state_ranges.last_mut() {
+                            Some((_, end, last)) if *last == next && *end + 1 == class => {
+                                *end = class;
+                            }
+                            _ if next != DEAD => state_ranges.push((class, class, next)),
+                            _ => {}
+                        }
+                    }
+                    offsets.push(len);
+                    len += 1 + 2 * state_ranges.len();
+                    ranges.push(state_ranges);
+                }
+
+                let mut table = Vec::with_capacity(len);
+                for state_ranges in &ranges {
+                    table.push(state_ranges.len());
+                    table.extend(state_ranges.iter().map(|&(start, end, _)| start | end << 8));
+                    table.extend(
+                        state_ranges
+                            .iter()
+                            .map(|&(_, _, next)| if next == DEAD { DEAD } else { offsets[next] }),
+                    );
+                }
+                let transitions = if len < u16::LIMIT {
+                    Transitions::Sparse16(Sparse {
+                        table: convert(table).into(),
+                    })
+                } else if len < u32::LIMIT {
+                    Transitions::Sparse32(Sparse {
+                        table: convert(table).into(),
+                    })
+                } else {
+                    return None;
+                };
+                Some((transitions, offsets))
+            }
+        }
+    }
+
+    pub fn layout(&self) -> crate::config::DfaLayout {
+        match self {
+            Transitions::Dense16(_) | Transitions::Dense32(_) => crate::config::DfaLayout::Dense,
+            Transitions::Sparse16(_) | Transitions::Sparse32(_) => crate::config::DfaLayout::Sparse,
+        }
+    }
+
+    pub fn state_id_bytes(&self) -> usize {
+        match self {
+            Transitions::Dense16(_) | Transitions::Sparse16(_) => std::mem::size_of::<u16>(),
+            Transitions::Dense32(_) | Transitions::Sparse32(_) => std::mem::size_of::<u32>(),
+        }
+    }
+
+    pub fn table_bytes(&self) -> usize {
+        match self {
+            Transitions::Dense16(Dense { table, .. }) | Transitions::Sparse16(Sparse { table }) => {
+                std::mem::size_of_val(&**table)
+            }
+            Transitions::Dense32(Dense { table, .. }) | Transitions::Sparse32(Sparse { table }) => {
+                std::mem::size_of_val(&**table)
+            }
+        }
+    }
+
+    pub fn offsets(&self, state_count: usize) -> Option<Vec<usize>> {
+        match self {
+            Transitions::Dense16(dense) => dense_offsets(dense, state_count),
+            Transitions::Dense32(dense) => dense_offsets(dense, state_count),
+            Transitions::Sparse16(sparse) => sparse_offsets(sparse),
+            Transitions::Sparse32(sparse) => sparse_offsets(sparse),
+        }
+    }
+
+    pub fn write(&self, writer: &mut crate::serialize::Writer) {
+        let (kind, shift) = match self {
+            Transitions::Dense16(dense) => (0, dense.shift),
+            Transitions::Dense32(dense) => (1, dense.shift),
+            Transitions::Sparse16(_) => (2, 0),
+            Transitions::Sparse32(_) => (3, 0),
+        };
+        writer.u64(kind);
+        writer.u64(shift as u64);
+        match self {
+            Transitions::Dense16(Dense { table, .. }) | Transitions::Sparse16(Sparse { table }) => {
+                writer.slice(table)
+            }
+            Transitions::Dense32(Dense { table, .. }) | Transitions::Sparse32(Sparse { table }) => {
+                writer.slice(table)
+            }
+        }
+    }
+
+    pub fn read(reader: &mut crate::serialize::Reader<'a>) -> crate::Result<Transitions<'a>> {
+        let kind = reader.u64()?;
+        let shift = u32::try_from(reader.u64()?)
+            .ok()
+            .filter(|&shift| shift <= 8)
+            .ok_or(crate::Error::Deserialize("invalid row stride"))?;
+        let transitions = match kind {
+            0 => Transitions::Dense16(Dense {
+                shift,
+                table: reader.slice()?.into(),
+            }),
+            1 => Transitions::Dense32(Dense {
+                shift,
+                table: reader.slice()?.into(),
+            }),
+            2 => Transitions::Sparse16(Sparse {
+                table: reader.slice()?.into(),
+            }),
+            3 => Transitions::Sparse32(Sparse {
+                table: reader.slice()?.into(),
+            }),
+            _ => return Err(crate::Error::Deserialize("unknown table layout")),
+        };
+        Ok(transitions)
+    }
+
+    pub fn validate(&self, offsets: &[usize], alphabet_len: usize) -> bool {
+        let valid_target = |next: usize| next == DEAD || offsets.binary_search(&next).is_ok();
+        match self {
+            Transitions::Dense16(dense) => validate_dense(dense, alphabet_len, valid_target),
+            Transitions::Dense32(dense) => validate_dense(dense, alphabet_len, valid_target),
+            Transitions::Sparse16(sparse) => {
+                validate_sparse(sparse, offsets, alphabet_len, valid_target)
+            }
+            Transitions::Sparse32(sparse) => {
+                validate_sparse(sparse, offsets, alphabet_len, valid_target)
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn as_bytes(&self) -> (bool, *const u8) {
+        match self {
+            Transitions::Dense16(Dense { table, .. }) | Transitions::Sparse16(Sparse { table }) => {
+                (
+                    matches!(table, std::borrow::Cow::Borrowed(_)),
+                    table.as_ptr().cast(),
+                )
+            }
+            Transitions::Dense32(Dense { table, .. }) | Transitions::Sparse32(Sparse { table }) => {
+                (
+                    matches!(table, std::borrow::Cow::Borrowed(_)),
+                    table.as_ptr().cast(),
+                )
+            }
+        }
+    }
+}
+
+pub fn min_table_bytes(layout: crate::config::DfaLayout, stride: usize, states: usize) -> usize {
+    let len = match layout {
+        crate::config::DfaLayout::Dense => states.saturating_mul(stride.next_power_of_two()),
+        crate::config::DfaLayout::Sparse => states,
+    };
+    let id_bytes = if len < u16::LIMIT {
+        std::mem::size_of::<u16>()
+    } else {
+        std::mem::size_of::<u32>()
+    };
+    len.saturating_mul(id_bytes)
+}
+
+fn convert<T: StateID>(table: Vec<usize>) -> Vec<T> {
+    table.into_iter().map(T::from_usize).collect()
+}
+
+fn dense_offsets<T: StateID>(dense: &Dense<T>, state_count: usize) -> Option<Vec<usize>> {
+    let len = dense.table.len();
+    (len >> dense.shift == state_count && len & ((1 << dense.shift) - 1) == 0 && len < T::LIMIT)
+        .then(|| (0..state_count).map(|state| state << dense.shift).collect())
+}
+
+fn sparse_offsets<T: StateID>(sparse: &Sparse<T>) -> Option<Vec<usize>> {
+    if sparse.table.len() >= T::LIMIT {
+        return None;
+    }
+    let mut offsets = Vec::new();
+    let mut at = 0;
+    while at < sparse.table.len() {
+        offsets.push(at);
+        let len = sparse.table[at].raw();
+        at = len
+            .checked_mul(2)
+            .and_then(|words| (at + 1).checked_add(words))?;
+    }
+    (at == sparse.table.len()).then_some(offsets)
+}
+
+fn validate_dense<T: StateID>(
+    dense: &Dense<T>,
+    alphabet_len: usize,
+    valid_target: impl Fn(usize) -> bool,
+) -> bool {
+    alphabet_len <= 1 << dense.shift
+        && dense.table.iter().enumerate().all(|(at, next)| {
+            at & ((1 << dense.shift) - 1) >= alphabet_len || valid_target(next.to_usize())
+        })
+}
+
+fn validate_sparse<T: StateID>(
+    sparse: &Sparse<T>,
+    offsets: &[usize],
+    alphabet_len: usize,
+    valid_target: impl Fn(usize) -> bool,
+) -> bool {
+    offsets.iter().all(|&state| {
+        let len = sparse.table[state].raw();
+        let ranges = &sparse.table[state + 1..state + 1 + len];
+        let targets = &sparse.table[state + 1 + len..state + 1 + 2 * len];
+        let mut min = 0;
+        ranges.iter().all(|range| {
+            let range = range.raw();
+            let (start, end) = (range & 0xFF, range >> 8);
+            let valid = min <= start && start <= end && end < alphabet_len;
+            min = end + 1;
+            valid
+        }) && targets.iter().all(|next| valid_target(next.to_usize()))
+    })
+}
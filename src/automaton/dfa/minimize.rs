@@ -0,0 +1,217 @@
+use foldhash::HashMapExt as _;
+
+struct Partition {
+    elements: Vec<usize>,
+    location: Vec<usize>,
+    block_of: Vec<usize>,
+    bounds: Vec<(usize, usize)>,
+    marked: Vec<usize>,
+}
+
+impl Partition {
+    fn new(keys: &[usize]) -> Self {
+        let mut elements: Vec<usize> = (0..keys.len()).collect();
+        elements.sort_by_key(|&state| keys[state]);
+
+        let mut location = vec![0; keys.len()];
+        let mut block_of = vec![0; keys.len()];
+        let mut bounds = Vec::new();
+        for (i, &state) in elements.iter().enumerate() {
+            if i == 0 || keys[elements[i - 1]] != keys[state] {
+                bounds.push((i, i));
+            }
+            bounds.last_mut().unwrap().1 = i + 1;
+            location[state] = i;
+            block_of[state] = bounds.len() - 1;
+        }
+
+        let marked = vec![0; bounds.len()];
+        Partition {
+            elements,
+            location,
+            block_of,
+            bounds,
+            marked,
+        }
+    }
+
+    fn members(&self, block: usize) -> &[usize] {
+        let (start, end) = self.bounds[block];
+        &self.elements[start..end]
+    }
+
+    fn mark(&mut self, state: usize, touched: &mut Vec<usize>) {
+        let block = self.block_of[state];
+        let target = self.bounds[block].0 + self.marked[block];
+        let at = self.location[state];
+        if at < target {
+            return;
+        }
+        self.elements.swap(at, target);
+        self.location[self.elements[at]] = at;
+        self.location[state] = target;
+        if self.marked[block] == 0 {
+            touched.push(block);
+        }
+        self.marked[block] += 1;
+    }
+
+    fn split(&mut self, block: usize) -> Option<usize> {
+        let (start, end) = self.bounds[block];
+        let marked = std::mem::take(&mut self.marked[block]);
+        if marked == end - start {
+            return None;
+        }
+
+        let new_block = self.bounds.len();
+        self.bounds.push((start, start + marked));
+        self.marked.push(0);
+        self.bounds[block].0 = start + marked;
+        for &state in &self.elements[start..start + marked] {
+            self.block_of[state] = new_block;
+        }
+        Some(new_block)
+    }
+}
+
+pub fn minimize(dfa: &super::Table) -> super::Table {
+    let n = dfa.state_count();
+    let dead = n;
+
+    let target = |state: usize, symbol: usize| -> usize {
+        if state == dead {
+            return dead;
+        }
+        match dfa.next[state * dfa.stride + symbol] {
+            super::DEAD => dead,
+            next => next as usize,
+        }
+    };
+
+    let mut inverse: Vec<Vec<usize>> = Vec::with_capacity(dfa.stride);
+    let mut inverse_start: Vec<Vec<usize>> = Vec::with_capacity(dfa.stride);
+    for symbol in 0..dfa.stride {
+        let mut start = vec![0; n + 2];
+        for state in 0..=n {
+            start[target(state, symbol) + 1] += 1;
+        }
+        for i in 1..start.len() {
+            start[i] += start[i - 1];
+        }
+        let mut fill = start.clone();
+        let mut sources = vec![0; n + 1];
+        for state in 0..=n {
+            let next = target(state, symbol);
+            sources[fill[next]] = state;
+            fill[next] += 1;
+        }
+        inverse.push(sources);
+        inverse_start.push(start);
+    }
+
+    let mut signatures = foldhash::HashMap::new();
+    let keys: Vec<usize> = (0..=n)
+        .map(|state| {
+            // Tagged states must also agree on their register commands and final registers.
+            let signature = if state == dead {
+                None
+            } else {
+                Some((
+                    dfa.accepts[state],
+                    dfa.finals.get(state),
+                    dfa.ops.get(state * dfa.stride..(state + 1) * dfa.stride),
+                ))
+            };
+            let next = signatures.len();
+            *signatures.entry(signature).or_insert(next)
+        })
+        .collect();
+
+    let mut partition = Partition::new(&keys);
+    let mut worklist: Vec<usize> = (0..partition.bounds.len()).collect();
+    let mut in_worklist = vec![true; partition.bounds.len()];
+    let mut touched = Vec::new();
+    let mut splitter = Vec::new();
+
+    while let Some(block) = worklist.pop() {
+        in_worklist[block] = false;
+        let members = partition.members(block).to_vec();
+        for symbol in 0..dfa.stride {
+            splitter.clear();
+            for &state in &members {
+                let range = inverse_start[symbol][state]..inverse_start[symbol][state + 1];
+                splitter.extend_from_slice(&inverse[symbol][range]);
+            }
+            for &state in &splitter {
+                partition.mark(state, &mut touched);
+            }
+            for block in touched.drain(..) {
+                let Some(new_block) = partition.split(block) else {
+                    continue;
+                };
+                in_worklist.push(false);
+                let (old_start, old_end) = partition.bounds[block];
+                let (new_start, new_end) = partition.bounds[new_block];
+                let push = if in_worklist[block] || new_end - new_start <= old_end - old_start {
+                    new_block
+                } else {
+                    block
+                };
+                if !in_worklist[push] {
+                    in_worklist[push] = true;
+                    worklist.push(push);
+                }
+            }
+        }
+    }
+
+    let dead_block = partition.block_of[dead];
+    let mut ids = vec![super::DEAD; partition.bounds.len()];
+    let mut order = vec![partition.block_of[dfa.start]];
+    ids[order[0]] = 0;
+    let mut i = 0;
+    while i < order.len() {
+        let state = partition.members(order[i])[0];
+        for symbol in 0..dfa.stride {
+            let block = partition.block_of[target(state, symbol)];
+            if block != dead_block && ids[block] == super::DEAD {
+                ids[block] = order.len() as super::DfaStateID;
+                order.push(block);
+            }
+        }
+        i += 1;
+    }
+
+    let map = |next: super::DfaStateID| -> super::DfaStateID {
+        if next == super::DEAD {
+            super::DEAD
+        } else {
+            ids[partition.block_of[next as usize]]
+        }
+    };
+
+    let mut accepts = Vec::with_capacity(order.len());
+    let mut table = vec![super::DEAD; order.len() * dfa.stride];
+    let mut ops = Vec::new();
+    let mut finals = Vec::new();
+    for (id, &block) in order.iter().enumerate() {
+        let state = partition.members(block)[0];
+        accepts.push(dfa.accepts[state]);
+        for byte in 0..dfa.stride {
+            table[id * dfa.stride + byte] = map(dfa.next[state * dfa.stride + byte]);
+        }
+        if let Some(row) = dfa.ops.get(state * dfa.stride..(state + 1) * dfa.stride) {
+            ops.extend_from_slice(row);
+        }
+        finals.extend(dfa.finals.get(state).cloned());
+    }
+    super::Table {
+        start: 0,
+        accepts,
+        classes: dfa.classes,
+        stride: dfa.stride,
+        next: table,
+        ops,
+        finals,
+    }
+}
@@ -0,0 +1,688 @@
+use foldhash::HashMapExt as _;
+
+struct SparseSet {
+    dense: Vec<u32>,
+    sparse: Vec<u32>,
+}
+
+impl SparseSet {
+    fn new(capacity: usize) -> Self {
+        SparseSet {
+            dense: Vec::with_capacity(capacity),
+            sparse: vec![0; capacity],
+        }
+    }
+
+    fn insert(&mut self, value: u32) {
+        if self.contains(value) {
+            return;
+        }
+        self.sparse[value as usize] = self.dense.len() as u32;
+        self.dense.push(value);
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        let index = self.sparse[value as usize] as usize;
+        index < self.dense.len() && self.dense[index] == value
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
+    }
+}
+
+struct StateSets {
+    arena: Vec<u32>,
+    bounds: Vec<usize>,
+    heads: foldhash::HashMap<u64, usize>,
+    chain: Vec<Option<usize>>,
+    hasher: foldhash::fast::FixedState,
+}
+
+impl StateSets {
+    fn new() -> Self {
+        StateSets {
+            arena: Vec::new(),
+            bounds: vec![0],
+            heads: foldhash::HashMap::new(),
+            chain: Vec::new(),
+            hasher: foldhash::fast::FixedState::default(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.chain.len()
+    }
+
+    fn get(&self, id: usize) -> &[u32] {
+        &self.arena[self.bounds[id]..self.bounds[id + 1]]
+    }
+
+    fn find(&self, set: &[u32]) -> (u64, Option<usize>) {
+        let hash = std::hash::BuildHasher::hash_one(&self.hasher, set);
+        let mut next = self.heads.get(&hash).copied();
+        while let Some(id) = next {
+            if self.get(id) == set {
+                return (hash, Some(id));
+            }
+            next = self.chain[id];
+        }
+        (hash, None)
+    }
+
+    fn insert(&mut self, hash: u64, set: &[u32]) -> usize {
+        let id = self.len();
+        self.arena.extend_from_slice(set);
+        self.bounds.push(self.arena.len());
+        self.chain.push(self.heads.insert(hash, id));
+        id
+    }
+}
+
+enum Symbol {
+    Ascii(usize),
+    Literal(char),
+    Other,
+}
+
+struct Alphabet {
+    ascii: Vec<Vec<char>>,
+    literals: Vec<char>,
+    symbols: Vec<Vec<usize>>,
+    edges: Vec<Vec<(usize, u32)>>,
+}
+
+impl Alphabet {
+    fn new(nfa: &crate::automaton::nfa::Nfa) -> Self {
+        let mut ids = foldhash::HashMap::new();
+        let mut labels = Vec::new();
+        let edges = (0..nfa.state_count() as u32)
+            .map(|state| {
+                nfa.node(state)
+                    .transitions
+                    .iter()
+                    .map(|&(label, to)| {
+                        let id = *ids.entry(label).or_insert_with(|| {
+                            labels.push(label);
+                            labels.len() - 1
+                        });
+                        (id, to)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let signatures: Vec<Vec<bool>> = (0u8..128)
+            .map(|byte| {
+                labels
+                    .iter()
+                    .map(|&label| match label {
+                        crate::automaton::label::NfaLabel::Char(c) => c == byte as char,
+                        crate::automaton::label::NfaLabel::Class(class) => {
+                            class.matches(byte as char)
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut groups: Vec<&Vec<bool>> = signatures
+            .iter()
+            .filter(|signature| signature.contains(&true))
+            .collect();
+        groups.sort_unstable();
+        groups.dedup();
+        let mut ascii = vec![Vec::new(); groups.len()];
+        for (byte, signature) in signatures.iter().enumerate() {
+            if let Ok(group) = groups.binary_search(&signature) {
+                ascii[group].push(byte as u8 as char);
+            }
+        }
+
+        let mut literals: Vec<char> = labels
+            .iter()
+            .filter_map(|&label| match label {
+                crate::automaton::label::NfaLabel::Char(c) if !c.is_ascii() => Some(c),
+                _ => None,
+            })
+            .collect();
+        literals.sort_unstable();
+
+        let other = groups.len() + literals.len();
+        let symbols = labels
+            .iter()
+            .enumerate()
+            .map(|(id, &label)| {
+                let mut symbols: Vec<usize> = (0..groups.len())
+                    .filter(|&group| groups[group][id])
+                    .collect();
+                match label {
+                    crate::automaton::label::NfaLabel::Char(c) if !c.is_ascii() => {
+                        symbols.push(groups.len() + literals.binary_search(&c).unwrap());
+                    }
+                    crate::automaton::label::NfaLabel::Class(crate::charclass::CharClass::Any) => {
+                        symbols.push(other);
+                    }
+                    _ => {}
+                }
+                symbols
+            })
+            .collect();
+
+        Alphabet {
+            ascii,
+            literals,
+            symbols,
+            edges,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.ascii.len() + self.literals.len() + 1
+    }
+
+    fn symbol(&self, symbol: usize) -> Symbol {
+        if symbol < self.ascii.len() {
+            Symbol::Ascii(symbol)
+        } else if symbol - self.ascii.len() < self.literals.len() {
+            Symbol::Literal(self.literals[symbol - self.ascii.len()])
+        } else {
+            Symbol::Other
+        }
+    }
+
+    fn collect(&self, states: &[u32], buckets: &mut [Vec<u32>], touched: &mut Vec<usize>) {
+        for &state in states {
+            for &(label, to) in &self.edges[state as usize] {
+                for &symbol in &self.symbols[label] {
+                    let bucket = &mut buckets[symbol];
+                    if bucket.is_empty() {
+                        touched.push(symbol);
+                    }
+                    bucket.push(to);
+                }
+            }
+        }
+    }
+
+    fn step(&self, state: u32, symbol: usize) -> impl Iterator<Item = u32> + '_ {
+        let literal = matches!(self.symbol(symbol), Symbol::Literal(_));
+        self.edges[state as usize]
+            .iter()
+            .filter(move |&&(label, _)| {
+                let symbols = &self.symbols[label];
+                symbols.contains(&symbol) || literal && symbols.contains(&(self.len() - 1))
+            })
+            .map(|&(_, to)| to)
+    }
+
+    fn targets<'a>(
+        &self,
+        buckets: &'a [Vec<u32>],
+        symbol: usize,
+    ) -> impl Iterator<Item = u32> + 'a {
+        let fallback = match self.symbol(symbol) {
+            Symbol::Literal(_) => buckets[self.len() - 1].as_slice(),
+            _ => &[],
+        };
+        buckets[symbol].iter().chain(fallback).copied()
+    }
+}
+
+pub fn build(
+    nfa: &crate::automaton::nfa::Nfa,
+    stride: usize,
+    config: &crate::config::Config,
+    reverse: bool,
+) -> crate::Result<crate::automaton::dfa::CharDfa> {
+    let alphabet = Alphabet::new(nfa);
+    let mut accepting = vec![false; nfa.state_count()];
+    for &state in nfa.accept() {
+        accepting[state as usize] = true;
+    }
+
+    let start = nfa.closure(nfa.start());
+    let base = if reverse { start } else { &[] };
+    let mut in_base = vec![false; nfa.state_count()];
+    for &state in base {
+        in_base[state as usize] = true;
+    }
+    let base_accepting = base.iter().any(|&state| accepting[state as usize]);
+
+    let mut sparse = SparseSet::new(nfa.state_count());
+    let mut buckets = vec![Vec::new(); alphabet.len()];
+    let mut base_touched = Vec::new();
+    alphabet.collect(base, &mut buckets, &mut base_touched);
+    let base_steps: Vec<Vec<u32>> = (0..alphabet.len())
+        .map(|symbol| {
+            sparse.clear();
+            for to in alphabet.targets(&buckets, symbol) {
+                for &state in nfa.closure(to) {
+                    if !in_base[state as usize] {
+                        sparse.insert(state);
+                    }
+                }
+            }
+            sparse.dense.clone()
+        })
+        .collect();
+    for bucket in &mut buckets {
+        bucket.clear();
+    }
+
+    let mut sets = StateSets::new();
+    let initial: Vec<u32> = start
+        .iter()
+        .copied()
+        .filter(|&state| !in_base[state as usize])
+        .collect();
+    let (hash, _) = sets.find(&initial);
+    sets.insert(hash, &initial);
+
+    let mut base_ids = vec![None; alphabet.len()];
+    let mut touched = Vec::new();
+    let mut next = Vec::new();
+    let mut accepts = Vec::new();
+    let mut chars = Vec::new();
+    let mut any = Vec::new();
+
+    let mut current = 0;
+    while current < sets.len() {
+        let set = sets.get(current);
+        accepts.push(base_accepting || set.iter().any(|&state| accepting[state as usize]));
+        alphabet.collect(set, &mut buckets, &mut touched);
+        for &symbol in &base_touched {
+            if buckets[symbol].is_empty() {
+                touched.push(symbol);
+            }
+        }
+        touched.sort_unstable();
+        touched.dedup();
+
+        for &symbol in &touched {
+            let own = alphabet.targets(&buckets, symbol).next().is_some();
+            let id = match base_ids[symbol] {
+                Some(id) if !own => id,
+                _ => {
+                    sparse.clear();
+                    for &state in &base_steps[symbol] {
+                        sparse.insert(state);
+                    }
+                    for to in alphabet.targets(&buckets, symbol) {
+                        for &state in nfa.closure(to) {
+                            if !in_base[state as usize] {
+                                sparse.insert(state);
+                            }
+                        }
+                    }
+                    next.clear();
+                    next.extend_from_slice(&sparse.dense);
+                    next.sort_unstable();
+
+                    let id = match sets.find(&next) {
+                        (_, Some(id)) => id,
+                        (hash, None) => {
+                            crate::automaton::dfa::check_size(sets.len() + 1, stride, config)?;
+                            sets.insert(hash, &next)
+                        }
+                    };
+                    if !own {
+                        base_ids[symbol] = Some(id);
+                    }
+                    id
+                }
+            };
+            let (from, to) = (
+                current as crate::automaton::dfa::DfaStateID,
+                id as crate::automaton::dfa::DfaStateID,
+            );
+            match alphabet.symbol(symbol) {
+                Symbol::Ascii(group) => {
+                    chars.extend(alphabet.ascii[group].iter().map(|&c| (from, c, to)))
+                }
+                Symbol::Literal(c) => chars.push((from, c, to)),
+                Symbol::Other => any.push((from, to)),
+            }
+        }
+        for symbol in touched.drain(..) {
+            buckets[symbol].clear();
+        }
+        current += 1;
+    }
+
+    Ok(crate::automaton::dfa::CharDfa {
+        start: 0,
+        accepts,
+        chars,
+        any,
+    })
+}
+
+enum Frame {
+    Explore(u32),
+    Emit(u32),
+    Untag,
+}
+
+// Walks epsilons depth first in priority order like the Pike VM, emitting consuming states on
+// entry and accepting states after their own epsilons, with the tags set along the path.
+fn tagged_closure(
+    nfa: &crate::automaton::nfa::Nfa,
+    accepting: &[bool],
+    from: u32,
+    visited: &mut SparseSet,
+    path: &mut Vec<u32>,
+    stack: &mut Vec<Frame>,
+    mut emit: impl FnMut(u32, &[u32]),
+) {
+    stack.push(Frame::Explore(from));
+    while let Some(frame) = stack.pop() {
+        let state = match frame {
+            Frame::Untag => {
+                path.pop();
+                continue;
+            }
+            Frame::Emit(state) => {
+                emit(state, path);
+                continue;
+            }
+            Frame::Explore(state) if visited.contains(state) => continue,
+            Frame::Explore(state) => state,
+        };
+        visited.insert(state);
+        let node = nfa.node(state);
+        if let Some(tag) = node.tag {
+            path.push(tag);
+            stack.push(Frame::Untag);
+        }
+        if accepting[state as usize] {
+            stack.push(Frame::Emit(state));
+        }
+        if !node.transitions.is_empty() {
+            emit(state, path);
+        }
+        stack.extend(node.epsilons.iter().rev().map(|&to| Frame::Explore(to)));
+    }
+}
+
+// A tagged state is its threads in priority order followed by each thread's register per slot.
+// Registers are numbered by first use, so states that differ only by register names coincide.
+fn canonical(
+    threads: &[(u32, u32, Vec<u32>)],
+    old: &[u32],
+    slots: usize,
+    key: &mut Vec<u32>,
+    sources: &mut Vec<u32>,
+) {
+    let old_len = old.len() / (1 + slots);
+    key.clear();
+    sources.clear();
+    key.extend(threads.iter().map(|&(state, _, _)| state));
+    for (_, from, tags) in threads {
+        for slot in 0..slots {
+            let value = if tags.contains(&(slot as u32)) {
+                crate::automaton::dfa::tagged::SET
+            } else if *from == crate::automaton::dfa::tagged::NONE {
+                crate::automaton::dfa::tagged::NONE
+            } else {
+                old[old_len + *from as usize * slots + slot]
+            };
+            key.push(match value {
+                crate::automaton::dfa::tagged::NONE => value,
+                _ => match sources.iter().position(|&source| source == value) {
+                    Some(register) => register as u32,
+                    None => {
+                        sources.push(value);
+                        sources.len() as u32 - 1
+                    }
+                },
+            });
+        }
+    }
+}
+
+// Orders the parallel copy `register[i] = old[sources[i]]` so that no register is overwritten
+// before it is read, breaking cycles through `temp`, and sets registers last.
+fn sequence(sources: &[u32], temp: u32, commands: &mut Vec<u32>) {
+    let mut moves: Vec<(u32, u32)> = sources
+        .iter()
+        .enumerate()
+        .map(|(dst, &src)| (dst as u32, src))
+        .filter(|&(dst, src)| src != dst && src != crate::automaton::dfa::tagged::SET)
+        .collect();
+    while !moves.is_empty() {
+        match moves
+            .iter()
+            .position(|&(dst, _)| moves.iter().all(|&(_, src)| src != dst))
+        {
+            Some(ready) => {
+                let (dst, src) = moves.remove(ready);
+                commands.extend([dst, src]);
+            }
+            None => {
+                let saved = moves[0].0;
+                commands.extend([temp, saved]);
+                for (_, src) in &mut moves {
+                    if *src == saved {
+                        *src = temp;
+                    }
+                }
+            }
+        }
+    }
+    for (dst, &src) in sources.iter().enumerate() {
+        if src == crate::automaton::dfa::tagged::SET {
+            commands.extend([dst as u32, src]);
+        }
+    }
+}
+
+pub fn build_tagged(
+    nfa: &crate::automaton::nfa::Nfa,
+    slots: usize,
+    stride: usize,
+    config: &crate::config::Config,
+) -> crate::Result<(
+    crate::automaton::dfa::CharDfa,
+    crate::automaton::dfa::tagged::Tags,
+)> {
+    let alphabet = Alphabet::new(nfa);
+    let mut accepting = vec![false; nfa.state_count()];
+    for &state in nfa.accept() {
+        accepting[state as usize] = true;
+    }
+
+    let mut visited = SparseSet::new(nfa.state_count());
+    let mut path = Vec::new();
+    let mut stack = Vec::new();
+    let mut threads = Vec::new();
+    let mut key = Vec::new();
+    let mut sources = Vec::new();
+    let mut command = Vec::new();
+    let mut commands = vec![Vec::new()];
+    let mut command_ids = foldhash::HashMap::new();
+    command_ids.insert(Vec::new(), 0);
+    let mut intern = |command: &[u32]| -> u32 {
+        *command_ids.entry(command.to_vec()).or_insert_with(|| {
+            commands.push(command.to_vec());
+            commands.len() as u32 - 1
+        })
+    };
+
+    tagged_closure(
+        nfa,
+        &accepting,
+        nfa.start(),
+        &mut visited,
+        &mut path,
+        &mut stack,
+        |state, tags| threads.push((state, crate::automaton::dfa::tagged::NONE, tags.to_vec())),
+    );
+    canonical(&threads, &[], slots, &mut key, &mut sources);
+    sequence(&sources, sources.len() as u32, &mut command);
+    let init = intern(&command);
+    let mut registers = sources.len() + 1;
+
+    let mut sets = StateSets::new();
+    let (hash, _) = sets.find(&key);
+    sets.insert(hash, &key);
+
+    let mut accepts = Vec::new();
+    let mut finals = Vec::new();
+    let mut chars = Vec::new();
+    let mut any = Vec::new();
+    let mut char_ops = Vec::new();
+    let mut any_ops = Vec::new();
+
+    let mut current = 0;
+    while current < sets.len() {
+        let old = sets.get(current).to_vec();
+        let (states, old_registers) = old.split_at(old.len() / (1 + slots));
+        match states.iter().position(|&state| accepting[state as usize]) {
+            Some(thread) => {
+                accepts.push(true);
+                finals.push(old_registers[thread * slots..(thread + 1) * slots].into());
+            }
+            None => {
+                accepts.push(false);
+                finals.push(vec![crate::automaton::dfa::tagged::NONE; slots].into());
+            }
+        }
+        let in_use = old_registers
+            .iter()
+            .filter(|&&register| register != crate::automaton::dfa::tagged::NONE)
+            .max()
+            .map_or(0, |&register| register as usize + 1);
+
+        for symbol in 0..alphabet.len() {
+            visited.clear();
+            threads.clear();
+            for (thread, &state) in states.iter().enumerate() {
+                for to in alphabet.step(state, symbol) {
+                    tagged_closure(
+                        nfa,
+                        &accepting,
+                        to,
+                        &mut visited,
+                        &mut path,
+                        &mut stack,
+                        |state, tags| threads.push((state, thread as u32, tags.to_vec())),
+                    );
+                }
+            }
+            if threads.is_empty() {
+                continue;
+            }
+
+            canonical(&threads, &old, slots, &mut key, &mut sources);
+            let id = match sets.find(&key) {
+                (_, Some(id)) => id,
+                (hash, None) => {
+                    crate::automaton::dfa::check_size(sets.len() + 1, stride, config)?;
+                    sets.insert(hash, &key)
+                }
+            };
+            let temp = in_use.max(sources.len());
+            registers = registers.max(temp + 1);
+            command.clear();
+            sequence(&sources, temp as u32, &mut command);
+            let op = intern(&command);
+
+            let (from, to) = (
+                current as crate::automaton::dfa::DfaStateID,
+                id as crate::automaton::dfa::DfaStateID,
+            );
+            match alphabet.symbol(symbol) {
+                Symbol::Ascii(group) => {
+                    for &c in &alphabet.ascii[group] {
+                        chars.push((from, c, to));
+                        char_ops.push(op);
+                    }
+                }
+                Symbol::Literal(c) => {
+                    chars.push((from, c, to));
+                    char_ops.push(op);
+                }
+                Symbol::Other => {
+                    any.push((from, to));
+                    any_ops.push(op);
+                }
+            }
+        }
+        current += 1;
+    }
+
+    Ok((
+        crate::automaton::dfa::CharDfa {
+            start: 0,
+            accepts,
+            chars,
+            any,
+        },
+        crate::automaton::dfa::tagged::Tags {
+            slots,
+            registers,
+            init,
+            commands,
+            chars: char_ops,
+            any: any_ops,
+            finals,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_set() {
+        let mut set = SparseSet::new(8);
+        for value in [3, 1, 3, 7, 1] {
+            set.insert(value);
+        }
+        assert_eq!(set.dense, [3, 1, 7]);
+        set.clear();
+        set.insert(7);
+        assert_eq!(set.dense, [7]);
+    }
+
+    #[test]
+    fn state_sets() {
+        let mut sets = StateSets::new();
+        for set in [&[0, 2][..], &[], &[1, 2, 3]] {
+            let (hash, found) = sets.find(set);
+            assert_eq!(found, None);
+            sets.insert(hash, set);
+        }
+        assert_eq!(sets.len(), 3);
+        assert_eq!(sets.find(&[1, 2, 3]).1, Some(2));
+        assert_eq!(sets.find(&[]).1, Some(1));
+        assert_eq!(sets.get(0), [0, 2]);
+        assert_eq!(sets.find(&[0, 2, 3]).1, None);
+    }
+
+    #[test]
+    fn register_copies() {
+        let mut commands = Vec::new();
+        sequence(
+            &[1, 0, crate::automaton::dfa::tagged::SET, 3],
+            4,
+            &mut commands,
+        );
+        assert_eq!(
+            commands,
+            [4, 0, 0, 1, 1, 4, 2, crate::automaton::dfa::tagged::SET]
+        );
+
+        commands.clear();
+        sequence(
+            &[crate::automaton::dfa::tagged::SET, 0, 1],
+            3,
+            &mut commands,
+        );
+        assert_eq!(
+            commands,
+            [2, 1, 1, 0, 0, crate::automaton::dfa::tagged::SET]
+        );
+    }
+}
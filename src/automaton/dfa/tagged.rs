@@ -0,0 +1,349 @@
+pub const NONE: u32 = u32::MAX;
+pub const SET: u32 = u32::MAX - 1;
+
+// Register commands collected by the tagged subset construction, parallel to the `CharDfa`
+// transitions. Each command is a list of `(dst, src)` pairs where `src` is a register or `SET`.
+pub struct Tags {
+    pub slots: usize,
+    pub registers: usize,
+    pub init: u32,
+    pub commands: Vec<Vec<u32>>,
+    pub chars: Vec<u32>,
+    pub any: Vec<u32>,
+    pub finals: Vec<Box<[u32]>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaggedDfa<'a> {
+    dfa: super::Dfa<'a>,
+    slots: usize,
+    registers: usize,
+    init: usize,
+    bounds: std::borrow::Cow<'a, [u32]>,
+    commands: std::borrow::Cow<'a, [u32]>,
+    ops: std::borrow::Cow<'a, [u32]>,
+    finals: std::borrow::Cow<'a, [u32]>,
+    offsets: Vec<usize>,
+}
+
+impl TaggedDfa<'_> {
+    pub fn new(
+        nfa: &crate::automaton::nfa::Nfa,
+        slots: usize,
+        config: &crate::config::Config,
+    ) -> crate::Result<TaggedDfa<'static>> {
+        let classes = super::classes::from_nfa(nfa);
+        let stride = super::classes::alphabet_len(&classes);
+        let (dfa, tags) = super::subset::build_tagged(nfa, slots, stride, config)?;
+        let (dfa, table, order) =
+            super::Dfa::finish_with_tags(dfa, Some(&tags), Some(classes), config, false)?;
+
+        let mut bounds = vec![0];
+        let mut commands = Vec::new();
+        for command in &tags.commands {
+            commands.extend_from_slice(command);
+            bounds.push(commands.len() as u32);
+        }
+        let tagged = TaggedDfa {
+            slots,
+            registers: tags.registers,
+            init: tags.init as usize,
+            bounds: bounds.into(),
+            commands: commands.into(),
+            ops: order
+                .iter()
+                .flat_map(|&state| &table.ops[state * table.stride..(state + 1) * table.stride])
+                .copied()
+                .collect::<Vec<_>>()
+                .into(),
+            finals: order
+                .iter()
+                .flat_map(|&state| &*table.finals[state])
+                .copied()
+                .collect::<Vec<_>>()
+                .into(),
+            offsets: dfa.transitions.offsets(dfa.state_count).unwrap_or_default(),
+            dfa,
+        };
+        crate::config::Limit::DfaBytes.check(tagged.memory().total_bytes, config.max_dfa_bytes)?;
+        Ok(tagged)
+    }
+}
+
+impl<'a> TaggedDfa<'a> {
+    pub fn dfa(&self) -> &super::Dfa<'a> {
+        &self.dfa
+    }
+
+    pub fn stats(&self, reverse: Option<&super::Dfa>) -> crate::stats::Stats {
+        crate::stats::Stats::new(
+            self.dfa.minimization,
+            Some(self.memory()),
+            reverse.map(super::Dfa::memory),
+        )
+    }
+
+    pub fn memory(&self) -> crate::stats::Memory {
+        let mut memory = self.dfa.memory();
+        memory.total_bytes += std::mem::size_of_val(&*self.bounds)
+            + std::mem::size_of_val(&*self.commands)
+            + std::mem::size_of_val(&*self.ops)
+            + std::mem::size_of_val(&*self.finals);
+        memory
+    }
+
+    pub fn captures(&self, input: &str) -> Option<Vec<Option<usize>>> {
+        match &self.dfa.transitions {
+            super::layout::Transitions::Dense16(table) => self.captures_in(table, input),
+            super::layout::Transitions::Dense32(table) => self.captures_in(table, input),
+            super::layout::Transitions::Sparse16(table) => self.captures_in(table, input),
+            super::layout::Transitions::Sparse32(table) => self.captures_in(table, input),
+        }
+    }
+
+    fn captures_in<T: super::layout::Table>(
+        &self,
+        table: &T,
+        input: &str,
+    ) -> Option<Vec<Option<usize>>> {
+        let stride = super::classes::alphabet_len(&self.dfa.classes);
+        let mut registers = vec![None; self.registers];
+        self.apply(self.init, &mut registers, 0);
+
+        let mut state = self.dfa.start;
+        for (at, &byte) in input.as_bytes().iter().enumerate() {
+            let class = self.dfa.classes[byte as usize];
+            let next = table.next(state, class);
+            if next == super::layout::DEAD {
+                return None;
+            }
+            let op = self.ops[self.index(table, state) * stride + class as usize];
+            self.apply(op as usize, &mut registers, at + 1);
+            state = next;
+        }
+
+        if !self.dfa.is_accept(state) {
+            return None;
+        }
+        let index = self.index(table, state);
+        Some(
+            self.finals[index * self.slots..(index + 1) * self.slots]
+                .iter()
+                .map(|&register| match register {
+                    NONE => None,
+                    register => registers[register as usize],
+                })
+                .collect(),
+        )
+    }
+
+    #[inline]
+    fn index<T: super::layout::Table>(&self, table: &T, state: usize) -> usize {
+        table
+            .accel_index(state)
+            .unwrap_or_else(|| self.offsets.binary_search(&state).unwrap())
+    }
+
+    #[inline]
+    fn apply(&self, command: usize, registers: &mut [Option<usize>], at: usize) {
+        let range = self.bounds[command] as usize..self.bounds[command + 1] as usize;
+        for pair in self.commands[range].chunks_exact(2) {
+            registers[pair[0] as usize] = match pair[1] {
+                SET => Some(at),
+                src => registers[src as usize],
+            };
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.dfa.to_bytes();
+        let mut writer = crate::serialize::Writer::new(crate::serialize::Kind::Tags);
+        writer.u64(self.slots as u64);
+        writer.u64(self.registers as u64);
+        writer.u64(self.init as u64);
+        writer.slice(&self.bounds);
+        writer.slice(&self.commands);
+        writer.slice(&self.ops);
+        writer.slice(&self.finals);
+        bytes.extend(writer.finish());
+        bytes
+    }
+
+    pub fn from_bytes_prefix(bytes: &'a [u8]) -> crate::Result<(TaggedDfa<'a>, &'a [u8])> {
+        let (dfa, rest) = super::Dfa::from_bytes_prefix(bytes)?;
+        let mut reader = crate::serialize::Reader::new(rest, crate::serialize::Kind::Tags)?;
+        let usize = |value: u64| {
+            usize::try_from(value).map_err(|_| crate::Error::Deserialize("offset too large"))
+        };
+        let tagged = TaggedDfa {
+            slots: usize(reader.u64()?)?,
+            registers: usize(reader.u64()?)?,
+            init: usize(reader.u64()?)?,
+            bounds: reader.slice()?.into(),
+            commands: reader.slice()?.into(),
+            ops: reader.slice()?.into(),
+            finals: reader.slice()?.into(),
+            offsets: dfa.transitions.offsets(dfa.state_count).unwrap_or_default(),
+            dfa,
+        };
+        tagged.validate()?;
+        Ok((tagged, reader.rest()))
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        let count = self.bounds.len().saturating_sub(1);
+        if self.dfa.is_reverse()
+            || self.bounds.first() != Some(&0)
+            || self
+                .bounds
+                .windows(2)
+                .any(|w| w[0] > w[1] || (w[1] - w[0]) % 2 != 0)
+            || self.bounds.last().map(|&end| end as usize) != Some(self.commands.len())
+            || self.init >= count
+        {
+            return Err(crate::Error::Deserialize("invalid register commands"));
+        }
+        let register = |register: u32| (register as usize) < self.registers;
+        if !self
+            .commands
+            .chunks_exact(2)
+            .all(|pair| register(pair[0]) && (pair[1] == SET || register(pair[1])))
+        {
+            return Err(crate::Error::Deserialize("invalid register commands"));
+        }
+        let stride = super::classes::alphabet_len(&self.dfa.classes);
+        if self.ops.len() != self.dfa.state_count * stride
+            || self.ops.iter().any(|&op| op as usize >= count)
+        {
+            return Err(crate::Error::Deserialize("invalid register commands"));
+        }
+        if self.finals.len() != self.dfa.state_count * self.slots
+            || !self
+                .finals
+                .iter()
+                .all(|&final_register| final_register == NONE || register(final_register))
+        {
+            return Err(crate::Error::Deserialize("invalid final registers"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged(pattern: &str, config: &crate::config::Config) -> crate::Result<TaggedDfa<'static>> {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let mut parser = crate::parser::Parser::with_captures(&mut lexer);
+        let ast = parser.parse().unwrap();
+        let slots = 2 * (parser.groups() + 1);
+        TaggedDfa::new(
+            &crate::automaton::nfa::Nfa::tagged(ast, config)?,
+            slots,
+            config,
+        )
+    }
+
+    fn pike(pattern: &str, input: &str) -> Option<Vec<Option<usize>>> {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let mut parser = crate::parser::Parser::with_captures(&mut lexer);
+        let ast = parser.parse().unwrap();
+        let groups =
+            crate::vm::Groups::new(ast, parser.groups(), &crate::config::Config::default())
+                .unwrap();
+        groups.captures(input)
+    }
+
+    #[test]
+    fn tagged_captures() {
+        let cases: [(&str, &[&str]); 6] = [
+            ("(a|ab)(c|bcd)(d*)", &["abcd", "acd", "abcdd", "ab", ""]),
+            (r"(\w+)(\d+)", &["abc12", "1", "a1b2", "12"]),
+            ("(a*)*(b)?", &["", "aa", "aab", "b", "ba"]),
+            ("(.)(正規|.)*", &["x正規", "正規表現", "\n"]),
+            ("((a)|b)+", &["ab", "ba", "abab", "c"]),
+            ("(a?)+(b|)(x*)*", &["", "a", "ab", "axx", "bx"]),
+        ];
+        for layout in [
+            crate::config::DfaLayout::Dense,
+            crate::config::DfaLayout::Sparse,
+        ] {
+            for minimize in [false, true] {
+                let config = crate::config::Config {
+                    dfa_layout: layout,
+                    minimize,
+                    ..crate::config::Config::default()
+                };
+                for (pattern, inputs) in cases {
+                    let tdfa = tagged(pattern, &config).unwrap();
+                    for input in inputs {
+                        assert_eq!(
+                            tdfa.captures(input),
+                            pike(pattern, input),
+                            "{pattern} {input} {layout:?} {minimize}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn register_commands() {
+        let tdfa = tagged("(a)(b)", &crate::config::Config::default()).unwrap();
+        let copies = tdfa
+            .commands
+            .chunks_exact(2)
+            .filter(|pair| pair[1] != SET)
+            .count();
+        assert_eq!(copies, 0);
+    }
+
+    #[test]
+    fn limits() {
+        let pattern = "(a|b)*a(a|b)(a|b)(a|b)";
+        assert!(matches!(
+            tagged(
+                pattern,
+                &crate::config::Config {
+                    max_dfa_states: 8,
+                    ..crate::config::Config::default()
+                }
+            ),
+            Err(crate::Error::LimitExceeded(crate::Limit::DfaStates, 8))
+        ));
+        assert!(
+            tagged(pattern, &crate::config::Config::default())
+                .unwrap()
+                .dfa()
+                .state_count()
+                > 8
+        );
+        assert!(matches!(
+            tagged(
+                pattern,
+                &crate::config::Config {
+                    max_dfa_bytes: 1024,
+                    ..crate::config::Config::default()
+                }
+            ),
+            Err(crate::Error::LimitExceeded(crate::Limit::DfaBytes, 1024))
+        ));
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let tdfa = tagged("(a+)(b|c)?", &crate::config::Config::default()).unwrap();
+        let bytes = crate::serialize::leak_aligned(&tdfa.to_bytes());
+        let (loaded, rest) = TaggedDfa::from_bytes_prefix(bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(loaded, tdfa);
+        assert_eq!(loaded.captures("aab"), tdfa.captures("aab"));
+
+        let mut corrupt = tdfa.to_bytes();
+        let registers = tdfa.dfa.to_bytes().len() + 32;
+        corrupt[registers..registers + 8].copy_from_slice(&0u64.to_ne_bytes());
+        assert!(TaggedDfa::from_bytes_prefix(crate::serialize::leak_aligned(&corrupt)).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glushkov {
+    pub positions: Vec<crate::automaton::label::NfaLabel>,
+    pub first: Vec<usize>,
+    pub last: Vec<usize>,
+    pub follow: Vec<Vec<usize>>,
+    pub nullable: bool,
+    transitions: usize,
+    max_transitions: usize,
+}
+
+struct Linear {
+    nullable: bool,
+    first: Vec<usize>,
+    last: Vec<usize>,
+}
+
+impl Glushkov {
+    pub fn new(ast: &crate::parser::AstNode, max_transitions: usize) -> crate::Result<Glushkov> {
+        let mut glushkov = Glushkov {
+            positions: Vec::new(),
+            first: Vec::new(),
+            last: Vec::new(),
+            follow: Vec::new(),
+            nullable: false,
+            transitions: 0,
+            max_transitions,
+        };
+        let mut linear = glushkov.linearize(ast)?;
+        for set in glushkov
+            .follow
+            .iter_mut()
+            .chain([&mut linear.first, &mut linear.last])
+        {
+            set.sort_unstable();
+            set.dedup();
+        }
+        glushkov.first = linear.first;
+        glushkov.last = linear.last;
+        glushkov.nullable = linear.nullable;
+        Ok(glushkov)
+    }
+
+    fn position(&mut self, label: crate::automaton::label::NfaLabel) -> Linear {
+        let position = self.positions.len();
+        self.positions.push(label);
+        self.follow.push(Vec::new());
+        Linear {
+            nullable: false,
+            first: vec![position],
+            last: vec![position],
+        }
+    }
+
+    fn connect(&mut self, last: &[usize], first: &[usize]) -> crate::Result<()> {
+        self.transitions = self
+            .transitions
+            .saturating_add(last.len().saturating_mul(first.len()));
+        crate::config::Limit::NfaTransitions.check(self.transitions, self.max_transitions)?;
+        for &from in last {
+            self.follow[from].extend_from_slice(first);
+        }
+        Ok(())
+    }
+
+    fn linearize(&mut self, node: &crate::parser::AstNode) -> crate::Result<Linear> {
+        Ok(match node {
+            crate::parser::AstNode::Char(c) => {
+                self.position(crate::automaton::label::NfaLabel::Char(*c))
+            }
+            crate::parser::AstNode::Class(class) => {
+                self.position(crate::automaton::label::NfaLabel::Class(*class))
+            }
+            crate::parser::AstNode::Empty => Linear {
+                nullable: false,
+                first: Vec::new(),
+                last: Vec::new(),
+            },
+            crate::parser::AstNode::Epsilon => Linear {
+                nullable: true,
+                first: Vec::new(),
+                last: Vec::new(),
+            },
+            crate::parser::AstNode::Capture(_, node) => self.linearize(node)?,
+            crate::parser::AstNode::Question(node) => Linear {
+                nullable: true,
+                ..self.linearize(node)?
+            },
+            crate::parser::AstNode::Plus(node) => {
+                let inner = self.linearize(node)?;
+                self.connect(&inner.last, &inner.first)?;
+                inner
+            }
+            crate::parser::AstNode::Star(node) => {
+                let inner = self.linearize(node)?;
+                self.connect(&inner.last, &inner.first)?;
+                Linear {
+                    nullable: true,
+                    ..inner
+                }
+            }
+            crate::parser::AstNode::Or(left, right) => {
+                let left = self.linearize(left)?;
+                let right = self.linearize(right)?;
+                Linear {
+                    nullable: left.nullable || right.nullable,
+                    first: [left.first, right.first].concat(),
+                    last: [left.last, right.last].concat(),
+                }
+            }
+            crate::parser::AstNode::Seq(left, right) => {
+                let left = self.linearize(left)?;
+                let right = self.linearize(right)?;
+                self.connect(&left.last, &right.first)?;
+                let first = if left.nullable {
+                    [left.first, right.first.clone()].concat()
+                } else {
+                    left.first
+                };
+                let last = if right.nullable {
+                    [left.last, right.last].concat()
+                } else {
+                    right.last
+                };
+                Linear {
+                    nullable: left.nullable && right.nullable,
+                    first,
+                    last,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automaton::label::NfaLabel;
+
+    fn glushkov(pattern: &str) -> Glushkov {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let ast = crate::parser::Parser::new(&mut lexer).parse().unwrap();
+        Glushkov::new(&ast, usize::MAX).unwrap()
+    }
+
+    #[test]
+    fn positions() {
+        let g = glushkov("(a|b)*abb");
+        assert_eq!(
+            g.positions,
+            vec![
+                NfaLabel::Char('a'),
+                NfaLabel::Char('b'),
+                NfaLabel::Char('a'),
+                NfaLabel::Char('b'),
+                NfaLabel::Char('b')
+            ]
+        );
+        assert_eq!(g.first, vec![0, 1, 2]);
+        assert_eq!(g.last, vec![4]);
+        assert_eq!(
+            g.follow,
+            vec![vec![0, 1, 2], vec![0, 1, 2], vec![3], vec![4], vec![]]
+        );
+        assert!(!g.nullable);
+
+        let g = glushkov("a+(b|)c?");
+        assert_eq!(g.first, vec![0]);
+        assert_eq!(g.last, vec![0, 1, 2]);
+        assert_eq!(g.follow, vec![vec![0, 1, 2], vec![2], vec![]]);
+        assert!(!g.nullable);
+
+        let g = glushkov(r"(\d|x)*");
+        assert_eq!(
+            g.positions[0],
+            NfaLabel::Class(crate::charclass::CharClass::Digit)
+        );
+        assert!(g.nullable);
+    }
+
+    #[test]
+    fn transition_limit() {
+        let mut lexer = crate::lexer::Lexer::new("(a|b|c)*d");
+        let ast = crate::parser::Parser::new(&mut lexer).parse().unwrap();
+        assert_eq!(Glushkov::new(&ast, 12).unwrap().follow.concat().len(), 12);
+        assert!(matches!(
+            Glushkov::new(&ast, 11),
+            Err(crate::Error::LimitExceeded(
+                crate::config::Limit::NfaTransitions,
+                11
+            ))
+        ));
+    }
+}
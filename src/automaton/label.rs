@@ -1,6 +1,5 @@
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum NfaLabel {
-    Epsilon,
     Char(char),
     Class(crate::charclass::CharClass),
 }
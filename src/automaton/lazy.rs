@@ -0,0 +1,541 @@
+use foldhash::HashMapExt as _;
+
+use crate::automaton::dfa::{ACCEL_MIN_REMAINING, Accel, DEAD, DfaStateID};
+
+const UNKNOWN: DfaStateID = DfaStateID::MAX - 1;
+const DEFAULT_CACHE_CAPACITY: usize = 2 * (1 << 20);
+const MIN_CLEARS_BEFORE_GIVING_UP: usize = 3;
+const MIN_BYTES_PER_STATE: usize = 10;
+const STATE_OVERHEAD: usize = 128 * std::mem::size_of::<DfaStateID>()
+    + std::mem::size_of::<Option<Accel>>()
+    + std::mem::size_of::<foldhash::HashMap<char, DfaStateID>>()
+    + 4 * std::mem::size_of::<usize>();
+
+type StateSet = std::sync::Arc<[u32]>;
+
+#[derive(Debug)]
+pub struct LazyCache {
+    states: foldhash::HashMap<StateSet, DfaStateID>,
+    sets: Vec<StateSet>,
+    ascii_table: Vec<DfaStateID>,
+    unicode_table: Vec<foldhash::HashMap<char, DfaStateID>>,
+    accepts: bit_set::BitSet,
+    accels: Vec<Option<Accel>>,
+    start: DfaStateID,
+    memory: usize,
+    scratch: bit_set::BitSet,
+    clears: usize,
+    search_clears: usize,
+    last_clear_at: usize,
+    fallbacks: usize,
+    pike: crate::vm::Cache,
+    reverse: Option<Box<LazyCache>>,
+}
+
+impl LazyCache {
+    fn new(nfa: &crate::automaton::nfa::NfaEdges) -> Self {
+        let mut cache = LazyCache {
+            states: foldhash::HashMap::new(),
+            sets: Vec::new(),
+            ascii_table: Vec::new(),
+            unicode_table: Vec::new(),
+            accepts: bit_set::BitSet::new(),
+            accels: Vec::new(),
+            start: DEAD,
+            memory: 0,
+            scratch: bit_set::BitSet::new(),
+            clears: 0,
+            search_clears: 0,
+            last_clear_at: 0,
+            fallbacks: 0,
+            pike: crate::vm::Cache::new(0),
+            reverse: None,
+        };
+        cache.start = cache.add_state(nfa, nfa.start.clone().into());
+        cache
+    }
+
+    fn add_state(&mut self, nfa: &crate::automaton::nfa::NfaEdges, set: StateSet) -> DfaStateID {
+        let id = self.sets.len() as DfaStateID;
+        if set.iter().any(|&s| nfa.accept.contains(s as usize)) {
+            self.accepts.insert(id as usize);
+        }
+        self.memory += STATE_OVERHEAD + set.len() * std::mem::size_of::<u32>() * 2;
+        self.states.insert(set.clone(), id);
+        self.sets.push(set);
+        self.ascii_table.extend_from_slice(&[UNKNOWN; 128]);
+        self.unicode_table.push(foldhash::HashMap::new());
+        self.accels.push(None);
+        id
+    }
+
+    fn clear(&mut self, nfa: &crate::automaton::nfa::NfaEdges, keep: StateSet) -> DfaStateID {
+        self.states.clear();
+        self.sets.clear();
+        self.ascii_table.clear();
+        self.unicode_table.clear();
+        self.accepts.make_empty();
+        self.accels.clear();
+        self.memory = 0;
+        self.clears += 1;
+        self.search_clears += 1;
+
+        self.start = self.add_state(nfa, nfa.start.clone().into());
+        match self.states.get(&keep) {
+            Some(&id) => id,
+            None => self.add_state(nfa, keep),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LazyDfa {
+    nfa: std::sync::Arc<crate::automaton::nfa::NfaEdges>,
+    reverse: crate::automaton::nfa::NfaEdges,
+    capacity: usize,
+    fallback: crate::vm::Vm,
+    pool: crate::pool::Pool<LazyCache>,
+}
+
+impl LazyDfa {
+    pub fn new(ast: crate::parser::AstNode, config: &crate::config::Config) -> crate::Result<Self> {
+        Self::with_capacity(ast, config, DEFAULT_CACHE_CAPACITY)
+    }
+
+    pub fn with_capacity(
+        ast: crate::parser::AstNode,
+        config: &crate::config::Config,
+        capacity: usize,
+    ) -> crate::Result<Self> {
+        let nfa = crate::automaton::nfa::Nfa::build(ast.clone(), config)?;
+        let nfa = std::sync::Arc::new(crate::automaton::nfa::NfaEdges::new(&nfa));
+        let reverse = crate::automaton::nfa::Nfa::build(ast.reverse(), config)?;
+        let reverse = crate::automaton::nfa::NfaEdges::new(&reverse);
+        let fallback = crate::vm::Vm::new(ast, config)?;
+
+        let template = nfa.clone();
+        Ok(LazyDfa {
+            nfa,
+            reverse,
+            capacity,
+            fallback,
+            pool: crate::pool::Pool::new(move || LazyCache::new(&template)),
+        })
+    }
+
+    // Lower bound on the states a default-sized cache holds for an NFA of `nfa_states` states.
+    pub fn cache_states(nfa_states: usize) -> usize {
+        DEFAULT_CACHE_CAPACITY / (STATE_OVERHEAD + nfa_states * std::mem::size_of::<u32>() * 2)
+    }
+
+    pub fn create_cache(&self) -> LazyCache {
+        LazyCache::new(&self.nfa)
+    }
+
+    pub fn is_match(&self, input: &str) -> bool {
+        self.is_match_with(&mut self.pool.get(), input)
+    }
+
+    pub fn is_match_with(&self, cache: &mut LazyCache, input: &str) -> bool {
+        match self.search(cache, input) {
+            Some(matched) => matched,
+            None => {
+                cache.fallbacks += 1;
+                self.fallback.is_match_with(&mut cache.pike, input)
+            }
+        }
+    }
+
+    pub fn find(&self, input: &str) -> Option<(usize, usize)> {
+        self.find_with(&mut self.pool.get(), input)
+    }
+
+    pub fn find_with(&self, cache: &mut LazyCache, input: &str) -> Option<(usize, usize)> {
+        match self.search_find(cache, input) {
+            Some(found) => found,
+            None => {
+                cache.fallbacks += 1;
+                self.fallback.find_with(&mut cache.pike, input)
+            }
+        }
+    }
+
+    fn search_find(&self, cache: &mut LazyCache, input: &str) -> Option<Option<(usize, usize)>> {
+        let mut reverse = cache
+            .reverse
+            .take()
+            .unwrap_or_else(|| Box::new(LazyCache::new(&self.reverse)));
+        let start = self.rfind_start(&mut reverse, input);
+        cache.reverse = Some(reverse);
+        let Some(start) = start? else {
+            return Some(None);
+        };
+        let end = self.longest_match_end(cache, input, start)?;
+        Some(end.map(|end| (start, end)))
+    }
+
+    fn rfind_start(&self, cache: &mut LazyCache, input: &str) -> Option<Option<usize>> {
+        cache.search_clears = 0;
+        cache.last_clear_at = 0;
+        let mut state = cache.start;
+        let mut found = cache
+            .accepts
+            .contains(state as usize)
+            .then_some(input.len());
+
+        for (at, c) in input.char_indices().rev() {
+            state = match self.next(cache, &mut state, c, input.len() - at, true)? {
+                DEAD => cache.start,
+                next => next,
+            };
+            if cache.accepts.contains(state as usize) {
+                found = Some(at);
+            }
+        }
+
+        Some(found)
+    }
+
+    fn longest_match_end(
+        &self,
+        cache: &mut LazyCache,
+        input: &str,
+        start: usize,
+    ) -> Option<Option<usize>> {
+        cache.search_clears = 0;
+        cache.last_clear_at = 0;
+        let mut state = cache.start;
+        let mut end = cache.accepts.contains(state as usize).then_some(start);
+
+        for (at, c) in input[start..].char_indices() {
+            state = match self.next(cache, &mut state, c, at, false)? {
+                DEAD => break,
+                next => next,
+            };
+            if cache.accepts.contains(state as usize) {
+                end = Some(start + at + c.len_utf8());
+            }
+        }
+
+        Some(end)
+    }
+
+    fn search(&self, cache: &mut LazyCache, input: &str) -> Option<bool> {
+        cache.search_clears = 0;
+        cache.last_clear_at = 0;
+        let mut state = cache.start;
+
+        if input.is_ascii() {
+            let bytes = input.as_bytes();
+            let len = bytes.len();
+            let mut at = 0usize;
+
+            while at < len {
+                if len - at >= ACCEL_MIN_REMAINING {
+                    let accel = self.accel(cache, &mut state, at)?;
+                    if accel.is_enabled() {
+                        if let Some((class, next_state)) = accel.class_loop {
+                            let start = at;
+                            while at < len && class.matches(bytes[at] as char) {
+                                at += 1;
+                            }
+                            state = next_state;
+                            if at >= len {
+                                break;
+                            }
+                            if at > start {
+                                continue;
+                            }
+                        } else if let Some(loop_byte) = accel.loop_byte {
+                            let start = at;
+                            while at < len && bytes[at] == loop_byte {
+                                at += 1;
+                            }
+                            if at >= len {
+                                break;
+                            }
+                            if at > start {
+                                continue;
+                            }
+                        } else if let Some(hit) = accel.memchr_fwd(bytes, at) {
+                            at = hit;
+                        }
+                    }
+                }
+
+                let byte = bytes[at];
+                let mut next = cache.ascii_table[state as usize * 128 + byte as usize];
+                if next == UNKNOWN {
+                    next = self.transition(cache, &mut state, byte as char, at, false)?;
+                }
+                if next == DEAD {
+                    return Some(false);
+                }
+                state = next;
+                at += 1;
+            }
+        } else {
+            for (at, c) in input.char_indices() {
+                let next = self.next(cache, &mut state, c, at, false)?;
+                if next == DEAD {
+                    return Some(false);
+                }
+                state = next;
+            }
+        }
+
+        Some(cache.accepts.contains(state as usize))
+    }
+
+    fn next(
+        &self,
+        cache: &mut LazyCache,
+        state: &mut DfaStateID,
+        c: char,
+        at: usize,
+        reverse: bool,
+    ) -> Option<DfaStateID> {
+        let next = if c.is_ascii() {
+            cache.ascii_table[*state as usize * 128 + c as usize]
+        } else {
+            cache.unicode_table[*state as usize]
+                .get(&c)
+                .copied()
+                .unwrap_or(UNKNOWN)
+        };
+        if next != UNKNOWN {
+            return Some(next);
+        }
+        self.transition(cache, state, c, at, reverse)
+    }
+
+    fn transition(
+        &self,
+        cache: &mut LazyCache,
+        state: &mut DfaStateID,
+        c: char,
+        at: usize,
+        reverse: bool,
+    ) -> Option<DfaStateID> {
+        let (nfa, base) = if reverse {
+            (&self.reverse, self.reverse.start.as_slice())
+        } else {
+            (&*self.nfa, &[][..])
+        };
+        let mut scratch = std::mem::take(&mut cache.scratch);
+        let mut next_set = nfa.step(&cache.sets[*state as usize], c, &mut scratch);
+        cache.scratch = scratch;
+        if !base.is_empty() {
+            next_set.extend_from_slice(base);
+            next_set.sort_unstable();
+            next_set.dedup();
+        }
+
+        let next = if next_set.is_empty() {
+            DEAD
+        } else if let Some(&id) = cache.states.get(next_set.as_slice()) {
+            id
+        } else {
+            let cost = STATE_OVERHEAD + next_set.len() * std::mem::size_of::<u32>() * 2;
+            if cache.memory + cost > self.capacity {
+                if cache.search_clears + 1 >= MIN_CLEARS_BEFORE_GIVING_UP
+                    && at - cache.last_clear_at < MIN_BYTES_PER_STATE * cache.sets.len()
+                {
+                    return None;
+                }
+                let keep = cache.sets[*state as usize].clone();
+                *state = cache.clear(nfa, keep);
+                cache.last_clear_at = at;
+            }
+            match cache.states.get(next_set.as_slice()) {
+                Some(&id) => id,
+                None => cache.add_state(nfa, next_set.into()),
+            }
+        };
+
+        if c.is_ascii() {
+            cache.ascii_table[*state as usize * 128 + c as usize] = next;
+        } else {
+            cache.memory += 2 * std::mem::size_of::<(char, DfaStateID)>();
+            cache.unicode_table[*state as usize].insert(c, next);
+        }
+        Some(next)
+    }
+
+    fn accel(&self, cache: &mut LazyCache, state: &mut DfaStateID, at: usize) -> Option<Accel> {
+        if let Some(accel) = cache.accels[*state as usize] {
+            return Some(accel);
+        }
+
+        let expanding = *state;
+        for byte in 0u8..128 {
+            if cache.ascii_table[*state as usize * 128 + byte as usize] == UNKNOWN {
+                self.transition(cache, state, byte as char, at, false)?;
+            }
+        }
+
+        if *state != expanding {
+            return Some(Accel::default());
+        }
+        let accel = crate::automaton::dfa::build_accel(
+            *state as usize,
+            &cache.ascii_table[*state as usize * 128..(*state as usize + 1) * 128],
+        );
+        cache.accels[*state as usize] = Some(accel);
+        Some(accel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pattern: &str) -> crate::parser::AstNode {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let mut parser = crate::parser::Parser::new(&mut lexer);
+        parser.parse().unwrap()
+    }
+
+    fn random_ab(len: usize) -> String {
+        let mut seed = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                if seed & 1 == 0 { 'a' } else { 'b' }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_like_dfa() {
+        let lazy = LazyDfa::new(
+            parse("(p(erl|ython|hp)|ruby)"),
+            &crate::config::Config::default(),
+        )
+        .unwrap();
+        assert!(lazy.is_match("perl"));
+        assert!(lazy.is_match("python"));
+        assert!(!lazy.is_match("rust"));
+        assert!(!lazy.is_match(""));
+
+        let lazy = LazyDfa::new(
+            parse("正規表現(太郎|次郎)."),
+            &crate::config::Config::default(),
+        )
+        .unwrap();
+        assert!(lazy.is_match("正規表現太郎a"));
+        assert!(lazy.is_match("正規表現次郎♥"));
+        assert!(!lazy.is_match("正規表現三郎a"));
+
+        let lazy = LazyDfa::new(parse(r"\d+"), &crate::config::Config::default()).unwrap();
+        assert!(lazy.is_match(&"0123456789".repeat(100)));
+        assert!(!lazy.is_match(&format!("{}a", "0123456789".repeat(100))));
+    }
+
+    #[test]
+    fn states_are_built_on_demand() {
+        let pattern = format!("(a|b)*a{}", "(a|b)".repeat(20));
+        let lazy = LazyDfa::new(parse(&pattern), &crate::config::Config::default()).unwrap();
+        let input = format!("{}a{}", "ab".repeat(50), "b".repeat(20));
+        assert!(lazy.is_match(&input));
+        assert!(!lazy.is_match(&format!("{}b{}", "ab".repeat(50), "b".repeat(20))));
+
+        let cache = lazy.pool.get();
+        assert!(cache.sets.len() <= 2 * input.len());
+        assert_eq!(cache.fallbacks, 0);
+    }
+
+    #[test]
+    fn cache_is_cleared_when_full() {
+        let pattern = format!("(a|b)*a{}", "(a|b)".repeat(3));
+        let lazy = LazyDfa::with_capacity(
+            parse(&pattern),
+            &crate::config::Config::default(),
+            12 * STATE_OVERHEAD,
+        )
+        .unwrap();
+        let input = format!(
+            "{}abbb",
+            format!("{}{}", "a".repeat(200), "b".repeat(200)).repeat(10)
+        );
+        assert!(lazy.is_match(&input));
+        assert!(!lazy.is_match(&input[..input.len() - 4]));
+
+        let cache = lazy.pool.get();
+        assert!(cache.clears > 0);
+        assert_eq!(cache.fallbacks, 0);
+        assert!(cache.memory <= 12 * STATE_OVERHEAD);
+    }
+
+    #[test]
+    fn falls_back_to_vm_when_thrashing() {
+        let pattern = format!("(a|b)*a{}", "(a|b)".repeat(12));
+        let lazy = LazyDfa::with_capacity(
+            parse(&pattern),
+            &crate::config::Config::default(),
+            4 * STATE_OVERHEAD,
+        )
+        .unwrap();
+        let input = random_ab(500);
+        let expected = input.as_bytes()[input.len() - 13] == b'a';
+        assert_eq!(lazy.is_match(&input), expected);
+        assert_eq!(lazy.pool.get().fallbacks, 1);
+    }
+
+    #[test]
+    fn find_fills_the_cache() {
+        for (pattern, haystack) in [
+            (r"\d+", "ab 123 45"),
+            ("(a|ab)(c|bcd)(d*)", "xxabcdd"),
+            ("正規.", "a正規\n正規表現"),
+            ("b*", "bba"),
+            ("x+", "aaxxa"),
+        ] {
+            let ast = parse(pattern);
+            let lazy = LazyDfa::new(ast.clone(), &crate::config::Config::default()).unwrap();
+            let vm = crate::vm::Vm::new(ast, &crate::config::Config::default()).unwrap();
+            let mut cache = lazy.create_cache();
+            assert_eq!(
+                lazy.find_with(&mut cache, haystack),
+                vm.find(haystack),
+                "{pattern}"
+            );
+            assert!(cache.sets.len() > 1, "{pattern}");
+            assert!(cache.reverse.as_ref().unwrap().sets.len() > 1, "{pattern}");
+            assert_eq!(cache.fallbacks, 0);
+        }
+        let lazy = LazyDfa::new(parse("x+"), &crate::config::Config::default()).unwrap();
+        assert_eq!(lazy.find("aaa"), None);
+
+        let pattern = format!("(a|b)*a{}", "(a|b)".repeat(12));
+        let lazy = LazyDfa::with_capacity(
+            parse(&pattern),
+            &crate::config::Config::default(),
+            4 * STATE_OVERHEAD,
+        )
+        .unwrap();
+        let input = random_ab(500);
+        let vm = crate::vm::Vm::new(parse(&pattern), &crate::config::Config::default()).unwrap();
+        let mut cache = lazy.create_cache();
+        assert_eq!(lazy.find_with(&mut cache, &input), vm.find(&input));
+        assert_eq!(cache.fallbacks, 1);
+    }
+
+    #[test]
+    fn accel_carries_over() {
+        let lazy = LazyDfa::new(parse("a+b"), &crate::config::Config::default()).unwrap();
+        assert!(lazy.is_match(&format!("{}b", "a".repeat(1000))));
+        assert!(!lazy.is_match(&"a".repeat(1000)));
+
+        let cache = lazy.pool.get();
+        assert!(
+            cache
+                .accels
+                .iter()
+                .flatten()
+                .any(|accel| accel.loop_byte == Some(b'a'))
+        );
+    }
+}
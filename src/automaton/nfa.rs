@@ -1,19 +1,72 @@
-pub type NfaStateID = u64;
+pub type NfaStateID = u32;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NfaNode {
+    pub transitions: Vec<(crate::automaton::label::NfaLabel, NfaStateID)>,
+    pub epsilons: Vec<NfaStateID>,
+    pub tag: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
 pub struct NfaState {
-    id: NfaStateID,
+    nodes: Vec<NfaNode>,
+    limit: usize,
+    transitions: usize,
+    max_transitions: usize,
+    tagged: bool,
 }
 
 impl NfaState {
+    #[cfg(test)]
     pub fn new() -> Self {
-        NfaState { id: 0 }
+        NfaState::with_limits(usize::MAX, usize::MAX)
+    }
+
+    pub fn with_limits(limit: usize, max_transitions: usize) -> Self {
+        NfaState {
+            nodes: Vec::new(),
+            limit,
+            transitions: 0,
+            max_transitions,
+            tagged: false,
+        }
+    }
+
+    fn new_state(&mut self) -> crate::Result<NfaStateID> {
+        let id = self.nodes.len();
+        crate::config::Limit::NfaStates.check(id + 1, self.limit)?;
+        let id = NfaStateID::try_from(id).map_err(|_| crate::Error::StateIDOverflow(id))?;
+        self.nodes.push(NfaNode::default());
+        Ok(id)
     }
 
-    fn new_state(&mut self) -> NfaStateID {
-        let id = self.id;
-        self.id += 1;
-        id
+    fn new_tag(&mut self, slot: usize) -> crate::Result<NfaStateID> {
+        let id = self.new_state()?;
+        self.nodes[id as usize].tag = Some(slot as u32);
+        Ok(id)
+    }
+
+    fn add_transition(
+        &mut self,
+        from: NfaStateID,
+        label: crate::automaton::label::NfaLabel,
+        to: NfaStateID,
+    ) -> crate::Result<()> {
+        self.transitions += 1;
+        crate::config::Limit::NfaTransitions.check(self.transitions, self.max_transitions)?;
+        self.nodes[from as usize].transitions.push((label, to));
+        Ok(())
+    }
+
+    fn add_epsilon_transition(&mut self, from: NfaStateID, to: NfaStateID) {
+        let epsilons = &mut self.nodes[from as usize].epsilons;
+        if !epsilons.contains(&to) {
+            epsilons.push(to);
+        }
+    }
+
+    fn finish(&mut self, start: NfaStateID, accept: Vec<NfaStateID>) -> Nfa {
+        Nfa::new(start, accept, std::mem::take(&mut self.nodes))
     }
 }
 
@@ -21,17 +74,47 @@ impl NfaState {
 pub struct Nfa {
     start: NfaStateID,
     accept: std::collections::HashSet<NfaStateID>,
-    transitions:
-        std::collections::HashSet<(NfaStateID, crate::automaton::label::NfaLabel, NfaStateID)>,
+    nodes: Vec<NfaNode>,
+    closures: Vec<Vec<NfaStateID>>,
 }
 
 impl Nfa {
-    pub fn new(start: NfaStateID, accept: Vec<NfaStateID>) -> Self {
-        Nfa {
+    fn new(start: NfaStateID, accept: Vec<NfaStateID>, nodes: Vec<NfaNode>) -> Self {
+        let mut nfa = Nfa {
             start,
             accept: accept.into_iter().collect(),
-            transitions: std::collections::HashSet::new(),
+            closures: vec![Vec::new(); nodes.len()],
+            nodes,
+        };
+
+        let mut seen = vec![NfaStateID::MAX; nfa.nodes.len()];
+        let mut stack = Vec::new();
+        let targets = std::iter::once(start).chain(
+            nfa.nodes
+                .iter()
+                .flat_map(|node| node.transitions.iter().map(|&(_, to)| to)),
+        );
+        for target in targets.collect::<Vec<_>>() {
+            if !nfa.closures[target as usize].is_empty() {
+                continue;
+            }
+            let mut closure = Vec::new();
+            seen[target as usize] = target;
+            stack.push(target);
+            while let Some(state) = stack.pop() {
+                closure.push(state);
+                for &to in &nfa.nodes[state as usize].epsilons {
+                    if seen[to as usize] != target {
+                        seen[to as usize] = target;
+                        stack.push(to);
+                    }
+                }
+            }
+            closure.sort_unstable();
+            nfa.closures[target as usize] = closure;
         }
+
+        nfa
     }
 
     pub fn start(&self) -> NfaStateID {
@@ -42,187 +125,307 @@ impl Nfa {
         &self.accept
     }
 
-    pub fn transitions(
+    pub fn state_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn node(&self, state: NfaStateID) -> &NfaNode {
+        &self.nodes[state as usize]
+    }
+
+    pub fn closure(&self, state: NfaStateID) -> &[NfaStateID] {
+        debug_assert!(!self.closures[state as usize].is_empty());
+        &self.closures[state as usize]
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = crate::automaton::label::NfaLabel> + '_ {
+        self.nodes
+            .iter()
+            .flat_map(|node| node.transitions.iter().map(|&(label, _)| label))
+    }
+
+    pub fn size(&self, construction: crate::config::NfaConstruction) -> crate::stats::NfaSize {
+        let epsilon_transitions = self.nodes.iter().map(|node| node.epsilons.len()).sum();
+        crate::stats::NfaSize {
+            construction,
+            states: self.state_count(),
+            transitions: self.labels().count() + epsilon_transitions,
+            epsilon_transitions,
+        }
+    }
+
+    #[cfg(test)]
+    fn edges(
         &self,
-    ) -> &std::collections::HashSet<(NfaStateID, crate::automaton::label::NfaLabel, NfaStateID)>
-    {
-        &self.transitions
+    ) -> std::collections::HashSet<(
+        NfaStateID,
+        Option<crate::automaton::label::NfaLabel>,
+        NfaStateID,
+    )> {
+        let mut edges = std::collections::HashSet::new();
+        for (from, node) in self.nodes.iter().enumerate() {
+            let from = from as NfaStateID;
+            for &(label, to) in &node.transitions {
+                edges.insert((from, Some(label), to));
+            }
+            for &to in &node.epsilons {
+                edges.insert((from, None, to));
+            }
+        }
+        edges
     }
 
-    fn add_transition(&mut self, from: NfaStateID, char: char, to: NfaStateID) {
-        self.transitions
-            .insert((from, crate::automaton::label::NfaLabel::Char(char), to));
+    pub fn build(
+        node: crate::parser::AstNode,
+        config: &crate::config::Config,
+    ) -> crate::Result<Nfa> {
+        let state = &mut NfaState::with_limits(config.max_nfa_states, config.max_nfa_transitions);
+        match config.nfa_construction {
+            crate::config::NfaConstruction::Thompson => Nfa::new_from_node(node, state),
+            crate::config::NfaConstruction::Antimirov => {
+                Nfa::from_partial_derivatives(&node, state)
+            }
+            crate::config::NfaConstruction::Glushkov => Nfa::from_positions(
+                &crate::automaton::glushkov::Glushkov::new(&node, config.max_nfa_transitions)?,
+                state,
+            ),
+        }
     }
 
-    fn add_class_transition(
-        &mut self,
-        from: NfaStateID,
-        class: crate::charclass::CharClass,
-        to: NfaStateID,
-    ) {
-        self.transitions
-            .insert((from, crate::automaton::label::NfaLabel::Class(class), to));
+    pub fn tagged(
+        node: crate::parser::AstNode,
+        config: &crate::config::Config,
+    ) -> crate::Result<Nfa> {
+        let state = &mut NfaState {
+            tagged: true,
+            ..NfaState::with_limits(config.max_nfa_states, config.max_nfa_transitions)
+        };
+        Nfa::new_from_node(node, state)
     }
 
-    fn add_epsilon_transition(&mut self, from: NfaStateID, to: NfaStateID) {
-        self.transitions
-            .insert((from, crate::automaton::label::NfaLabel::Epsilon, to));
+    pub fn from_positions(
+        glushkov: &crate::automaton::glushkov::Glushkov,
+        state: &mut NfaState,
+    ) -> crate::Result<Nfa> {
+        let start = state.new_state()?;
+        let ids = glushkov
+            .positions
+            .iter()
+            .map(|_| state.new_state())
+            .collect::<crate::Result<Vec<_>>>()?;
+        let mut accept: Vec<_> = glushkov.last.iter().map(|&last| ids[last]).collect();
+        if glushkov.nullable {
+            accept.push(start);
+        }
+        for &first in &glushkov.first {
+            state.add_transition(start, glushkov.positions[first], ids[first])?;
+        }
+        for (from, follow) in glushkov.follow.iter().enumerate() {
+            for &to in follow {
+                state.add_transition(ids[from], glushkov.positions[to], ids[to])?;
+            }
+        }
+
+        Ok(state.finish(start, accept))
     }
 
-    fn merge_nfa(&mut self, other: &Nfa) {
-        self.transitions.extend(other.transitions.clone());
-        self.add_epsilon_transition(self.start, other.start);
-        for accept in other.accept.iter() {
-            self.accept.insert(*accept);
+    pub fn from_partial_derivatives(
+        node: &crate::parser::AstNode,
+        state: &mut NfaState,
+    ) -> crate::Result<Nfa> {
+        let (accepts, transitions) =
+            crate::derivative::partial_derivatives(node, state.limit, state.max_transitions)?;
+        let ids = accepts
+            .iter()
+            .map(|_| state.new_state())
+            .collect::<crate::Result<Vec<_>>>()?;
+        let accept = ids
+            .iter()
+            .zip(&accepts)
+            .filter(|&(_, &accepting)| accepting)
+            .map(|(&id, _)| id)
+            .collect();
+        for (from, label, to) in transitions {
+            state.add_transition(ids[from], label, ids[to])?;
         }
+
+        Ok(state.finish(ids[0], accept))
     }
 
     pub fn new_from_node(node: crate::parser::AstNode, state: &mut NfaState) -> crate::Result<Nfa> {
+        let (start, accept) = Nfa::fragment(node, state)?;
+        Ok(state.finish(start, accept))
+    }
+
+    fn fragment(
+        node: crate::parser::AstNode,
+        state: &mut NfaState,
+    ) -> crate::Result<(NfaStateID, Vec<NfaStateID>)> {
         match node {
             crate::parser::AstNode::Char(c) => {
-                let start = state.new_state();
-                let accept = state.new_state();
-                let mut nfa = Nfa::new(start, vec![accept]);
-                nfa.add_transition(start, c, accept);
+                let start = state.new_state()?;
+                let accept = state.new_state()?;
+                state.add_transition(start, crate::automaton::label::NfaLabel::Char(c), accept)?;
 
-                Ok(nfa)
+                Ok((start, vec![accept]))
             }
             crate::parser::AstNode::Class(class) => {
-                let start = state.new_state();
-                let accept = state.new_state();
-                let mut nfa = Nfa::new(start, vec![accept]);
-                nfa.add_class_transition(start, class, accept);
-
-                Ok(nfa)
+                let start = state.new_state()?;
+                let accept = state.new_state()?;
+                state.add_transition(
+                    start,
+                    crate::automaton::label::NfaLabel::Class(class),
+                    accept,
+                )?;
+
+                Ok((start, vec![accept]))
             }
             crate::parser::AstNode::Epsilon => {
-                let start = state.new_state();
-                let accept = state.new_state();
-                let mut nfa = Nfa::new(start, vec![accept]);
-                nfa.add_epsilon_transition(start, accept);
+                let start = state.new_state()?;
+                let accept = state.new_state()?;
+                state.add_epsilon_transition(start, accept);
 
-                Ok(nfa)
+                Ok((start, vec![accept]))
             }
             crate::parser::AstNode::Plus(boxed) => {
-                let remain = Nfa::new_from_node(*boxed, state)?;
-                let start = state.new_state();
-                let accept = state.new_state();
-                let mut nfa = Nfa::new(start, vec![accept]);
-
-                nfa.transitions.extend(remain.transitions.clone());
-                nfa.add_epsilon_transition(start, remain.start);
-                nfa.add_epsilon_transition(start, accept);
-                for accept_state in remain.accept.iter() {
-                    nfa.add_epsilon_transition(*accept_state, remain.start);
-                    nfa.add_epsilon_transition(*accept_state, accept);
+                let (remain, remain_accept) = Nfa::fragment(*boxed, state)?;
+                let start = state.new_state()?;
+                let accept = state.new_state()?;
+
+                state.add_epsilon_transition(start, remain);
+                for &accept_state in &remain_accept {
+                    state.add_epsilon_transition(accept_state, remain);
+                    state.add_epsilon_transition(accept_state, accept);
                 }
 
-                Ok(nfa)
+                Ok((start, vec![accept]))
             }
             crate::parser::AstNode::Star(boxed) => {
-                let remain = Nfa::new_from_node(*boxed, state)?;
-                let start = state.new_state();
-                let mut accepts = remain.accept.clone();
-                accepts.insert(start);
-
-                let mut nfa = Nfa::new(start, accepts.into_iter().collect());
-                nfa.merge_nfa(&remain);
-                nfa.add_epsilon_transition(start, remain.start);
-
-                for accept in &remain.accept {
-                    nfa.add_epsilon_transition(*accept, remain.start);
+                let (remain, mut accept) = Nfa::fragment(*boxed, state)?;
+                let start = state.new_state()?;
+
+                state.add_epsilon_transition(start, remain);
+                if state.tagged {
+                    // Loop back through the start so that an empty iteration cannot leave
+                    // the loop with its tags set, as in the Pike VM.
+                    for &accept_state in &accept {
+                        state.add_epsilon_transition(accept_state, start);
+                    }
+                    return Ok((start, vec![start]));
+                }
+                for &accept_state in &accept {
+                    state.add_epsilon_transition(accept_state, remain);
                 }
+                accept.push(start);
 
-                Ok(nfa)
+                Ok((start, accept))
             }
             crate::parser::AstNode::Question(boxed) => {
-                let remain = Nfa::new_from_node(*boxed, state)?;
-                let start = state.new_state();
-                let accept = remain
-                    .accept
-                    .union(&[start].into_iter().collect())
-                    .cloned()
-                    .collect();
-                let mut nfa = Nfa::new(start, accept);
-                nfa.merge_nfa(&remain);
-                nfa.add_epsilon_transition(start, remain.start);
-
-                for accept in &remain.accept {
-                    nfa.add_epsilon_transition(*accept, remain.start);
-                }
+                let (remain, mut accept) = Nfa::fragment(*boxed, state)?;
+                let start = state.new_state()?;
+
+                state.add_epsilon_transition(start, remain);
+                accept.push(start);
 
-                Ok(nfa)
+                Ok((start, accept))
             }
             crate::parser::AstNode::Or(boxed1, boxed2) => {
-                let remain1 = Nfa::new_from_node(*boxed1, state)?;
-                let remain2 = Nfa::new_from_node(*boxed2, state)?;
-                let start = state.new_state();
-
-                let accept: std::collections::HashSet<NfaStateID> =
-                    remain1.accept.union(&remain2.accept).cloned().collect();
-                let mut nfa = Nfa::new(start, accept.into_iter().collect());
-                nfa.merge_nfa(&remain1);
-                nfa.merge_nfa(&remain2);
-                nfa.add_epsilon_transition(start, remain1.start);
-                nfa.add_epsilon_transition(start, remain2.start);
-
-                Ok(nfa)
-            }
-            crate::parser::AstNode::Seq(left, right) => {
-                let left_nfa = Nfa::new_from_node(*left, state)?;
-                let right_nfa = Nfa::new_from_node(*right, state)?;
-
-                let mut nfa = Nfa::new(left_nfa.start, right_nfa.accept.iter().copied().collect());
-                nfa.transitions.extend(left_nfa.transitions);
-                nfa.transitions.extend(right_nfa.transitions);
-                for &accept in &left_nfa.accept {
-                    nfa.add_epsilon_transition(accept, right_nfa.start);
-                }
+                let (remain1, mut accept) = Nfa::fragment(*boxed1, state)?;
+                let (remain2, accept2) = Nfa::fragment(*boxed2, state)?;
+                let start = state.new_state()?;
 
-                Ok(nfa)
+                state.add_epsilon_transition(start, remain1);
+                state.add_epsilon_transition(start, remain2);
+                accept.extend(accept2);
+
+                Ok((start, accept))
             }
-            crate::parser::AstNode::Empty => unreachable!(),
-        }
-    }
+            crate::parser::AstNode::Seq(left, right) => {
+                let (left, left_accept) = Nfa::fragment(*left, state)?;
+                let (right, right_accept) = Nfa::fragment(*right, state)?;
 
-    pub fn epsilon_closure_with_bitset(&self, start: &bit_set::BitSet) -> bit_set::BitSet {
-        let mut visited = bit_set::BitSet::new();
-        let mut to_visit = std::collections::VecDeque::new();
+                for &accept in &left_accept {
+                    state.add_epsilon_transition(accept, right);
+                }
 
-        for state in start.iter() {
-            if !visited.contains(state) {
-                to_visit.push_back(state as NfaStateID);
+                Ok((left, right_accept))
             }
-        }
-
-        while let Some(state) = to_visit.pop_front() {
-            if !visited.contains(state as usize) {
-                visited.insert(state as usize);
-                for &(from, label, to) in self.transitions() {
-                    if from == state
-                        && label == crate::automaton::label::NfaLabel::Epsilon
-                        && !visited.contains(to as usize)
-                    {
-                        to_visit.push_back(to);
-                    }
+            crate::parser::AstNode::Capture(index, node) if state.tagged => {
+                let open = state.new_tag(2 * index)?;
+                let (remain, accept) = Nfa::fragment(*node, state)?;
+                let close = state.new_tag(2 * index + 1)?;
+
+                state.add_epsilon_transition(open, remain);
+                for &accept_state in &accept {
+                    state.add_epsilon_transition(accept_state, close);
                 }
+
+                Ok((open, vec![close]))
             }
+            crate::parser::AstNode::Capture(_, node) => Nfa::fragment(*node, state),
+            crate::parser::AstNode::Empty => unreachable!(),
         }
-
-        visited
     }
 
+    #[cfg(test)]
     pub fn epsilon_closure(
         &self,
         start: std::collections::BTreeSet<NfaStateID>,
     ) -> std::collections::BTreeSet<NfaStateID> {
-        let mut bit_start = bit_set::BitSet::new();
-        for &state in &start {
-            bit_start.insert(state as usize);
+        let mut visited = std::collections::BTreeSet::new();
+        let mut stack: Vec<_> = start.into_iter().collect();
+        while let Some(state) = stack.pop() {
+            if visited.insert(state) {
+                stack.extend(&self.nodes[state as usize].epsilons);
+            }
+        }
+        visited
+    }
+}
+
+#[derive(Debug)]
+pub struct NfaEdges {
+    pub start: Vec<u32>,
+    pub accept: bit_set::BitSet,
+    pub edges: Vec<Vec<(crate::automaton::label::NfaLabel, Vec<u32>)>>,
+}
+
+impl NfaEdges {
+    pub fn new(nfa: &Nfa) -> Self {
+        let edges = nfa
+            .nodes
+            .iter()
+            .map(|node| {
+                node.transitions
+                    .iter()
+                    .map(|&(label, to)| (label, nfa.closure(to).to_vec()))
+                    .collect()
+            })
+            .collect();
+
+        NfaEdges {
+            start: nfa.closure(nfa.start()).to_vec(),
+            accept: nfa.accept().iter().map(|&s| s as usize).collect(),
+            edges,
         }
+    }
 
-        let bit_result = self.epsilon_closure_with_bitset(&bit_start);
-        bit_result.iter().map(|s| s as NfaStateID).collect()
+    pub fn step(&self, set: &[u32], c: char, scratch: &mut bit_set::BitSet) -> Vec<u32> {
+        scratch.make_empty();
+        for &state in set {
+            for (label, closure) in &self.edges[state as usize] {
+                let matched = match label {
+                    crate::automaton::label::NfaLabel::Char(expected) => *expected == c,
+                    crate::automaton::label::NfaLabel::Class(class) => class.matches(c),
+                };
+                if matched {
+                    for &to in closure {
+                        scratch.insert(to as usize);
+                    }
+                }
+            }
+        }
+        scratch.iter().map(|s| s as u32).collect()
     }
 }
 
@@ -238,9 +441,12 @@ mod tests {
             &mut NfaState::new(),
         )
         .unwrap();
-        assert_eq!(nfa.transitions.len(), 1);
-        let (_, label, _) = nfa.transitions.iter().next().unwrap();
-        assert_eq!(*label, NfaLabel::Class(crate::charclass::CharClass::Digit));
+        assert_eq!(nfa.edges().len(), 1);
+        let (_, label, _) = nfa.edges().into_iter().next().unwrap();
+        assert_eq!(
+            label,
+            Some(NfaLabel::Class(crate::charclass::CharClass::Digit))
+        );
     }
 
     #[test]
@@ -251,8 +457,10 @@ mod tests {
         assert_eq!(nfa.start, 0);
         assert_eq!(nfa.accept, [1].into());
         assert_eq!(
-            nfa.transitions,
-            vec![(0, NfaLabel::Char('a'), 1)].into_iter().collect()
+            nfa.edges(),
+            vec![(0, Some(NfaLabel::Char('a')), 1)]
+                .into_iter()
+                .collect()
         );
 
         // [empty]
@@ -260,10 +468,7 @@ mod tests {
             Nfa::new_from_node(crate::parser::AstNode::Epsilon, &mut NfaState::new()).unwrap();
         assert_eq!(nfa.start, 0);
         assert_eq!(nfa.accept, [1].into());
-        assert_eq!(
-            nfa.transitions,
-            vec![(0, NfaLabel::Epsilon, 1)].into_iter().collect()
-        );
+        assert_eq!(nfa.edges(), vec![(0, None, 1)].into_iter().collect());
 
         // a*
         let nfa = Nfa::new_from_node(
@@ -274,11 +479,11 @@ mod tests {
         assert_eq!(nfa.start, 2);
         assert_eq!(nfa.accept, [1, 2].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (0, NfaLabel::Char('a'), 1),
-                (2, NfaLabel::Epsilon, 0),
-                (1, NfaLabel::Epsilon, 0)
+                (0, Some(NfaLabel::Char('a')), 1),
+                (2, None, 0),
+                (1, None, 0)
             ]
             .into_iter()
             .collect()
@@ -296,12 +501,12 @@ mod tests {
         assert_eq!(nfa.start, 4);
         assert_eq!(nfa.accept, [1, 3].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (0, NfaLabel::Char('a'), 1),
-                (2, NfaLabel::Char('b'), 3),
-                (4, NfaLabel::Epsilon, 0),
-                (4, NfaLabel::Epsilon, 2)
+                (0, Some(NfaLabel::Char('a')), 1),
+                (2, Some(NfaLabel::Char('b')), 3),
+                (4, None, 0),
+                (4, None, 2)
             ]
             .into_iter()
             .collect()
@@ -316,14 +521,10 @@ mod tests {
         assert_eq!(nfa.start, 2);
         assert_eq!(nfa.accept, [1, 2].into());
         assert_eq!(
-            nfa.transitions,
-            vec![
-                (1, NfaLabel::Epsilon, 0),
-                (0, NfaLabel::Char('a'), 1),
-                (2, NfaLabel::Epsilon, 0)
-            ]
-            .into_iter()
-            .collect()
+            nfa.edges(),
+            vec![(0, Some(NfaLabel::Char('a')), 1), (2, None, 0)]
+                .into_iter()
+                .collect()
         );
 
         // a+
@@ -335,13 +536,12 @@ mod tests {
         assert_eq!(nfa.start, 2);
         assert_eq!(nfa.accept, [3].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (1, NfaLabel::Epsilon, 3),
-                (0, NfaLabel::Char('a'), 1),
-                (2, NfaLabel::Epsilon, 3),
-                (1, NfaLabel::Epsilon, 0),
-                (2, NfaLabel::Epsilon, 0)
+                (1, None, 3),
+                (0, Some(NfaLabel::Char('a')), 1),
+                (1, None, 0),
+                (2, None, 0)
             ]
             .into_iter()
             .collect()
@@ -359,11 +559,11 @@ mod tests {
         assert_eq!(nfa.start, 0);
         assert_eq!(nfa.accept, [3].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (0, NfaLabel::Char('a'), 1),
-                (2, NfaLabel::Char('b'), 3),
-                (1, NfaLabel::Epsilon, 2)
+                (0, Some(NfaLabel::Char('a')), 1),
+                (2, Some(NfaLabel::Char('b')), 3),
+                (1, None, 2)
             ]
             .into_iter()
             .collect()
@@ -378,12 +578,12 @@ mod tests {
         assert_eq!(nfa.start, 4);
         assert_eq!(nfa.accept, [1, 3].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (0, NfaLabel::Char('a'), 1),
-                (2, NfaLabel::Char('b'), 3),
-                (4, NfaLabel::Epsilon, 0),
-                (4, NfaLabel::Epsilon, 2)
+                (0, Some(NfaLabel::Char('a')), 1),
+                (2, Some(NfaLabel::Char('b')), 3),
+                (4, None, 0),
+                (4, None, 2)
             ]
             .into_iter()
             .collect()
@@ -395,14 +595,14 @@ mod tests {
         assert_eq!(nfa.start, 5);
         assert_eq!(nfa.accept, [1, 3, 4].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (5, NfaLabel::Epsilon, 0),
-                (0, NfaLabel::Char('a'), 1),
-                (2, NfaLabel::Char('b'), 3),
-                (3, NfaLabel::Epsilon, 2),
-                (5, NfaLabel::Epsilon, 4),
-                (4, NfaLabel::Epsilon, 2)
+                (5, None, 0),
+                (0, Some(NfaLabel::Char('a')), 1),
+                (2, Some(NfaLabel::Char('b')), 3),
+                (3, None, 2),
+                (5, None, 4),
+                (4, None, 2)
             ]
             .into_iter()
             .collect()
@@ -414,16 +614,15 @@ mod tests {
         assert_eq!(nfa.start, 6);
         assert_eq!(nfa.accept, [1, 5].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (4, NfaLabel::Epsilon, 2),
-                (3, NfaLabel::Epsilon, 2),
-                (4, NfaLabel::Epsilon, 5),
-                (0, NfaLabel::Char('a'), 1),
-                (2, NfaLabel::Char('b'), 3),
-                (3, NfaLabel::Epsilon, 5),
-                (6, NfaLabel::Epsilon, 4),
-                (6, NfaLabel::Epsilon, 0)
+                (4, None, 2),
+                (3, None, 2),
+                (0, Some(NfaLabel::Char('a')), 1),
+                (2, Some(NfaLabel::Char('b')), 3),
+                (3, None, 5),
+                (6, None, 4),
+                (6, None, 0)
             ]
             .into_iter()
             .collect()
@@ -435,14 +634,13 @@ mod tests {
         assert_eq!(nfa.start, 5);
         assert_eq!(nfa.accept, [1, 3, 4].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (4, NfaLabel::Epsilon, 2),
-                (5, NfaLabel::Epsilon, 0),
-                (2, NfaLabel::Char('b'), 3),
-                (5, NfaLabel::Epsilon, 4),
-                (3, NfaLabel::Epsilon, 2),
-                (0, NfaLabel::Char('a'), 1)
+                (4, None, 2),
+                (5, None, 0),
+                (2, Some(NfaLabel::Char('b')), 3),
+                (5, None, 4),
+                (0, Some(NfaLabel::Char('a')), 1)
             ]
             .into_iter()
             .collect()
@@ -454,15 +652,15 @@ mod tests {
         assert_eq!(nfa.start, 7);
         assert_eq!(nfa.accept, [1, 3, 5].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (0, NfaLabel::Char('a'), 1),
-                (6, NfaLabel::Epsilon, 4),
-                (6, NfaLabel::Epsilon, 2),
-                (4, NfaLabel::Char('c'), 5),
-                (7, NfaLabel::Epsilon, 0),
-                (2, NfaLabel::Char('b'), 3),
-                (7, NfaLabel::Epsilon, 6)
+                (0, Some(NfaLabel::Char('a')), 1),
+                (6, None, 4),
+                (6, None, 2),
+                (4, Some(NfaLabel::Char('c')), 5),
+                (7, None, 0),
+                (2, Some(NfaLabel::Char('b')), 3),
+                (7, None, 6)
             ]
             .into_iter()
             .collect()
@@ -474,14 +672,14 @@ mod tests {
         assert_eq!(nfa.start, 0);
         assert_eq!(nfa.accept, [3, 5].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (1, NfaLabel::Epsilon, 6),
-                (0, NfaLabel::Char('a'), 1),
-                (2, NfaLabel::Char('b'), 3),
-                (6, NfaLabel::Epsilon, 4),
-                (6, NfaLabel::Epsilon, 2),
-                (4, NfaLabel::Char('c'), 5)
+                (1, None, 6),
+                (0, Some(NfaLabel::Char('a')), 1),
+                (2, Some(NfaLabel::Char('b')), 3),
+                (6, None, 4),
+                (6, None, 2),
+                (4, Some(NfaLabel::Char('c')), 5)
             ]
             .into_iter()
             .collect()
@@ -493,20 +691,19 @@ mod tests {
         assert_eq!(nfa.start, 7);
         assert_eq!(nfa.accept, [6, 7].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (5, NfaLabel::Epsilon, 4),
-                (1, NfaLabel::Epsilon, 4),
-                (2, NfaLabel::Char('b'), 3),
-                (7, NfaLabel::Epsilon, 5),
-                (1, NfaLabel::Epsilon, 6),
-                (5, NfaLabel::Epsilon, 6),
-                (4, NfaLabel::Epsilon, 0),
-                (4, NfaLabel::Epsilon, 2),
-                (0, NfaLabel::Char('a'), 1),
-                (3, NfaLabel::Epsilon, 6),
-                (6, NfaLabel::Epsilon, 5),
-                (3, NfaLabel::Epsilon, 4)
+                (5, None, 4),
+                (1, None, 4),
+                (2, Some(NfaLabel::Char('b')), 3),
+                (7, None, 5),
+                (1, None, 6),
+                (4, None, 0),
+                (4, None, 2),
+                (0, Some(NfaLabel::Char('a')), 1),
+                (3, None, 6),
+                (6, None, 5),
+                (3, None, 4)
             ]
             .into_iter()
             .collect()
@@ -518,19 +715,18 @@ mod tests {
         assert_eq!(nfa.start, 9);
         assert_eq!(nfa.accept, [1, 3, 4, 6, 7].into());
         assert_eq!(
-            nfa.transitions,
+            nfa.edges(),
             vec![
-                (8, NfaLabel::Epsilon, 4),
-                (7, NfaLabel::Epsilon, 5),
-                (6, NfaLabel::Epsilon, 5),
-                (2, NfaLabel::Char('b'), 3),
-                (9, NfaLabel::Epsilon, 8),
-                (0, NfaLabel::Char('a'), 1),
-                (9, NfaLabel::Epsilon, 0),
-                (5, NfaLabel::Char('c'), 6),
-                (8, NfaLabel::Epsilon, 7),
-                (3, NfaLabel::Epsilon, 2),
-                (4, NfaLabel::Epsilon, 2)
+                (8, None, 4),
+                (7, None, 5),
+                (2, Some(NfaLabel::Char('b')), 3),
+                (9, None, 8),
+                (0, Some(NfaLabel::Char('a')), 1),
+                (9, None, 0),
+                (5, Some(NfaLabel::Char('c')), 6),
+                (8, None, 7),
+                (3, None, 2),
+                (4, None, 2)
             ]
             .into_iter()
             .collect()
@@ -549,6 +745,9 @@ mod tests {
 
         let closure = nfa.epsilon_closure([nfa.start()].iter().cloned().collect());
         assert_eq!(closure, [0, 2, 4, 5].iter().cloned().collect());
+        assert_eq!(nfa.closure(nfa.start()), [0, 2, 4, 5]);
+        assert_eq!(nfa.node(nfa.start()).epsilons.len(), 2);
+        assert!(nfa.node(nfa.start()).transitions.is_empty());
 
         let mut lexer = crate::lexer::Lexer::new("a|b|c");
         let mut parser = crate::parser::Parser::new(&mut lexer);
@@ -557,4 +756,72 @@ mod tests {
         let closure = nfa.epsilon_closure([nfa.start()].iter().cloned().collect());
         assert_eq!(closure, [0, 2, 4, 6, 7].iter().cloned().collect());
     }
+
+    #[test]
+    fn glushkov_construction() {
+        let config = crate::config::Config {
+            nfa_construction: crate::config::NfaConstruction::Glushkov,
+            ..crate::config::Config::default()
+        };
+        for (pattern, positions) in [("a|b*", 2), ("(a|b)*abb", 5), ("ab(cd|)", 4), ("", 0)] {
+            let mut lexer = crate::lexer::Lexer::new(pattern);
+            let mut parser = crate::parser::Parser::new(&mut lexer);
+            let ast = parser.parse().unwrap();
+
+            let nfa = Nfa::build(ast.clone(), &config).unwrap();
+            let size = nfa.size(config.nfa_construction);
+            assert_eq!(size.epsilon_transitions, 0);
+            assert_eq!(nfa.state_count(), positions + 1);
+
+            let thompson = Nfa::build(ast, &crate::config::Config::default()).unwrap();
+            let glushkov = crate::automaton::dfa::Dfa::from_nfa_with_limits(&nfa, &config).unwrap();
+            let thompson =
+                crate::automaton::dfa::Dfa::from_nfa_with_limits(&thompson, &config).unwrap();
+            for input in ["", "a", "b", "bb", "abb", "babb", "ab", "abcd", "abc", "c"] {
+                assert_eq!(
+                    glushkov.is_match(input),
+                    thompson.is_match(input),
+                    "{input:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn antimirov_construction() {
+        let config = crate::config::Config {
+            nfa_construction: crate::config::NfaConstruction::Antimirov,
+            ..crate::config::Config::default()
+        };
+        for (pattern, positions) in [("a|b*", 2), ("(a|b)*abb", 5), ("ab(cd|)", 4)] {
+            let mut lexer = crate::lexer::Lexer::new(pattern);
+            let mut parser = crate::parser::Parser::new(&mut lexer);
+            let ast = parser.parse().unwrap();
+
+            let nfa = Nfa::build(ast.clone(), &config).unwrap();
+            let size = nfa.size(config.nfa_construction);
+            assert_eq!(size.epsilon_transitions, 0);
+            assert!(size.states <= positions + 1);
+
+            let thompson = Nfa::build(ast, &crate::config::Config::default()).unwrap();
+            let antimirov =
+                crate::automaton::dfa::Dfa::from_nfa_with_limits(&nfa, &config).unwrap();
+            let thompson =
+                crate::automaton::dfa::Dfa::from_nfa_with_limits(&thompson, &config).unwrap();
+            for input in ["", "a", "b", "bb", "abb", "babb", "ab", "abcd", "abc", "c"] {
+                assert_eq!(antimirov.is_match(input), thompson.is_match(input));
+            }
+        }
+
+        let mut lexer = crate::lexer::Lexer::new("(a|b)*abb");
+        let ast = crate::parser::Parser::new(&mut lexer).parse().unwrap();
+        assert!(matches!(
+            crate::derivative::partial_derivatives(&ast, 2, usize::MAX),
+            Err(crate::Error::LimitExceeded(crate::Limit::NfaStates, 2))
+        ));
+        assert!(matches!(
+            Nfa::from_partial_derivatives(&ast, &mut NfaState::with_limits(3, usize::MAX)),
+            Err(crate::Error::LimitExceeded(crate::Limit::NfaStates, 3))
+        ));
+    }
 }
@@ -0,0 +1,220 @@
+pub const MAX_POSITIONS: usize = 128;
+
+#[derive(Debug, Clone)]
+struct Masks {
+    first: u128,
+    last: u128,
+    nullable: bool,
+    ascii: [u128; 128],
+    chars: Vec<(char, u128)>,
+    classes: Vec<(crate::charclass::CharClass, u128)>,
+    follow: Vec<[u128; 256]>,
+}
+
+impl Masks {
+    fn new(glushkov: &crate::automaton::glushkov::Glushkov) -> Masks {
+        let positions = glushkov.positions.len();
+        let set = |positions: &[usize]| positions.iter().fold(0, |set, &p| set | 1u128 << p);
+
+        let mut ascii = [0; 128];
+        let mut chars: Vec<(char, u128)> = Vec::new();
+        let mut classes: Vec<(crate::charclass::CharClass, u128)> = Vec::new();
+        for (position, label) in glushkov.positions.iter().enumerate() {
+            let bit = 1u128 << position;
+            match *label {
+                crate::automaton::label::NfaLabel::Char(c) => {
+                    if c.is_ascii() {
+                        ascii[c as usize] |= bit;
+                    } else {
+                        match chars.iter_mut().find(|(other, _)| *other == c) {
+                            Some((_, mask)) => *mask |= bit,
+                            None => chars.push((c, bit)),
+                        }
+                    }
+                }
+                crate::automaton::label::NfaLabel::Class(class) => {
+                    for (byte, mask) in ascii.iter_mut().enumerate() {
+                        if class.matches(byte as u8 as char) {
+                            *mask |= bit;
+                        }
+                    }
+                    match classes.iter_mut().find(|(other, _)| *other == class) {
+                        Some((_, mask)) => *mask |= bit,
+                        None => classes.push((class, bit)),
+                    }
+                }
+            }
+        }
+        chars.sort_unstable_by_key(|&(c, _)| c);
+
+        let mut follow = vec![[0; 256]; positions.div_ceil(8)];
+        for (chunk, table) in follow.iter_mut().enumerate() {
+            for byte in 1..256usize {
+                let position = chunk * 8 + byte.trailing_zeros() as usize;
+                let rest = table[byte & (byte - 1)];
+                table[byte] = rest | glushkov.follow.get(position).map_or(0, |f| set(f));
+            }
+        }
+
+        Masks {
+            first: set(&glushkov.first),
+            last: set(&glushkov.last),
+            nullable: glushkov.nullable,
+            ascii,
+            chars,
+            classes,
+            follow,
+        }
+    }
+
+    fn mask(&self, ch: char) -> u128 {
+        if ch.is_ascii() {
+            return self.ascii[ch as usize];
+        }
+        let literal = self
+            .chars
+            .binary_search_by_key(&ch, |&(c, _)| c)
+            .map_or(0, |index| self.chars[index].1);
+        self.classes
+            .iter()
+            .filter(|(class, _)| class.matches(ch))
+            .fold(literal, |mask, &(_, bits)| mask | bits)
+    }
+
+    fn follow(&self, state: u128) -> u128 {
+        self.follow
+            .iter()
+            .enumerate()
+            .fold(0, |next, (chunk, table)| {
+                next | table[(state >> (chunk * 8)) as usize & 0xff]
+            })
+    }
+
+    fn step(&self, state: u128, initial: u128, ch: char) -> u128 {
+        (self.follow(state) | initial) & self.mask(ch)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShiftAnd {
+    forward: Masks,
+    reverse: Masks,
+}
+
+impl ShiftAnd {
+    pub fn new(ast: &crate::parser::AstNode) -> crate::Result<ShiftAnd> {
+        let positions = position_count(ast);
+        if positions > MAX_POSITIONS {
+            return Err(crate::Error::TooManyPositions(positions, MAX_POSITIONS));
+        }
+        let glushkov = |ast| crate::automaton::glushkov::Glushkov::new(ast, usize::MAX);
+        Ok(ShiftAnd {
+            forward: Masks::new(&glushkov(ast)?),
+            reverse: Masks::new(&glushkov(&ast.reverse())?),
+        })
+    }
+
+    pub fn is_match(&self, input: &str) -> bool {
+        let masks = &self.forward;
+        let mut chars = input.chars();
+        let Some(ch) = chars.next() else {
+            return masks.nullable;
+        };
+        let mut state = masks.first & masks.mask(ch);
+        for ch in chars {
+            if state == 0 {
+                return false;
+            }
+            state = masks.step(state, 0, ch);
+        }
+        state & masks.last != 0
+    }
+
+    pub fn find(&self, input: &str) -> Option<(usize, usize)> {
+        let start = self.leftmost_start(input)?;
+        let masks = &self.forward;
+        let mut end = masks.nullable.then_some(start);
+        let mut initial = masks.first;
+        let mut state = 0;
+        for (index, ch) in input[start..].char_indices() {
+            state = masks.step(state, initial, ch);
+            initial = 0;
+            if state == 0 {
+                break;
+            }
+            if state & masks.last != 0 {
+                end = Some(start + index + ch.len_utf8());
+            }
+        }
+        end.map(|end| (start, end))
+    }
+
+    fn leftmost_start(&self, input: &str) -> Option<usize> {
+        let masks = &self.reverse;
+        let mut start = masks.nullable.then_some(input.len());
+        let mut state = 0;
+        for (index, ch) in input.char_indices().rev() {
+            state = masks.step(state, masks.first, ch);
+            if masks.nullable || state & masks.last != 0 {
+                start = Some(index);
+            }
+        }
+        start
+    }
+}
+
+fn position_count(ast: &crate::parser::AstNode) -> usize {
+    match ast {
+        crate::parser::AstNode::Char(_) | crate::parser::AstNode::Class(_) => 1,
+        crate::parser::AstNode::Empty | crate::parser::AstNode::Epsilon => 0,
+        crate::parser::AstNode::Plus(node)
+        | crate::parser::AstNode::Star(node)
+        | crate::parser::AstNode::Question(node)
+        | crate::parser::AstNode::Capture(_, node) => position_count(node),
+        crate::parser::AstNode::Or(left, right) | crate::parser::AstNode::Seq(left, right) => {
+            position_count(left) + position_count(right)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shift_and(pattern: &str) -> crate::Result<ShiftAnd> {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let ast = crate::parser::Parser::new(&mut lexer).parse().unwrap();
+        ShiftAnd::new(&ast)
+    }
+
+    #[test]
+    fn bit_parallel() {
+        let regex = shift_and("(a|ab)(c|bcd)(d*)").unwrap();
+        assert!(regex.is_match("abcd"));
+        assert!(regex.is_match("abc"));
+        assert!(!regex.is_match("abd"));
+        assert_eq!(regex.find("xxabcdd"), Some((2, 7)));
+        assert_eq!(regex.find("xabx"), None);
+
+        let regex = shift_and("abcd|c").unwrap();
+        assert_eq!(regex.find("xabcd"), Some((1, 5)));
+        assert_eq!(regex.find("xabcx"), Some((3, 4)));
+
+        let regex = shift_and(r"\d+|正規.").unwrap();
+        assert_eq!(regex.find("ab 123 c"), Some((3, 6)));
+        assert_eq!(regex.find("正規表現"), Some((0, 9)));
+        assert_eq!(regex.find("a正規\n1"), Some((8, 9)));
+
+        let regex = shift_and("b*").unwrap();
+        assert!(regex.is_match(""));
+        assert_eq!(regex.find("abb"), Some((0, 0)));
+
+        let regex = shift_and(&"a".repeat(MAX_POSITIONS)).unwrap();
+        assert!(regex.is_match(&"a".repeat(MAX_POSITIONS)));
+        assert!(!regex.is_match(&"a".repeat(MAX_POSITIONS - 1)));
+        assert!(matches!(
+            shift_and(&"a".repeat(MAX_POSITIONS + 1)),
+            Err(crate::Error::TooManyPositions(129, MAX_POSITIONS))
+        ));
+    }
+}
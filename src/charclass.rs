@@ -26,6 +26,25 @@ impl CharClass {
         }
     }
 
+    pub fn id(self) -> u8 {
+        match self {
+            CharClass::Any => 0,
+            CharClass::Digit => 1,
+            CharClass::Word => 2,
+            CharClass::Space => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(CharClass::Any),
+            1 => Some(CharClass::Digit),
+            2 => Some(CharClass::Word),
+            3 => Some(CharClass::Space),
+            _ => None,
+        }
+    }
+
     pub fn expand_ascii(self) -> [bool; 128] {
         let mut table = [false; 128];
         for byte in 0u8..128 {
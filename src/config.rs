@@ -0,0 +1,166 @@
+const DEFAULT_MAX_AST_NODES: usize = 1 << 20;
+const DEFAULT_MAX_NFA_STATES: usize = 1 << 20;
+const DEFAULT_MAX_NFA_TRANSITIONS: usize = 1 << 22;
+const DEFAULT_MAX_DFA_STATES: usize = 1 << 16;
+const DEFAULT_MAX_DFA_BYTES: usize = 10 * (1 << 20);
+const DEFAULT_MAX_PROGRAM_LEN: usize = 1 << 20;
+const DEFAULT_MAX_ARENA_NODES: usize = 1 << 18;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Limit {
+    AstNodes,
+    NfaStates,
+    NfaTransitions,
+    DfaStates,
+    DfaBytes,
+    ProgramLength,
+    ArenaNodes,
+}
+
+impl Limit {
+    pub fn check(self, value: usize, max: usize) -> crate::Result<()> {
+        if value > max {
+            Err(crate::Error::LimitExceeded(self, max))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::AstNodes => write!(f, "AST node"),
+            Limit::NfaStates => write!(f, "NFA state"),
+            Limit::NfaTransitions => write!(f, "NFA transition"),
+            Limit::DfaStates => write!(f, "DFA state"),
+            Limit::DfaBytes => write!(f, "DFA byte size"),
+            Limit::ProgramLength => write!(f, "program length"),
+            Limit::ArenaNodes => write!(f, "derivative arena node"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MatchKind {
+    #[default]
+    LeftmostLongest,
+    LeftmostFirst,
+}
+
+impl std::fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchKind::LeftmostLongest => write!(f, "leftmost-longest"),
+            MatchKind::LeftmostFirst => write!(f, "leftmost-first"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DfaLayout {
+    #[default]
+    Dense,
+    Sparse,
+}
+
+impl std::fmt::Display for DfaLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DfaLayout::Dense => write!(f, "dense"),
+            DfaLayout::Sparse => write!(f, "sparse"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum NfaConstruction {
+    #[default]
+    Thompson,
+    Antimirov,
+    Glushkov,
+}
+
+impl std::fmt::Display for NfaConstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NfaConstruction::Thompson => write!(f, "thompson"),
+            NfaConstruction::Antimirov => write!(f, "antimirov"),
+            NfaConstruction::Glushkov => write!(f, "glushkov"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DfaConstruction {
+    #[default]
+    Subset,
+    Derivative,
+}
+
+impl std::fmt::Display for DfaConstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DfaConstruction::Subset => write!(f, "subset"),
+            DfaConstruction::Derivative => write!(f, "derivative"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub max_ast_nodes: usize,
+    pub max_nfa_states: usize,
+    pub max_nfa_transitions: usize,
+    pub max_dfa_states: usize,
+    pub max_dfa_bytes: usize,
+    pub max_program_len: usize,
+    pub max_arena_nodes: usize,
+    pub minimize: bool,
+    pub prefilter: bool,
+    pub match_kind: MatchKind,
+    pub dfa_layout: DfaLayout,
+    pub dfa_construction: DfaConstruction,
+    pub nfa_construction: NfaConstruction,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_ast_nodes: DEFAULT_MAX_AST_NODES,
+            max_nfa_states: DEFAULT_MAX_NFA_STATES,
+            max_nfa_transitions: DEFAULT_MAX_NFA_TRANSITIONS,
+            max_dfa_states: DEFAULT_MAX_DFA_STATES,
+            max_dfa_bytes: DEFAULT_MAX_DFA_BYTES,
+            max_program_len: DEFAULT_MAX_PROGRAM_LEN,
+            max_arena_nodes: DEFAULT_MAX_ARENA_NODES,
+            minimize: false,
+            prefilter: true,
+            match_kind: MatchKind::LeftmostLongest,
+            dfa_layout: DfaLayout::Dense,
+            dfa_construction: DfaConstruction::Subset,
+            nfa_construction: NfaConstruction::Thompson,
+        }
+    }
+}
+
+impl Config {
+    #[cfg(test)]
+    pub fn unlimited() -> Self {
+        Config {
+            max_ast_nodes: usize::MAX,
+            max_nfa_states: usize::MAX,
+            max_nfa_transitions: usize::MAX,
+            max_dfa_states: usize::MAX,
+            max_dfa_bytes: usize::MAX,
+            max_program_len: usize::MAX,
+            max_arena_nodes: usize::MAX,
+            minimize: false,
+            prefilter: true,
+            match_kind: MatchKind::LeftmostLongest,
+            dfa_layout: DfaLayout::Dense,
+            dfa_construction: DfaConstruction::Subset,
+            nfa_construction: NfaConstruction::Thompson,
+        }
+    }
+}
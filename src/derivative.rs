@@ -2,6 +2,7 @@ use foldhash::HashMapExt as _;
 use foldhash::HashSetExt as _;
 
 const DEFAULT_MAX_AST_SIZE: usize = 1000;
+const DEFAULT_CACHE_CAPACITY: usize = 1 << 16;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 struct AstId(u32);
@@ -23,8 +24,11 @@ enum NodeKind {
     Question(AstId),
     Or(AstId, AstId),
     Seq(AstId, AstId),
+    And(AstId, AstId),
+    Not(AstId),
 }
 
+#[derive(Clone, Debug)]
 struct AstArena {
     nodes: Vec<NodeKind>,
     interner: foldhash::HashMap<NodeKind, AstId>,
@@ -32,6 +36,7 @@ struct AstArena {
     structural_size_cache: Vec<Option<usize>>,
     empty: AstId,
     epsilon: AstId,
+    universal: AstId,
 }
 
 impl AstArena {
@@ -43,12 +48,15 @@ impl AstArena {
             structural_size_cache: Vec::new(),
             empty: AstId(0),
             epsilon: AstId(0),
+            universal: AstId(0),
         };
 
         let empty = arena.direct_intern(NodeKind::Empty);
         arena.empty = empty;
         let epsilon = arena.direct_intern(NodeKind::Epsilon);
         arena.epsilon = epsilon;
+        let universal = arena.direct_intern(NodeKind::Not(empty));
+        arena.universal = universal;
 
         arena
     }
@@ -61,6 +69,10 @@ impl AstArena {
         self.epsilon
     }
 
+    fn universal(&self) -> AstId {
+        self.universal
+    }
+
     fn kind(&self, id: AstId) -> &NodeKind {
         &self.nodes[id.index()]
     }
@@ -100,7 +112,10 @@ impl AstArena {
             NodeKind::Star(_) => true,
             NodeKind::Question(_) => true,
             NodeKind::Or(left, right) => self.nullable_of(left) || self.nullable_of(right),
-            NodeKind::Seq(left, right) => self.nullable_of(left) && self.nullable_of(right),
+            NodeKind::Seq(left, right) | NodeKind::And(left, right) => {
+                self.nullable_of(left) && self.nullable_of(right)
+            }
+            NodeKind::Not(inner) => !self.nullable_of(inner),
         };
         self.nullable_cache[id.index()] = Some(value);
         value
@@ -118,6 +133,55 @@ impl AstArena {
         size
     }
 
+    fn compact(&mut self, base: usize, roots: &[AstId]) -> Vec<AstId> {
+        let mut live = vec![false; self.nodes.len()];
+        live[..base].fill(true);
+        let mut stack = roots.to_vec();
+        while let Some(id) = stack.pop() {
+            if std::mem::replace(&mut live[id.index()], true) {
+                continue;
+            }
+            match *self.kind(id) {
+                NodeKind::Plus(inner)
+                | NodeKind::Star(inner)
+                | NodeKind::Question(inner)
+                | NodeKind::Not(inner) => stack.push(inner),
+                NodeKind::Or(left, right)
+                | NodeKind::Seq(left, right)
+                | NodeKind::And(left, right) => stack.extend([left, right]),
+                NodeKind::Empty | NodeKind::Epsilon | NodeKind::Char(_) | NodeKind::Class(_) => {}
+            }
+        }
+
+        let nodes = std::mem::take(&mut self.nodes);
+        let nullable_cache = std::mem::take(&mut self.nullable_cache);
+        let structural_size_cache = std::mem::take(&mut self.structural_size_cache);
+        self.interner.clear();
+        let mut remap = vec![AstId(u32::MAX); nodes.len()];
+        for (old, kind) in nodes.into_iter().enumerate() {
+            if !live[old] {
+                continue;
+            }
+            let map = |id: AstId| remap[id.index()];
+            let kind = match kind {
+                NodeKind::Plus(inner) => NodeKind::Plus(map(inner)),
+                NodeKind::Star(inner) => NodeKind::Star(map(inner)),
+                NodeKind::Question(inner) => NodeKind::Question(map(inner)),
+                NodeKind::Not(inner) => NodeKind::Not(map(inner)),
+                NodeKind::Or(left, right) => NodeKind::Or(map(left), map(right)),
+                NodeKind::Seq(left, right) => NodeKind::Seq(map(left), map(right)),
+                NodeKind::And(left, right) => NodeKind::And(map(left), map(right)),
+                kind => kind,
+            };
+            remap[old] = AstId(self.nodes.len() as u32);
+            self.interner.insert(kind.clone(), remap[old]);
+            self.nodes.push(kind);
+            self.nullable_cache.push(nullable_cache[old]);
+            self.structural_size_cache.push(structural_size_cache[old]);
+        }
+        remap
+    }
+
     fn export(&self, id: AstId) -> crate::parser::AstNode {
         match self.kind(id) {
             NodeKind::Empty => crate::parser::AstNode::Empty,
@@ -137,60 +201,289 @@ impl AstArena {
                 Box::new(self.export(*left)),
                 Box::new(self.export(*right)),
             ),
+            NodeKind::And(_, _) | NodeKind::Not(_) => unreachable!(),
         }
     }
 }
 
+#[derive(Debug)]
+pub struct DerivativeCache {
+    arena: AstArena,
+    memo: foldhash::HashMap<(AstId, u32), (AstId, bool)>,
+    counts: crate::stats::TransitionCache,
+    compactions: usize,
+    pike: crate::vm::Cache,
+}
+
+impl DerivativeCache {
+    fn new(arena: &AstArena) -> Self {
+        DerivativeCache {
+            arena: arena.clone(),
+            memo: foldhash::HashMap::new(),
+            counts: crate::stats::TransitionCache::default(),
+            compactions: 0,
+            pike: crate::vm::Cache::new(0),
+        }
+    }
+
+    fn evict(&mut self, keep: usize) {
+        let mut kept = 0;
+        self.memo.retain(|_, (_, hot)| {
+            let retain = std::mem::replace(hot, false) && kept < keep;
+            kept += retain as usize;
+            retain
+        });
+        self.counts.evictions += 1;
+    }
+
+    fn compact(&mut self, base: usize, state: AstId) -> AstId {
+        let mut roots: Vec<AstId> = self
+            .memo
+            .iter()
+            .flat_map(|(&(from, _), &(to, _))| [from, to])
+            .collect();
+        roots.push(state);
+        let remap = self.arena.compact(base, &roots);
+        self.memo = self
+            .memo
+            .drain()
+            .map(|((from, symbol), (to, hot))| {
+                ((remap[from.index()], symbol), (remap[to.index()], hot))
+            })
+            .collect();
+        self.compactions += 1;
+        remap[state.index()]
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: std::sync::atomic::AtomicUsize,
+    misses: std::sync::atomic::AtomicUsize,
+    evictions: std::sync::atomic::AtomicUsize,
+    compactions: std::sync::atomic::AtomicUsize,
+    arena_nodes: std::sync::atomic::AtomicUsize,
+}
+
 pub struct Derivative {
-    arena: std::cell::RefCell<AstArena>,
     start: AstId,
+    reverse_start: AstId,
+    start_nullable: bool,
     canonical: crate::parser::AstNode,
     max_ast_size: usize,
-    derivative_memo: std::cell::RefCell<foldhash::HashMap<(AstId, char), AstId>>,
+    capacity: usize,
+    max_arena_nodes: usize,
+    symbols: Symbols,
+    arena: std::sync::Arc<AstArena>,
+    pool: crate::pool::Pool<DerivativeCache>,
+    counters: Counters,
+    fallback: std::sync::OnceLock<crate::vm::Vm>,
 }
 
 impl Derivative {
-    pub fn new(ast: crate::parser::AstNode) -> Self {
+    pub fn new(ast: crate::parser::AstNode, config: &crate::config::Config) -> crate::Result<Self> {
+        Self::with_capacity(ast, config, DEFAULT_CACHE_CAPACITY)
+    }
+
+    pub fn with_capacity(
+        ast: crate::parser::AstNode,
+        config: &crate::config::Config,
+        capacity: usize,
+    ) -> crate::Result<Self> {
         let mut arena = AstArena::new();
         let start = from_parser(&mut arena, &ast);
+        let reverse_start = from_parser(&mut arena, &ast.reverse());
         let canonical = arena.export(start);
+        let start_nullable = arena.nullable_of(start);
+        crate::config::Limit::ArenaNodes.check(arena.nodes.len(), config.max_arena_nodes)?;
+        let symbols = Symbols::new(&arena);
 
-        Derivative {
-            arena: std::cell::RefCell::new(arena),
+        let arena = std::sync::Arc::new(arena);
+        let template = arena.clone();
+        Ok(Derivative {
             start,
+            reverse_start,
+            start_nullable,
             canonical,
             max_ast_size: DEFAULT_MAX_AST_SIZE,
-            derivative_memo: std::cell::RefCell::new(foldhash::HashMap::new()),
+            capacity,
+            max_arena_nodes: config.max_arena_nodes,
+            symbols,
+            arena,
+            pool: crate::pool::Pool::new(move || DerivativeCache::new(&template)),
+            counters: Counters::default(),
+            fallback: std::sync::OnceLock::new(),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn with_max_ast_size(self, max_ast_size: usize) -> Self {
+        Derivative {
+            max_ast_size,
+            ..self
         }
     }
 
+    pub fn stats(&self) -> crate::Stats {
+        let load = |counter: &std::sync::atomic::AtomicUsize| {
+            counter.load(std::sync::atomic::Ordering::Relaxed)
+        };
+        crate::Stats::from_derivative(
+            crate::stats::TransitionCache {
+                hits: load(&self.counters.hits),
+                misses: load(&self.counters.misses),
+                evictions: load(&self.counters.evictions),
+            },
+            crate::stats::Arena {
+                nodes: load(&self.counters.arena_nodes),
+                compactions: load(&self.counters.compactions),
+            },
+        )
+    }
+
+    fn record(&self, cache: &mut DerivativeCache) {
+        let counts = std::mem::take(&mut cache.counts);
+        for (counter, count) in [
+            (&self.counters.hits, counts.hits),
+            (&self.counters.misses, counts.misses),
+            (&self.counters.evictions, counts.evictions),
+            (
+                &self.counters.compactions,
+                std::mem::take(&mut cache.compactions),
+            ),
+        ] {
+            counter.fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+        }
+        self.counters.arena_nodes.store(
+            cache.arena.nodes.len(),
+            std::sync::atomic::Ordering::Relaxed,
+        );
+    }
+
+    fn fallback(&self) -> &crate::vm::Vm {
+        self.fallback.get_or_init(|| {
+            let config = crate::config::Config {
+                max_program_len: usize::MAX,
+                ..crate::config::Config::default()
+            };
+            crate::vm::Vm::new(self.canonical.clone(), &config)
+                .expect("simplified patterns always compile")
+        })
+    }
+
+    #[inline]
+    fn step(&self, cache: &mut DerivativeCache, state: AstId, ch: char) -> AstId {
+        let key = (state, self.symbols.symbol(ch));
+        if let Some((next, hot)) = cache.memo.get_mut(&key) {
+            *hot = true;
+            cache.counts.hits += 1;
+            return *next;
+        }
+        cache.counts.misses += 1;
+        let mut next = derivative_id(&mut cache.arena, state, ch);
+        if cache.memo.len() >= self.capacity {
+            cache.evict(self.capacity / 2);
+        }
+        cache.memo.insert(key, (next, false));
+        if cache.arena.nodes.len() > self.max_arena_nodes {
+            cache.evict(self.capacity / 2);
+            next = cache.compact(self.arena.nodes.len(), next);
+            if cache.arena.nodes.len() > self.max_arena_nodes / 2 {
+                cache.memo.clear();
+                next = cache.compact(self.arena.nodes.len(), next);
+            }
+        }
+        next
+    }
+
+    pub fn create_cache(&self) -> DerivativeCache {
+        DerivativeCache::new(&self.arena)
+    }
+
     pub fn is_match(&self, input: &str) -> bool {
-        let mut arena = self.arena.borrow_mut();
-        let mut memo = self.derivative_memo.borrow_mut();
-        memo.clear();
+        self.is_match_with(&mut self.pool.get(), input)
+    }
+
+    pub fn is_match_with(&self, cache: &mut DerivativeCache, input: &str) -> bool {
         let mut state = self.start;
+        let mut matched = None;
 
         for ch in input.chars() {
-            state = derivative_with_cache(&mut arena, state, ch, &mut memo);
+            state = self.step(cache, state, ch);
 
-            if arena.structural_size_of(state) > self.max_ast_size {
-                return match_fallback(&self.canonical, input);
+            if cache.arena.structural_size_of(state) > self.max_ast_size {
+                matched = Some(self.fallback().is_match_with(&mut cache.pike, input));
+                break;
             }
         }
 
-        arena.nullable_of(state)
+        self.record(cache);
+        matched.unwrap_or_else(|| cache.arena.nullable_of(state))
     }
 
-    pub fn is_empty_match(&self) -> bool {
-        self.arena.borrow_mut().nullable_of(self.start)
+    pub fn find(&self, input: &str) -> Option<(usize, usize)> {
+        self.find_with(&mut self.pool.get(), input)
     }
-}
 
-impl Clone for Derivative {
-    fn clone(&self) -> Self {
-        let mut clone = Derivative::new(self.canonical.clone());
-        clone.max_ast_size = self.max_ast_size;
-        clone
+    pub fn find_with(&self, cache: &mut DerivativeCache, input: &str) -> Option<(usize, usize)> {
+        let found = match self.leftmost_start(cache, input) {
+            Some(Some(start)) => self
+                .longest_from(cache, input, start)
+                .map(|end| end.map(|end| (start, end))),
+            Some(None) => Some(None),
+            None => None,
+        };
+        let found = found.unwrap_or_else(|| self.fallback().find_with(&mut cache.pike, input));
+
+        self.record(cache);
+        found
+    }
+
+    fn leftmost_start(&self, cache: &mut DerivativeCache, input: &str) -> Option<Option<usize>> {
+        let mut state = self.reverse_start;
+        let mut start = cache.arena.nullable_of(state).then_some(input.len());
+
+        for (at, ch) in input.char_indices().rev() {
+            let next = self.step(cache, state, ch);
+            if cache.arena.structural_size_of(next) > self.max_ast_size {
+                return None;
+            }
+            state = mk_or(&mut cache.arena, next, self.reverse_start);
+            if cache.arena.nullable_of(state) {
+                start = Some(at);
+            }
+        }
+
+        Some(start)
+    }
+
+    fn longest_from(
+        &self,
+        cache: &mut DerivativeCache,
+        input: &str,
+        start: usize,
+    ) -> Option<Option<usize>> {
+        let mut state = self.start;
+        let mut end = self.start_nullable.then_some(start);
+
+        for (at, ch) in input[start..].char_indices() {
+            state = self.step(cache, state, ch);
+            if state == cache.arena.empty() {
+                break;
+            }
+            if cache.arena.structural_size_of(state) > self.max_ast_size {
+                return None;
+            }
+            if cache.arena.nullable_of(state) {
+                end = Some(start + at + ch.len_utf8());
+            }
+        }
+
+        Some(end)
+    }
+
+    pub fn is_empty_match(&self) -> bool {
+        self.start_nullable
     }
 }
 
@@ -239,22 +532,311 @@ fn from_parser(arena: &mut AstArena, node: &crate::parser::AstNode) -> AstId {
             let right_id = from_parser(arena, right);
             mk_seq(arena, left_id, right_id)
         }
+        crate::parser::AstNode::Capture(_, inner) => from_parser(arena, inner),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Ast(crate::parser::AstNode),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+impl Expr {
+    pub fn reverse(&self) -> Expr {
+        match self {
+            Expr::Ast(ast) => Expr::Ast(ast.reverse()),
+            Expr::And(left, right) => {
+                Expr::And(Box::new(left.reverse()), Box::new(right.reverse()))
+            }
+            Expr::Not(inner) => Expr::Not(Box::new(inner.reverse())),
+        }
     }
 }
 
-fn derivative_with_cache(
+fn from_expr(arena: &mut AstArena, expr: &Expr) -> AstId {
+    match expr {
+        Expr::Ast(ast) => from_parser(arena, ast),
+        Expr::And(left, right) => {
+            let left_id = from_expr(arena, left);
+            let right_id = from_expr(arena, right);
+            mk_and(arena, left_id, right_id)
+        }
+        Expr::Not(inner) => {
+            let inner_id = from_expr(arena, inner);
+            mk_not(arena, inner_id)
+        }
+    }
+}
+
+struct Symbols {
+    representatives: Vec<char>,
+    ascii: [u16; 128],
+    chars: usize,
+}
+
+impl Symbols {
+    fn new(arena: &AstArena) -> Self {
+        let leaves: Vec<&NodeKind> = arena
+            .nodes
+            .iter()
+            .filter(|kind| matches!(kind, NodeKind::Char(_) | NodeKind::Class(_)))
+            .collect();
+        let matches = |kind: &NodeKind, ch: char| match kind {
+            NodeKind::Char(c) => *c == ch,
+            NodeKind::Class(class) => class.matches(ch),
+            _ => false,
+        };
+
+        let mut representatives = Vec::new();
+        let mut signatures = foldhash::HashMap::new();
+        let mut ascii = [0; 128];
+        for byte in 0u8..128 {
+            let signature: Vec<bool> = leaves
+                .iter()
+                .map(|kind| matches(kind, byte as char))
+                .collect();
+            ascii[byte as usize] = *signatures.entry(signature).or_insert_with(|| {
+                representatives.push(byte as char);
+                representatives.len() as u16 - 1
+            });
+        }
+        let ascii_len = representatives.len();
+        representatives.extend(leaves.iter().filter_map(|kind| match kind {
+            NodeKind::Char(c) if !c.is_ascii() => Some(*c),
+            _ => None,
+        }));
+        representatives[ascii_len..].sort_unstable();
+        let mut chars = representatives.split_off(ascii_len);
+        chars.dedup();
+        representatives.extend(&chars);
+        representatives.extend(('\u{80}'..=char::MAX).find(|ch| chars.binary_search(ch).is_err()));
+
+        Symbols {
+            representatives,
+            ascii,
+            chars: chars.len(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.representatives.len()
+    }
+
+    fn symbol(&self, ch: char) -> u32 {
+        if ch.is_ascii() {
+            return self.ascii[ch as usize] as u32;
+        }
+        let ascii_len = self.len() - self.chars - 1;
+        match self.representatives[ascii_len..self.len() - 1].binary_search(&ch) {
+            Ok(index) => (ascii_len + index) as u32,
+            Err(_) => self.len() as u32 - 1,
+        }
+    }
+}
+
+fn partition(arena: &mut AstArena, id: AstId, symbols: &Symbols) -> Vec<u32> {
+    match arena.kind(id).clone() {
+        NodeKind::Empty | NodeKind::Epsilon => vec![0; symbols.len()],
+        NodeKind::Char(c) => symbols
+            .representatives
+            .iter()
+            .map(|&ch| (ch == c) as u32)
+            .collect(),
+        NodeKind::Class(class) => symbols
+            .representatives
+            .iter()
+            .map(|&ch| class.matches(ch) as u32)
+            .collect(),
+        NodeKind::Plus(inner)
+        | NodeKind::Star(inner)
+        | NodeKind::Question(inner)
+        | NodeKind::Not(inner) => partition(arena, inner, symbols),
+        NodeKind::Seq(left, _) if !arena.nullable_of(left) => partition(arena, left, symbols),
+        NodeKind::Or(left, right) | NodeKind::Seq(left, right) | NodeKind::And(left, right) => {
+            let left = partition(arena, left, symbols);
+            let right = partition(arena, right, symbols);
+            let mut blocks = foldhash::HashMap::new();
+            left.into_iter()
+                .zip(right)
+                .map(|pair| {
+                    let next = blocks.len() as u32;
+                    *blocks.entry(pair).or_insert(next)
+                })
+                .collect()
+        }
+    }
+}
+
+pub fn explore(
+    expr: &Expr,
+    config: &crate::config::Config,
+    reverse: bool,
+) -> crate::Result<crate::automaton::dfa::CharDfa> {
+    let mut arena = AstArena::new();
+    let start = from_expr(&mut arena, expr);
+    let symbols = Symbols::new(&arena);
+    let ascii_len = symbols.len() - symbols.chars - 1;
+
+    let mut ids = foldhash::HashMap::new();
+    ids.insert(start, 0);
+    let mut states = vec![start];
+    let mut dfa = crate::automaton::dfa::CharDfa {
+        start: 0,
+        accepts: Vec::new(),
+        chars: Vec::new(),
+        any: Vec::new(),
+    };
+    let mut targets = Vec::new();
+    while let Some(&state) = states.get(dfa.accepts.len()) {
+        let from = dfa.accepts.len() as crate::automaton::dfa::DfaStateID;
+        dfa.accepts.push(arena.nullable_of(state));
+
+        let blocks = partition(&mut arena, state, &symbols);
+        let mut derivatives = foldhash::HashMap::new();
+        targets.clear();
+        for (&block, &ch) in blocks.iter().zip(&symbols.representatives) {
+            let next = match derivatives.get(&block) {
+                Some(&next) => next,
+                None => {
+                    let next = derivative_id(&mut arena, state, ch);
+                    derivatives.insert(block, next);
+                    next
+                }
+            };
+            let next = if next == arena.empty() {
+                crate::automaton::dfa::DEAD
+            } else {
+                let next = if reverse {
+                    mk_or(&mut arena, next, start)
+                } else {
+                    next
+                };
+                match ids.get(&next) {
+                    Some(&id) => id,
+                    None => {
+                        crate::config::Limit::DfaStates
+                            .check(states.len() + 1, config.max_dfa_states)?;
+                        ids.insert(next, states.len() as crate::automaton::dfa::DfaStateID);
+                        states.push(next);
+                        states.len() as crate::automaton::dfa::DfaStateID - 1
+                    }
+                }
+            };
+            targets.push(next);
+        }
+
+        for byte in 0u8..128 {
+            match targets[symbols.ascii[byte as usize] as usize] {
+                crate::automaton::dfa::DEAD => {}
+                to => dfa.chars.push((from, byte as char, to)),
+            }
+        }
+        let other = targets[symbols.len() - 1];
+        for (&ch, &to) in symbols.representatives[ascii_len..]
+            .iter()
+            .zip(&targets[ascii_len..symbols.len() - 1])
+        {
+            if to != other {
+                dfa.chars.push((from, ch, to));
+            }
+        }
+        if other != crate::automaton::dfa::DEAD {
+            dfa.any.push((from, other));
+        }
+    }
+
+    Ok(dfa)
+}
+
+type PartialDerivatives = (
+    Vec<bool>,
+    Vec<(usize, crate::automaton::label::NfaLabel, usize)>,
+);
+
+pub fn partial_derivatives(
+    ast: &crate::parser::AstNode,
+    max_states: usize,
+    max_transitions: usize,
+) -> crate::Result<PartialDerivatives> {
+    let mut arena = AstArena::new();
+    let start = from_parser(&mut arena, ast);
+
+    let mut ids = foldhash::HashMap::new();
+    ids.insert(start, 0);
+    let mut states = vec![start];
+    let mut accepts = Vec::new();
+    let mut transitions = Vec::new();
+    let mut form = Vec::new();
+    let mut seen = foldhash::HashSet::new();
+    while let Some(&state) = states.get(accepts.len()) {
+        let from = accepts.len();
+        accepts.push(arena.nullable_of(state));
+
+        form.clear();
+        seen.clear();
+        linear_form(&mut arena, state, &mut form);
+        for &(label, next) in &form {
+            if !seen.insert((label, next)) {
+                continue;
+            }
+            let to = match ids.get(&next) {
+                Some(&to) => to,
+                None => {
+                    crate::config::Limit::NfaStates.check(states.len() + 1, max_states)?;
+                    ids.insert(next, states.len());
+                    states.push(next);
+                    states.len() - 1
+                }
+            };
+            crate::config::Limit::NfaTransitions.check(transitions.len() + 1, max_transitions)?;
+            transitions.push((from, label, to));
+        }
+    }
+
+    Ok((accepts, transitions))
+}
+
+fn linear_form(
     arena: &mut AstArena,
     id: AstId,
-    c: char,
-    memo: &mut foldhash::HashMap<(AstId, char), AstId>,
-) -> AstId {
-    if let Some(&cached) = memo.get(&(id, c)) {
-        return cached;
+    form: &mut Vec<(crate::automaton::label::NfaLabel, AstId)>,
+) {
+    match arena.kind(id).clone() {
+        NodeKind::Empty | NodeKind::Epsilon => {}
+        NodeKind::Char(c) => {
+            form.push((crate::automaton::label::NfaLabel::Char(c), arena.epsilon()))
+        }
+        NodeKind::Class(class) => form.push((
+            crate::automaton::label::NfaLabel::Class(class),
+            arena.epsilon(),
+        )),
+        NodeKind::Question(inner) => linear_form(arena, inner, form),
+        NodeKind::Or(left, right) => {
+            linear_form(arena, left, form);
+            linear_form(arena, right, form);
+        }
+        NodeKind::Seq(left, right) => {
+            let from = form.len();
+            linear_form(arena, left, form);
+            for (_, term) in &mut form[from..] {
+                *term = mk_seq(arena, *term, right);
+            }
+            if arena.nullable_of(left) {
+                linear_form(arena, right, form);
+            }
+        }
+        NodeKind::Plus(inner) | NodeKind::Star(inner) => {
+            let star = mk_star(arena, inner);
+            let from = form.len();
+            linear_form(arena, inner, form);
+            for (_, term) in &mut form[from..] {
+                *term = mk_seq(arena, *term, star);
+            }
+        }
+        NodeKind::And(_, _) | NodeKind::Not(_) => unreachable!(),
     }
-
-    let result = derivative_id(arena, id, c);
-    memo.insert((id, c), result);
-    result
 }
 
 fn derivative_id(arena: &mut AstArena, id: AstId, c: char) -> AstId {
@@ -304,6 +886,16 @@ fn derivative_id(arena: &mut AstArena, id: AstId, c: char) -> AstId {
 
             mk_or(arena, first, second)
         }
+        NodeKind::And(left, right) => {
+            let (left, right) = (*left, *right);
+            let dl = derivative_id(arena, left, c);
+            let dr = derivative_id(arena, right, c);
+            mk_and(arena, dl, dr)
+        }
+        NodeKind::Not(inner) => {
+            let inner = derivative_id(arena, *inner, c);
+            mk_not(arena, inner)
+        }
     }
 }
 
@@ -321,10 +913,11 @@ fn structural_size_dfs(arena: &AstArena, id: AstId, visited: &mut foldhash::Hash
     }
 
     match arena.kind(id) {
-        NodeKind::Plus(inner) | NodeKind::Star(inner) | NodeKind::Question(inner) => {
-            structural_size_dfs(arena, *inner, visited)
-        }
-        NodeKind::Or(left, right) | NodeKind::Seq(left, right) => {
+        NodeKind::Plus(inner)
+        | NodeKind::Star(inner)
+        | NodeKind::Question(inner)
+        | NodeKind::Not(inner) => structural_size_dfs(arena, *inner, visited),
+        NodeKind::Or(left, right) | NodeKind::Seq(left, right) | NodeKind::And(left, right) => {
             structural_size_dfs(arena, *left, visited);
             structural_size_dfs(arena, *right, visited);
         }
@@ -386,157 +979,56 @@ fn mk_or(arena: &mut AstArena, left: AstId, right: AstId) -> AstId {
     if right == arena.empty() {
         return left;
     }
-    let (lo, hi) = ordered_pair(left, right);
-    arena.intern(NodeKind::Or(lo, hi))
-}
-
-fn ordered_pair(a: AstId, b: AstId) -> (AstId, AstId) {
-    if a > b { (b, a) } else { (a, b) }
-}
-
-fn match_fallback(original: &crate::parser::AstNode, input: &str) -> bool {
-    let mut ast = original.clone();
-    for ch in input.chars() {
-        ast = derivative_parser(&ast, ch);
+    if left == arena.universal() || right == arena.universal() {
+        return arena.universal();
     }
-    contain_epsilon_parser(&ast)
+    mk_set(arena, left, right, true)
 }
 
-fn derivative_parser(ast: &crate::parser::AstNode, c: char) -> crate::parser::AstNode {
-    let raw = match ast {
-        crate::parser::AstNode::Empty | crate::parser::AstNode::Epsilon => {
-            crate::parser::AstNode::Empty
-        }
-        crate::parser::AstNode::Char(ch) => {
-            if *ch == c {
-                crate::parser::AstNode::Epsilon
-            } else {
-                crate::parser::AstNode::Empty
-            }
-        }
-        crate::parser::AstNode::Class(class) => {
-            if class.matches(c) {
-                crate::parser::AstNode::Epsilon
-            } else {
-                crate::parser::AstNode::Empty
-            }
-        }
-        crate::parser::AstNode::Plus(inner) => crate::parser::AstNode::Seq(
-            Box::new(derivative_parser(inner, c)),
-            Box::new(crate::parser::AstNode::Star(inner.clone())),
-        ),
-        crate::parser::AstNode::Star(inner) => crate::parser::AstNode::Seq(
-            Box::new(derivative_parser(inner, c)),
-            Box::new(crate::parser::AstNode::Star(inner.clone())),
-        ),
-        crate::parser::AstNode::Question(inner) => derivative_parser(inner, c),
-        crate::parser::AstNode::Or(left, right) => crate::parser::AstNode::Or(
-            Box::new(derivative_parser(left, c)),
-            Box::new(derivative_parser(right, c)),
-        ),
-        crate::parser::AstNode::Seq(left, right) => crate::parser::AstNode::Or(
-            Box::new(crate::parser::AstNode::Seq(
-                Box::new(derivative_parser(left, c)),
-                Box::new((**right).clone()),
-            )),
-            Box::new(crate::parser::AstNode::Seq(
-                Box::new(delta_parser(left)),
-                Box::new(derivative_parser(right, c)),
-            )),
-        ),
-    };
-
-    normalize_parser(raw)
-}
-
-fn normalize_parser(ast: crate::parser::AstNode) -> crate::parser::AstNode {
-    match ast {
-        crate::parser::AstNode::Or(left, right) => {
-            let left = normalize_parser(*left);
-            let right = normalize_parser(*right);
-
-            if matches!(left, crate::parser::AstNode::Empty) {
-                return right;
-            }
-            if matches!(right, crate::parser::AstNode::Empty) {
-                return left;
-            }
-            if left == right {
-                return left;
-            }
-
-            crate::parser::AstNode::Or(Box::new(left), Box::new(right))
-        }
-        crate::parser::AstNode::Seq(left, right) => {
-            let left = normalize_parser(*left);
-            let right = normalize_parser(*right);
-
-            if matches!(left, crate::parser::AstNode::Empty)
-                || matches!(right, crate::parser::AstNode::Empty)
-            {
-                return crate::parser::AstNode::Empty;
-            }
-            if matches!(left, crate::parser::AstNode::Epsilon) {
-                return right;
-            }
-            if matches!(right, crate::parser::AstNode::Epsilon) {
-                return left;
-            }
-
-            crate::parser::AstNode::Seq(Box::new(left), Box::new(right))
-        }
-        crate::parser::AstNode::Plus(inner) => {
-            let inner = normalize_parser(*inner);
-            if matches!(inner, crate::parser::AstNode::Empty) {
-                crate::parser::AstNode::Empty
-            } else {
-                crate::parser::AstNode::Plus(Box::new(inner))
-            }
-        }
-        crate::parser::AstNode::Star(inner) => {
-            let inner = normalize_parser(*inner);
-            if matches!(inner, crate::parser::AstNode::Empty)
-                || matches!(inner, crate::parser::AstNode::Epsilon)
-            {
-                crate::parser::AstNode::Epsilon
-            } else {
-                crate::parser::AstNode::Star(Box::new(inner))
-            }
-        }
-        crate::parser::AstNode::Question(inner) => {
-            let inner = normalize_parser(*inner);
-            if matches!(inner, crate::parser::AstNode::Empty) {
-                crate::parser::AstNode::Epsilon
-            } else {
-                crate::parser::AstNode::Question(Box::new(inner))
-            }
-        }
-        other => other,
+fn mk_and(arena: &mut AstArena, left: AstId, right: AstId) -> AstId {
+    if left == right {
+        return left;
+    }
+    if left == arena.empty() || right == arena.empty() {
+        return arena.empty();
+    }
+    if left == arena.universal() {
+        return right;
+    }
+    if right == arena.universal() {
+        return left;
     }
+    mk_set(arena, left, right, false)
 }
 
-fn delta_parser(ast: &crate::parser::AstNode) -> crate::parser::AstNode {
-    if contain_epsilon_parser(ast) {
-        crate::parser::AstNode::Epsilon
-    } else {
-        crate::parser::AstNode::Empty
+fn mk_not(arena: &mut AstArena, inner: AstId) -> AstId {
+    match arena.kind(inner) {
+        NodeKind::Not(inner) => *inner,
+        _ => arena.intern(NodeKind::Not(inner)),
     }
 }
 
-fn contain_epsilon_parser(ast: &crate::parser::AstNode) -> bool {
-    match ast {
-        crate::parser::AstNode::Epsilon
-        | crate::parser::AstNode::Star(_)
-        | crate::parser::AstNode::Question(_) => true,
-        crate::parser::AstNode::Empty
-        | crate::parser::AstNode::Char(_)
-        | crate::parser::AstNode::Class(_) => false,
-        crate::parser::AstNode::Plus(inner) => contain_epsilon_parser(inner),
-        crate::parser::AstNode::Or(left, right) => {
-            contain_epsilon_parser(left) || contain_epsilon_parser(right)
-        }
-        crate::parser::AstNode::Seq(left, right) => {
-            contain_epsilon_parser(left) && contain_epsilon_parser(right)
+fn mk_set(arena: &mut AstArena, left: AstId, right: AstId, or: bool) -> AstId {
+    let mut operands = Vec::new();
+    for mut id in [left, right] {
+        while let (NodeKind::Or(head, tail), true) | (NodeKind::And(head, tail), false) =
+            (arena.kind(id), or)
+        {
+            operands.push(*head);
+            id = *tail;
         }
+        operands.push(id);
     }
+    operands.sort_unstable();
+    operands.dedup();
+
+    let mut operands = operands.into_iter().rev();
+    let last = operands.next().unwrap();
+    operands.fold(last, |tail, head| {
+        arena.intern(if or {
+            NodeKind::Or(head, tail)
+        } else {
+            NodeKind::And(head, tail)
+        })
+    })
 }
@@ -1,4 +1,4 @@
-#[derive(thiserror::Error, Debug)]
+#[derive(thiserror::Error, Clone, Debug)]
 pub enum Error {
     #[error("unexpected character: {0}")]
     UnexpectedChar(crate::lexer::Token),
@@ -14,6 +14,16 @@ pub enum Error {
     CompileError,
     #[error("invalid method: {0}")]
     InvalidMethod(String),
+    #[error("{0} limit of {1} exceeded")]
+    LimitExceeded(crate::config::Limit, usize),
+    #[error("invalid serialized data: {0}")]
+    Deserialize(&'static str),
+    #[error("pattern is not an alternation of literals")]
+    NotLiteral,
+    #[error("{0} matching is not supported by the {1} engine")]
+    UnsupportedMatchKind(crate::config::MatchKind, crate::Method),
+    #[error("pattern has {0} positions, the shift-and engine supports at most {1}")]
+    TooManyPositions(usize, usize),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,65 +1,599 @@
 mod automaton;
 mod charclass;
+mod config;
 mod derivative;
 mod error;
 mod lexer;
+mod literal;
+mod meta;
 mod parser;
+mod pool;
+mod serialize;
+mod stats;
 mod vm;
 
+pub use config::{DfaConstruction, DfaLayout, Limit, MatchKind, NfaConstruction};
 pub use error::{Error, Result};
+pub use meta::{Decision, Reason};
+pub use serialize::Aligned;
+pub use stats::{Arena, Memory, Minimization, NfaSize, Stats, TransitionCache};
 
 #[global_allocator]
 static MIMALLOC: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Method {
+    Dfa,
+    LazyDfa,
+    Tdfa,
+    Vm,
+    Derivative,
+    Antimirov,
+    ShiftAnd,
+    AhoCorasick,
+    Auto,
+}
+
+impl std::str::FromStr for Method {
+    type Err = Error;
+
+    fn from_str(method: &str) -> Result<Method> {
+        match method {
+            "dfa" => Ok(Method::Dfa),
+            "lazy" => Ok(Method::LazyDfa),
+            "tdfa" => Ok(Method::Tdfa),
+            "vm" => Ok(Method::Vm),
+            "derivative" => Ok(Method::Derivative),
+            "antimirov" => Ok(Method::Antimirov),
+            "shift-and" => Ok(Method::ShiftAnd),
+            "aho-corasick" => Ok(Method::AhoCorasick),
+            "auto" => Ok(Method::Auto),
+            _ => Err(Error::InvalidMethod(method.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Method::Dfa => write!(f, "dfa"),
+            Method::LazyDfa => write!(f, "lazy"),
+            Method::Tdfa => write!(f, "tdfa"),
+            Method::Vm => write!(f, "vm"),
+            Method::Derivative => write!(f, "derivative"),
+            Method::Antimirov => write!(f, "antimirov"),
+            Method::ShiftAnd => write!(f, "shift-and"),
+            Method::AhoCorasick => write!(f, "aho-corasick"),
+            Method::Auto => write!(f, "auto"),
+        }
+    }
+}
+
 #[derive(Debug)]
 enum Regex {
-    Dfa { dfa: automaton::dfa::Dfa },
-    Vm { vm: vm::Vm },
-    Derivative { derivative: derivative::Derivative },
+    Dfa {
+        dfa: automaton::dfa::Dfa<'static>,
+        reverse: Option<Box<automaton::dfa::Dfa<'static>>>,
+    },
+    LazyDfa {
+        dfa: automaton::lazy::LazyDfa,
+    },
+    Tdfa {
+        tdfa: automaton::dfa::TaggedDfa<'static>,
+        reverse: Option<Box<automaton::dfa::Dfa<'static>>>,
+    },
+    Vm {
+        vm: vm::Vm,
+    },
+    Derivative {
+        derivative: Box<derivative::Derivative>,
+    },
+    Antimirov {
+        antimirov: automaton::antimirov::Antimirov,
+    },
+    ShiftAnd {
+        shift_and: Box<automaton::shift_and::ShiftAnd>,
+    },
+    AhoCorasick {
+        ac: automaton::aho_corasick::AhoCorasick,
+    },
+    Meta {
+        meta: Box<meta::Meta>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct EngineBuilder {
+    method: Method,
+    config: config::Config,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        EngineBuilder {
+            method: Method::Auto,
+            config: config::Config::default(),
+        }
+    }
+}
+
+impl EngineBuilder {
+    pub fn new() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    pub fn method(mut self, method: Method) -> EngineBuilder {
+        self.method = method;
+        self
+    }
+
+    pub fn ast_node_limit(mut self, limit: usize) -> EngineBuilder {
+        self.config.max_ast_nodes = limit;
+        self
+    }
+
+    pub fn nfa_state_limit(mut self, limit: usize) -> EngineBuilder {
+        self.config.max_nfa_states = limit;
+        self
+    }
+
+    pub fn nfa_transition_limit(mut self, limit: usize) -> EngineBuilder {
+        self.config.max_nfa_transitions = limit;
+        self
+    }
+
+    pub fn dfa_state_limit(mut self, limit: usize) -> EngineBuilder {
+        self.config.max_dfa_states = limit;
+        self
+    }
+
+    pub fn dfa_size_limit(mut self, bytes: usize) -> EngineBuilder {
+        self.config.max_dfa_bytes = bytes;
+        self
+    }
+
+    pub fn program_limit(mut self, limit: usize) -> EngineBuilder {
+        self.config.max_program_len = limit;
+        self
+    }
+
+    pub fn arena_node_limit(mut self, limit: usize) -> EngineBuilder {
+        self.config.max_arena_nodes = limit;
+        self
+    }
+
+    pub fn minimize(mut self, yes: bool) -> EngineBuilder {
+        self.config.minimize = yes;
+        self
+    }
+
+    pub fn prefilter(mut self, yes: bool) -> EngineBuilder {
+        self.config.prefilter = yes;
+        self
+    }
+
+    pub fn match_kind(mut self, kind: MatchKind) -> EngineBuilder {
+        self.config.match_kind = kind;
+        self
+    }
+
+    pub fn dfa_layout(mut self, layout: DfaLayout) -> EngineBuilder {
+        self.config.dfa_layout = layout;
+        self
+    }
+
+    pub fn nfa_construction(mut self, construction: NfaConstruction) -> EngineBuilder {
+        self.config.nfa_construction = construction;
+        self
+    }
+
+    pub fn dfa_construction(mut self, construction: DfaConstruction) -> EngineBuilder {
+        self.config.dfa_construction = construction;
+        self
+    }
+
+    pub fn build(&self, input: &str) -> Result<Engine> {
+        let config = &self.config;
+        let mut lexer = lexer::Lexer::new(input);
+        let mut parser = parser::Parser::new(&mut lexer).with_limit(config.max_ast_nodes);
+        let ast = parser.parse()?;
+        let literal = match self.method {
+            Method::AhoCorasick => true,
+            Method::Auto => meta::literal_alternation(&ast).is_some(),
+            _ => false,
+        };
+        if !literal && config.match_kind != MatchKind::LeftmostLongest {
+            return Err(Error::UnsupportedMatchKind(config.match_kind, self.method));
+        }
+        let prefilter = (config.prefilter && !literal)
+            .then(|| literal::Prefilter::new(&ast))
+            .flatten();
+
+        let mut nfa_size = None;
+        let regex = match self.method {
+            Method::Dfa if config.dfa_construction == DfaConstruction::Derivative => {
+                let expr = derivative::Expr::Ast(ast);
+                let reverse =
+                    automaton::dfa::Dfa::reverse_from_derivatives(&expr, config)?.map(Box::new);
+                let dfa = automaton::dfa::Dfa::from_derivatives(&expr, config)?;
+
+                Regex::Dfa { dfa, reverse }
+            }
+            Method::Dfa => {
+                let reverse = automaton::dfa::Dfa::reverse_from_ast(&ast, config)?.map(Box::new);
+                let nfa = automaton::nfa::Nfa::build(ast, config)?;
+                let dfa = automaton::dfa::Dfa::from_nfa_with_limits(&nfa, config)?;
+                nfa_size = Some(nfa.size(config.nfa_construction));
+
+                Regex::Dfa { dfa, reverse }
+            }
+            Method::Tdfa => {
+                let reverse = automaton::dfa::Dfa::reverse_from_ast(&ast, config)?.map(Box::new);
+                let mut lexer = lexer::Lexer::new(input);
+                let mut parser =
+                    parser::Parser::with_captures(&mut lexer).with_limit(config.max_ast_nodes);
+                let nfa = automaton::nfa::Nfa::tagged(parser.parse()?, config)?;
+                let tdfa = automaton::dfa::TaggedDfa::new(&nfa, 2 * (parser.groups() + 1), config)?;
+                nfa_size = Some(nfa.size(NfaConstruction::Thompson));
+
+                Regex::Tdfa { tdfa, reverse }
+            }
+            Method::LazyDfa => {
+                let dfa = automaton::lazy::LazyDfa::new(ast, config)?;
+
+                Regex::LazyDfa { dfa }
+            }
+            Method::Vm => {
+                let vm = vm::Vm::new(ast, config)?;
+
+                Regex::Vm { vm }
+            }
+            Method::Derivative => {
+                let derivative = Box::new(derivative::Derivative::new(ast, config)?);
+
+                Regex::Derivative { derivative }
+            }
+            Method::Antimirov => {
+                let antimirov = automaton::antimirov::Antimirov::new(ast, config)?;
+                nfa_size = Some(antimirov.size());
+
+                Regex::Antimirov { antimirov }
+            }
+            Method::ShiftAnd => {
+                let shift_and = Box::new(automaton::shift_and::ShiftAnd::new(&ast)?);
+
+                Regex::ShiftAnd { shift_and }
+            }
+            Method::AhoCorasick => {
+                let literals = ast.literals().ok_or(Error::NotLiteral)?;
+                let ac = automaton::aho_corasick::AhoCorasick::new(&literals, config.match_kind);
+
+                Regex::AhoCorasick { ac }
+            }
+            Method::Auto => {
+                let meta = Box::new(meta::Meta::new(ast, config)?);
+
+                Regex::Meta { meta }
+            }
+        };
+
+        Ok(Engine {
+            groups: (self.method != Method::Tdfa)
+                .then(|| std::sync::Arc::new(vm::LazyGroups::new(input, config))),
+            nfa_size,
+            ..Engine::from_regex(regex, prefilter)
+        })
+    }
+
+    pub fn build_boolean(&self, all: &[&str], none: &[&str]) -> Result<Engine> {
+        let config = &self.config;
+        if config.match_kind != MatchKind::LeftmostLongest {
+            return Err(Error::UnsupportedMatchKind(config.match_kind, Method::Dfa));
+        }
+        let parse = |input: &str| -> Result<derivative::Expr> {
+            let mut lexer = lexer::Lexer::new(input);
+            let ast = parser::Parser::new(&mut lexer)
+                .with_limit(config.max_ast_nodes)
+                .parse()?;
+            Ok(derivative::Expr::Ast(ast))
+        };
+        let mut expr =
+            derivative::Expr::Not(Box::new(derivative::Expr::Ast(parser::AstNode::Empty)));
+        for input in all {
+            expr = derivative::Expr::And(Box::new(expr), Box::new(parse(input)?));
+        }
+        for input in none {
+            let excluded = derivative::Expr::Not(Box::new(parse(input)?));
+            expr = derivative::Expr::And(Box::new(expr), Box::new(excluded));
+        }
+
+        let reverse = automaton::dfa::Dfa::reverse_from_derivatives(&expr, config)?.map(Box::new);
+        let dfa = automaton::dfa::Dfa::from_derivatives(&expr, config)?;
+        Ok(Engine {
+            groups: Some(std::sync::Arc::new(vm::LazyGroups::none())),
+            ..Engine::from_regex(Regex::Dfa { dfa, reverse }, None)
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    start: usize,
+    end: usize,
+}
+
+impl Match {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Captures {
+    slots: Vec<Option<usize>>,
+}
+
+impl Captures {
+    pub fn get(&self, index: usize) -> Option<Match> {
+        match self.slots.get(2 * index..2 * index + 2)? {
+            &[Some(start), Some(end)] => Some(Match { start, end }),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match>> + '_ {
+        (0..self.slots.len() / 2).map(|index| self.get(index))
+    }
+}
+
+#[derive(Debug)]
+enum CacheKind {
+    None,
+    LazyDfa(Box<automaton::lazy::LazyCache>),
+    Vm(vm::Cache),
+    Derivative(derivative::DerivativeCache),
+    Antimirov(automaton::antimirov::Threads),
 }
 
 #[derive(Debug)]
+pub struct Cache {
+    engine: usize,
+    kind: CacheKind,
+}
+
+static ENGINE_IDS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[derive(Clone, Debug)]
 pub struct Engine {
-    regex: Regex,
+    id: usize,
+    regex: std::sync::Arc<Regex>,
+    prefilter: Option<std::sync::Arc<literal::Prefilter>>,
+    groups: Option<std::sync::Arc<vm::LazyGroups>>,
+    nfa_size: Option<NfaSize>,
 }
 
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Engine>();
+    assert_send_sync::<Cache>();
+};
+
 impl Engine {
     pub fn new(input: &str, method: &'static str) -> Result<Engine> {
-        let mut lexer = lexer::Lexer::new(input);
-        let mut parser = parser::Parser::new(&mut lexer);
-        let ast = parser.parse()?;
+        Engine::with_method(input, method.parse()?)
+    }
 
-        match method {
-            "dfa" => {
-                let nfa =
-                    automaton::nfa::Nfa::new_from_node(ast, &mut automaton::nfa::NfaState::new())?;
-                let dfa = automaton::dfa::Dfa::from_nfa(&nfa);
-
-                Ok(Engine {
-                    regex: Regex::Dfa { dfa },
-                })
+    pub fn with_method(input: &str, method: Method) -> Result<Engine> {
+        EngineBuilder::new().method(method).build(input)
+    }
+
+    pub fn from_bytes(bytes: &'static [u8]) -> Result<Engine> {
+        match serialize::Reader::kind(bytes)? {
+            serialize::Kind::Dfa => {
+                let (dfa, rest) = automaton::dfa::Dfa::from_bytes_prefix(bytes)?;
+                let (tdfa, rest) = match serialize::Reader::kind(rest) {
+                    Ok(serialize::Kind::Tags) => {
+                        let (tdfa, rest) = automaton::dfa::TaggedDfa::from_bytes_prefix(bytes)?;
+                        (Some(tdfa), rest)
+                    }
+                    _ => (None, rest),
+                };
+                let reverse = match rest {
+                    [] => None,
+                    rest => Some(Box::new(automaton::dfa::Dfa::from_bytes(rest)?)),
+                };
+                if dfa.is_reverse() || reverse.as_ref().is_some_and(|dfa| !dfa.is_reverse()) {
+                    return Err(Error::Deserialize("unexpected DFA direction"));
+                }
+
+                let regex = match tdfa {
+                    Some(tdfa) => Regex::Tdfa { tdfa, reverse },
+                    None => Regex::Dfa { dfa, reverse },
+                };
+                Ok(Engine::from_regex(regex, None))
             }
-            "vm" => {
-                let vm = vm::Vm::new(ast)?;
+            serialize::Kind::Program => {
+                let vm = vm::Vm::from_bytes(bytes)?;
 
-                Ok(Engine {
-                    regex: Regex::Vm { vm },
-                })
+                Ok(Engine::from_regex(Regex::Vm { vm }, None))
             }
-            "derivative" => {
-                let derivative = derivative::Derivative::new(ast);
+            serialize::Kind::Tags => Err(Error::Deserialize("unexpected automaton kind")),
+        }
+    }
 
-                Ok(Engine {
-                    regex: Regex::Derivative { derivative },
-                })
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        match &*self.regex {
+            Regex::Dfa { dfa, reverse } => {
+                let mut bytes = dfa.to_bytes();
+                if let Some(reverse) = reverse {
+                    bytes.extend(reverse.to_bytes());
+                }
+                Some(bytes)
             }
-            _ => Err(Error::InvalidMethod(method.to_string())),
+            Regex::Tdfa { tdfa, reverse } => {
+                let mut bytes = tdfa.to_bytes();
+                if let Some(reverse) = reverse {
+                    bytes.extend(reverse.to_bytes());
+                }
+                Some(bytes)
+            }
+            Regex::Vm { vm } => Some(vm.to_bytes()),
+            _ => None,
+        }
+    }
+
+    fn from_regex(regex: Regex, prefilter: Option<literal::Prefilter>) -> Engine {
+        Engine {
+            id: ENGINE_IDS.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            regex: std::sync::Arc::new(regex),
+            prefilter: prefilter.map(std::sync::Arc::new),
+            groups: None,
+            nfa_size: None,
         }
     }
 
+    pub fn decision(&self) -> Option<&Decision> {
+        match &*self.regex {
+            Regex::Meta { meta } => Some(meta.decision()),
+            _ => None,
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let stats = match &*self.regex {
+            Regex::Dfa { dfa, reverse } => dfa.stats(reverse.as_deref()),
+            Regex::Tdfa { tdfa, reverse } => tdfa.stats(reverse.as_deref()),
+            Regex::Meta { meta } => meta.stats(),
+            Regex::Derivative { derivative } => derivative.stats(),
+            _ => Stats::default(),
+        };
+        stats.with_nfa(self.nfa_size)
+    }
+
+    pub fn create_cache(&self) -> Cache {
+        let kind = match &*self.regex {
+            Regex::LazyDfa { dfa } => CacheKind::LazyDfa(Box::new(dfa.create_cache())),
+            Regex::Vm { vm } => CacheKind::Vm(vm.create_cache()),
+            Regex::Derivative { derivative } => CacheKind::Derivative(derivative.create_cache()),
+            Regex::Antimirov { antimirov } => CacheKind::Antimirov(antimirov.create_cache()),
+            Regex::Meta { meta } => meta
+                .create_cache()
+                .map_or(CacheKind::None, |cache| CacheKind::LazyDfa(Box::new(cache))),
+            Regex::Dfa { .. }
+            | Regex::Tdfa { .. }
+            | Regex::ShiftAnd { .. }
+            | Regex::AhoCorasick { .. } => CacheKind::None,
+        };
+        Cache {
+            engine: self.id,
+            kind,
+        }
+    }
+
+    fn cache<'c>(&self, cache: &'c mut Cache) -> &'c mut CacheKind {
+        if cache.engine != self.id {
+            *cache = self.create_cache();
+        }
+        &mut cache.kind
+    }
+
+    fn search(
+        &self,
+        haystack: &str,
+        find: impl FnOnce(&str) -> Option<(usize, usize)>,
+    ) -> Option<Match> {
+        let from = match self
+            .prefilter(haystack)
+            .map(|prefilter| prefilter.search(haystack))
+        {
+            Some(literal::Search::NoMatch) => return None,
+            Some(literal::Search::Match(start, end)) => return Some(Match { start, end }),
+            Some(literal::Search::From(from)) => from,
+            None => 0,
+        };
+        let (start, end) = find(&haystack[from..])?;
+        Some(Match {
+            start: from + start,
+            end: from + end,
+        })
+    }
+
+    fn find_unfiltered(&self, haystack: &str) -> Option<(usize, usize)> {
+        match &*self.regex {
+            Regex::Dfa { dfa, reverse } => dfa.find(reverse.as_deref(), haystack),
+            Regex::Tdfa { tdfa, reverse } => tdfa.dfa().find(reverse.as_deref(), haystack),
+            Regex::LazyDfa { dfa } => dfa.find(haystack),
+            Regex::Vm { vm } => vm.find(haystack),
+            Regex::Derivative { derivative } => derivative.find(haystack),
+            Regex::Antimirov { antimirov } => antimirov.find(haystack),
+            Regex::ShiftAnd { shift_and } => shift_and.find(haystack),
+            Regex::AhoCorasick { ac } => ac.find(haystack),
+            Regex::Meta { meta } => meta.find(haystack),
+        }
+    }
+
+    pub fn find(&self, haystack: &str) -> Option<Match> {
+        self.search(haystack, |haystack| self.find_unfiltered(haystack))
+    }
+
+    pub fn find_with(&self, cache: &mut Cache, haystack: &str) -> Option<Match> {
+        let cache = self.cache(cache);
+        self.search(haystack, |haystack| match (&*self.regex, cache) {
+            (Regex::LazyDfa { dfa }, CacheKind::LazyDfa(cache)) => dfa.find_with(cache, haystack),
+            (Regex::Vm { vm }, CacheKind::Vm(cache)) => vm.find_with(cache, haystack),
+            (Regex::Derivative { derivative }, CacheKind::Derivative(cache)) => {
+                derivative.find_with(cache, haystack)
+            }
+            (Regex::Antimirov { antimirov }, CacheKind::Antimirov(cache)) => {
+                antimirov.find_with(cache, haystack)
+            }
+            (Regex::Meta { meta }, CacheKind::LazyDfa(cache)) => meta.find_with(cache, haystack),
+            _ => self.find_unfiltered(haystack),
+        })
+    }
+
+    pub fn captures(&self, haystack: &str) -> Option<Captures> {
+        let found = self.find(haystack)?;
+        let mut slots = match &*self.regex {
+            Regex::Tdfa { tdfa, .. } => tdfa.captures(&haystack[found.range()])?,
+            _ => self.groups.as_deref()?.captures(&haystack[found.range()])?,
+        };
+        for slot in slots.iter_mut().flatten() {
+            *slot += found.start;
+        }
+        slots[0] = Some(found.start);
+        slots[1] = Some(found.end);
+        Some(Captures { slots })
+    }
+
+    fn prefilter(&self, haystack: &str) -> Option<&literal::Prefilter> {
+        match &*self.regex {
+            Regex::Meta { meta } if !meta.prefilters(haystack) => None,
+            _ => self.prefilter.as_deref(),
+        }
+    }
+
+    fn prefiltered_is_match(&self, input: &str) -> Option<bool> {
+        self.prefilter(input)
+            .and_then(|prefilter| prefilter.is_match(input))
+    }
+
     pub fn is_match(&self, input: &str) -> bool {
-        match &self.regex {
-            Regex::Dfa { dfa } => dfa.is_match(input),
+        if let Some(is_match) = self.prefiltered_is_match(input) {
+            return is_match;
+        }
+        match &*self.regex {
+            Regex::Dfa { dfa, .. } => dfa.is_match(input),
+            Regex::Tdfa { tdfa, .. } => tdfa.dfa().is_match(input),
+            Regex::LazyDfa { dfa } => dfa.is_match(input),
             Regex::Vm { vm } => vm.is_match(input),
             Regex::Derivative { derivative } => {
                 if input.is_empty() {
@@ -67,6 +601,28 @@ impl Engine {
                 }
                 derivative.is_match(input)
             }
+            Regex::Antimirov { antimirov } => antimirov.is_match(input),
+            Regex::ShiftAnd { shift_and } => shift_and.is_match(input),
+            Regex::AhoCorasick { ac } => ac.is_match(input),
+            Regex::Meta { meta } => meta.is_match(input),
+        }
+    }
+
+    pub fn is_match_with(&self, cache: &mut Cache, input: &str) -> bool {
+        if let Some(is_match) = self.prefiltered_is_match(input) {
+            return is_match;
+        }
+        match (&*self.regex, self.cache(cache)) {
+            (Regex::LazyDfa { dfa }, CacheKind::LazyDfa(cache)) => dfa.is_match_with(cache, input),
+            (Regex::Vm { vm }, CacheKind::Vm(cache)) => vm.is_match_with(cache, input),
+            (Regex::Derivative { derivative }, CacheKind::Derivative(cache)) => {
+                derivative.is_match_with(cache, input)
+            }
+            (Regex::Antimirov { antimirov }, CacheKind::Antimirov(cache)) => {
+                antimirov.is_match_with(cache, input)
+            }
+            (Regex::Meta { meta }, CacheKind::LazyDfa(cache)) => meta.is_match_with(cache, input),
+            _ => self.is_match(input),
         }
     }
 }
@@ -119,6 +675,10 @@ mod tests {
         assert!(regex.is_match("aab"));
         assert!(regex.is_match("aaab"));
         assert!(!regex.is_match("a"));
+
+        let regex = Engine::new("a+", "dfa").unwrap();
+        assert!(regex.is_match("a"));
+        assert!(!regex.is_match(""));
     }
 
     #[test]
@@ -218,6 +778,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn regex_lazy_dfa() {
+        let regex = Engine::new("a|b*", "lazy").unwrap();
+        assert!(regex.is_match("a"));
+        assert!(regex.is_match("b"));
+        assert!(regex.is_match("bbb"));
+        assert!(!regex.is_match("c"));
+
+        let regex = Engine::new("(p(erl|ython|hp)|ruby)", "lazy").unwrap();
+        assert!(regex.is_match("perl"));
+        assert!(regex.is_match("php"));
+        assert!(!regex.is_match("rust"));
+
+        let regex = Engine::new("ab(cd|)", "lazy").unwrap();
+        assert!(regex.is_match("abcd"));
+        assert!(regex.is_match("ab"));
+        assert!(!regex.is_match("abc"));
+
+        let regex = Engine::new(r"a\|b\*", "lazy").unwrap();
+        assert!(regex.is_match("a|b*"));
+        assert!(!regex.is_match("ab"));
+
+        let regex = Engine::new("正規表現(太郎|次郎)", "lazy").unwrap();
+        assert!(regex.is_match("正規表現太郎"));
+        assert!(!regex.is_match("正規表現三郎"));
+
+        let regex = Engine::new("あい|♥", "lazy").unwrap();
+        assert!(regex.is_match("♥"));
+        assert!(!regex.is_match("♡"));
+
+        let pattern = format!("(a|b)*a{}", "(a|b)".repeat(20));
+        let regex = Engine::new(&pattern, "lazy").unwrap();
+        assert!(regex.is_match(&format!("bba{}", "b".repeat(20))));
+        assert!(!regex.is_match(&format!("aab{}", "a".repeat(20))));
+
+        for test in ["a(b", "*", ")c", "+"] {
+            assert!(Engine::new(test, "lazy").is_err());
+        }
+    }
+
     #[test]
     fn regex_vm() {
         let regex = Engine::new("a|b*", "vm").unwrap();
@@ -504,6 +1104,95 @@ mod tests {
         }
     }
 
+    #[test]
+    fn regex_auto() {
+        let regex = Engine::new("a|b*", "auto").unwrap();
+        assert!(regex.is_match("a"));
+        assert!(regex.is_match("bbb"));
+        assert!(!regex.is_match("c"));
+
+        let regex = Engine::new("(p(erl|ython|hp)|ruby)", "auto").unwrap();
+        assert!(regex.is_match("perl"));
+        assert!(regex.is_match("ruby"));
+        assert!(!regex.is_match("rust"));
+
+        let regex = Engine::new("正規表現(太郎|次郎)", "auto").unwrap();
+        assert!(regex.is_match("正規表現太郎"));
+        assert!(!regex.is_match("正規表現三郎"));
+
+        let regex = Engine::new(r"\w+", "auto").unwrap();
+        assert!(regex.is_match("foo_bar"));
+        assert!(!regex.is_match("-"));
+
+        for test in ["a(b", "*", ")c", "+"] {
+            assert!(Engine::new(test, "auto").is_err());
+        }
+    }
+
+    #[test]
+    fn auto_decision() {
+        let regex = Engine::new("a+b", "auto").unwrap();
+        let decision = regex.decision().unwrap();
+        assert_eq!(decision.method(), Method::Dfa);
+        assert!(decision.to_string().starts_with("dfa; "));
+
+        let pattern = format!("(a|b)*a{}", "(a|b)".repeat(12));
+        let regex = Engine::with_method(&pattern, Method::Auto).unwrap();
+        assert_eq!(regex.decision().unwrap().method(), Method::LazyDfa);
+        assert!(regex.is_match(&format!("a{}", "b".repeat(12))));
+
+        let regex = Engine::new("a+b", "dfa").unwrap();
+        assert!(regex.decision().is_none());
+    }
+
+    #[test]
+    fn method_from_str() {
+        assert_eq!("dfa".parse::<Method>().unwrap(), Method::Dfa);
+        assert_eq!("lazy".parse::<Method>().unwrap(), Method::LazyDfa);
+        assert_eq!("tdfa".parse::<Method>().unwrap(), Method::Tdfa);
+        assert_eq!("vm".parse::<Method>().unwrap(), Method::Vm);
+        assert_eq!("derivative".parse::<Method>().unwrap(), Method::Derivative);
+        assert_eq!("antimirov".parse::<Method>().unwrap(), Method::Antimirov);
+        assert_eq!("shift-and".parse::<Method>().unwrap(), Method::ShiftAnd);
+        assert_eq!("auto".parse::<Method>().unwrap(), Method::Auto);
+        assert!("nfa".parse::<Method>().is_err());
+        for method in [
+            Method::Dfa,
+            Method::LazyDfa,
+            Method::Tdfa,
+            Method::Vm,
+            Method::Derivative,
+            Method::Antimirov,
+            Method::ShiftAnd,
+            Method::Auto,
+        ] {
+            assert_eq!(method.to_string().parse::<Method>().unwrap(), method);
+        }
+    }
+
+    #[test]
+    fn shared_across_threads() {
+        for method in ["dfa", "lazy", "vm", "derivative", "auto"] {
+            let pattern = format!("(p(erl|ython|hp)|ruby){}", "(x|y)?".repeat(20));
+            let engine = std::sync::Arc::new(Engine::new(&pattern, method).unwrap());
+            std::thread::scope(|scope| {
+                for _ in 0..4 {
+                    let engine = engine.clone();
+                    scope.spawn(move || {
+                        for _ in 0..100 {
+                            assert!(engine.is_match("pythonxy"));
+                            assert!(!engine.is_match("rust"));
+                        }
+                    });
+                }
+            });
+
+            let clone = engine.as_ref().clone();
+            assert!(std::sync::Arc::ptr_eq(&clone.regex, &engine.regex));
+            assert!(clone.is_match("ruby"));
+        }
+    }
+
     #[test]
     fn invalid_method_name() {
         let regex = Engine::new("a", "正規表現太郎");
@@ -558,6 +1247,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn metacharacters_lazy_dfa() {
+        let cases = [
+            (r"\d", &["0", "9"] as &[&str], &["", "a"] as &[&str]),
+            (
+                r"\w",
+                &["a", "Z", "_", "9"] as &[&str],
+                &["", "-", "♥"] as &[&str],
+            ),
+            (r"\s", &[" ", "\t"] as &[&str], &["", "a"] as &[&str]),
+            (
+                r"\d+",
+                &["0", "42", "999"] as &[&str],
+                &["a", "4a"] as &[&str],
+            ),
+            (
+                r"\w+",
+                &["a", "Z9", "foo_bar"] as &[&str],
+                &["-", "♥"] as &[&str],
+            ),
+            (r"\s+", &[" ", "\t\n"] as &[&str], &["a", "a b"] as &[&str]),
+            (
+                "a.b",
+                &["a b", "a\tb", "a0b"] as &[&str],
+                &["ab", "a\nb"] as &[&str],
+            ),
+        ];
+        for (pattern, yes, no) in cases {
+            assert_match_all("lazy", pattern, yes, no);
+        }
+    }
+
     #[test]
     fn metacharacters_vm() {
         let cases = [
@@ -621,4 +1342,760 @@ mod tests {
             assert_match_all("derivative", pattern, yes, no);
         }
     }
+
+    #[test]
+    fn size_limits() {
+        assert!(matches!(
+            EngineBuilder::new().ast_node_limit(4).build("abcde"),
+            Err(Error::LimitExceeded(Limit::AstNodes, 4))
+        ));
+        assert!(matches!(
+            EngineBuilder::new()
+                .method(Method::Dfa)
+                .nfa_state_limit(8)
+                .build("abcdefghij"),
+            Err(Error::LimitExceeded(Limit::NfaStates, 8))
+        ));
+        let words = (0..40)
+            .map(|index| format!("w{index}"))
+            .collect::<Vec<_>>()
+            .join("|");
+        let pattern = format!("({words})*");
+        for (method, construction) in [
+            (Method::Dfa, NfaConstruction::Glushkov),
+            (Method::Dfa, NfaConstruction::Antimirov),
+            (Method::Antimirov, NfaConstruction::Thompson),
+        ] {
+            let builder = EngineBuilder::new()
+                .method(method)
+                .nfa_construction(construction);
+            assert!(builder.clone().build(&pattern).is_ok());
+            assert!(matches!(
+                builder.nfa_transition_limit(10).build(&pattern),
+                Err(Error::LimitExceeded(Limit::NfaTransitions, 10))
+            ));
+        }
+        let pattern = format!("(a|b)*a{}", "(a|b)".repeat(12));
+        assert!(matches!(
+            EngineBuilder::new()
+                .method(Method::Dfa)
+                .dfa_state_limit(100)
+                .build(&pattern),
+            Err(Error::LimitExceeded(Limit::DfaStates, 100))
+        ));
+        assert!(matches!(
+            EngineBuilder::new()
+                .method(Method::Dfa)
+                .dfa_size_limit(4096)
+                .build(&pattern),
+            Err(Error::LimitExceeded(Limit::DfaBytes, 4096))
+        ));
+        assert!(matches!(
+            EngineBuilder::new()
+                .method(Method::Vm)
+                .program_limit(5)
+                .build("abcdefghij"),
+            Err(Error::LimitExceeded(Limit::ProgramLength, 5))
+        ));
+        assert!(
+            EngineBuilder::new()
+                .method(Method::Derivative)
+                .program_limit(4)
+                .build("abcdefgh")
+                .is_ok()
+        );
+        let regex = EngineBuilder::new()
+            .method(Method::Dfa)
+            .program_limit(4)
+            .build("(ab)(cd)(ef)(gh)(ij)(kl)")
+            .unwrap();
+        assert!(regex.is_match("abcdefghijkl"));
+        assert!(regex.captures("abcdefghijkl").is_none());
+        assert!(matches!(
+            regex.groups.as_deref().unwrap().get(),
+            Err(Error::LimitExceeded(Limit::ProgramLength, 4))
+        ));
+        let regex = EngineBuilder::new()
+            .method(Method::Tdfa)
+            .program_limit(4)
+            .build("(ab)(cd)(ef)(gh)(ij)(kl)")
+            .unwrap();
+        assert!(regex.captures("abcdefghijkl").is_some());
+
+        let regex = EngineBuilder::new()
+            .method(Method::Dfa)
+            .dfa_state_limit(100)
+            .build("(p(erl|ython|hp)|ruby)")
+            .unwrap();
+        assert!(regex.is_match("python"));
+    }
+
+    #[test]
+    fn auto_respects_dfa_limit() {
+        let regex = EngineBuilder::new()
+            .dfa_state_limit(4)
+            .build("(p(erl|ython|hp)|ruby)")
+            .unwrap();
+        assert_eq!(regex.decision().unwrap().method(), Method::LazyDfa);
+        assert!(regex.is_match("python"));
+        assert!(!regex.is_match("rust"));
+    }
+
+    #[test]
+    fn minimized_dfa() {
+        let regex = EngineBuilder::new()
+            .method(Method::Dfa)
+            .minimize(true)
+            .build("(a|b)*abb")
+            .unwrap();
+        let minimization = regex.stats().minimization().unwrap();
+        assert_eq!(minimization.states_after, 4);
+        assert!(minimization.states_saved() > 0);
+        assert!(regex.is_match("babb"));
+        assert!(!regex.is_match("abab"));
+
+        let regex = Engine::new("(a|b)*abb", "dfa").unwrap();
+        assert_eq!(regex.stats().minimization(), None);
+    }
+
+    #[test]
+    fn dfa_layouts() {
+        let pattern = format!("x{}y", "abcdefghij".repeat(20));
+        let dense = Engine::new(&pattern, "dfa").unwrap();
+        let sparse = EngineBuilder::new()
+            .method(Method::Dfa)
+            .dfa_layout(DfaLayout::Sparse)
+            .build(&pattern)
+            .unwrap();
+        let (dense_memory, sparse_memory) = (
+            dense.stats().memory().unwrap(),
+            sparse.stats().memory().unwrap(),
+        );
+        assert_eq!(dense_memory.layout, DfaLayout::Dense);
+        assert_eq!(sparse_memory.layout, DfaLayout::Sparse);
+        assert_eq!(dense_memory.states, sparse_memory.states);
+        assert_eq!(dense_memory.state_id_bytes, 2);
+        assert!(sparse_memory.table_bytes * 4 < dense_memory.table_bytes);
+        assert!(sparse.stats().reverse_memory().is_some());
+
+        let haystack = format!("--{pattern}--");
+        assert_eq!(sparse.find(&haystack), dense.find(&haystack));
+        assert!(sparse.is_match(&pattern));
+        assert!(!sparse.is_match(&pattern[1..]));
+
+        let bytes = serialize::leak_aligned(&sparse.to_bytes().unwrap());
+        let loaded = Engine::from_bytes(bytes).unwrap();
+        assert_eq!(loaded.find(&haystack).map(|m| m.range()), Some(2..204));
+
+        assert_eq!(Engine::new("a", "vm").unwrap().stats().memory(), None);
+    }
+
+    #[test]
+    fn derivative_dfa_construction() {
+        let pattern = r"(\w+)@(\w+)(\.\w+)+";
+        let subset = Engine::new(pattern, "dfa").unwrap();
+        let derivative = EngineBuilder::new()
+            .method(Method::Dfa)
+            .dfa_construction(DfaConstruction::Derivative)
+            .minimize(true)
+            .build(pattern)
+            .unwrap();
+        for haystack in ["mail me@example.com now", "a@b", "x@y.z.", "@.x"] {
+            assert_eq!(derivative.is_match(haystack), subset.is_match(haystack));
+            assert_eq!(derivative.find(haystack), subset.find(haystack));
+        }
+        assert_eq!(
+            derivative.captures("me@example.com").unwrap().get(2),
+            subset.captures("me@example.com").unwrap().get(2)
+        );
+
+        let engine = EngineBuilder::new()
+            .build_boolean(&[r"\w+", ".*\\d.*"], &["0.*", ".*9"])
+            .unwrap();
+        assert!(engine.is_match("a1"));
+        assert!(engine.is_match("1_2"));
+        assert!(!engine.is_match("abc"));
+        assert!(!engine.is_match("01"));
+        assert!(!engine.is_match("19"));
+        assert_eq!(engine.find("-- x1 --").map(|m| m.range()), Some(3..5));
+        assert!(engine.captures("a1").unwrap().get(1).is_none());
+
+        let engine = EngineBuilder::new().build_boolean(&[], &["a*"]).unwrap();
+        assert!(engine.is_match("b"));
+        assert!(engine.is_match("ab"));
+        assert!(!engine.is_match("aa"));
+        assert!(!engine.is_match(""));
+    }
+
+    #[test]
+    fn derivative_transition_cache() {
+        let regex = Engine::new("ab(cd|)ef|g*|h+", "derivative").unwrap();
+        assert!(regex.is_match("abcdef"));
+        let first = regex.stats().transition_cache().unwrap();
+        assert_eq!((first.hits, first.misses), (0, 6));
+        assert!(regex.is_match("abcdef"));
+        assert!(!regex.is_match("abcde"));
+        let second = regex.stats().transition_cache().unwrap();
+        assert_eq!((second.hits, second.misses), (11, 6));
+        assert_eq!(regex.find("xxabefyy").map(|m| m.range()), Some(0..0));
+
+        let mut lexer = lexer::Lexer::new("(a|b)*c");
+        let ast = parser::Parser::new(&mut lexer).parse().unwrap();
+        let derivative =
+            derivative::Derivative::with_capacity(ast, &config::Config::default(), 2).unwrap();
+        let mut cache = derivative.create_cache();
+        assert!(derivative.is_match_with(&mut cache, "abbac"));
+        assert!(!derivative.is_match_with(&mut cache, "abca"));
+        let stats = derivative.stats().transition_cache().unwrap();
+        assert!(stats.evictions > 0);
+        assert_eq!(stats.hits + stats.misses, 9);
+
+        let mut lexer = lexer::Lexer::new("a+|bc|de");
+        let ast = parser::Parser::new(&mut lexer).parse().unwrap();
+        let derivative =
+            derivative::Derivative::with_capacity(ast, &config::Config::default(), 4).unwrap();
+        let mut cache = derivative.create_cache();
+        for input in ["aaa", "aaa", "bc", "de"] {
+            assert!(derivative.is_match_with(&mut cache, input));
+        }
+        let before = derivative.stats().transition_cache().unwrap();
+        assert_eq!(before.evictions, 1);
+        assert!(derivative.is_match_with(&mut cache, "aaa"));
+        let after = derivative.stats().transition_cache().unwrap();
+        assert_eq!(after.misses, before.misses);
+
+        let regex = Engine::new(r"\w+", "derivative").unwrap();
+        assert!(regex.is_match("abcdefghijklmnopqrstuvwxyz_0123456789"));
+        let stats = regex.stats().transition_cache().unwrap();
+        assert_eq!((stats.hits, stats.misses), (35, 2));
+        assert_eq!(
+            Engine::new("a", "vm").unwrap().stats().transition_cache(),
+            None
+        );
+    }
+
+    #[test]
+    fn derivative_arena_limit() {
+        let pattern = format!("(a|b)*a{}", "(a|b)".repeat(10));
+        let regex = EngineBuilder::new()
+            .method(Method::Derivative)
+            .arena_node_limit(200)
+            .build(&pattern)
+            .unwrap();
+        let vm = Engine::new(&pattern, "vm").unwrap();
+        for i in 0..200u32 {
+            let input: String = format!("{:016b}", i.wrapping_mul(2654435761))
+                .chars()
+                .map(|bit| if bit == '1' { 'a' } else { 'b' })
+                .collect();
+            assert_eq!(regex.is_match(&input), vm.is_match(&input), "{input}");
+            assert_eq!(regex.find(&input), vm.find(&input), "{input}");
+        }
+        let arena = regex.stats().arena().unwrap();
+        assert!(arena.compactions > 0);
+        assert!(arena.nodes <= 200);
+        assert!(matches!(
+            EngineBuilder::new()
+                .method(Method::Derivative)
+                .arena_node_limit(20)
+                .build(&pattern),
+            Err(Error::LimitExceeded(Limit::ArenaNodes, 20))
+        ));
+        assert!(
+            Engine::new(&pattern, "derivative")
+                .unwrap()
+                .stats()
+                .arena()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn shift_and() {
+        for pattern in [
+            r"(a|ab)*(\w|b)(a|.)*c",
+            "a|b*",
+            "ab(cd|)",
+            "正規表現(太郎|次郎)",
+            r"\s+x?",
+            "(p(erl|ython|hp)|ruby)",
+        ] {
+            let shift_and = Engine::new(pattern, "shift-and").unwrap();
+            let vm = Engine::new(pattern, "vm").unwrap();
+            for input in [
+                "",
+                "abc",
+                "ababac",
+                "xxabbc",
+                "abcd",
+                "bbb",
+                " x ",
+                "正規表現次郎",
+                "a python",
+            ] {
+                assert_eq!(shift_and.is_match(input), vm.is_match(input), "{input:?}");
+                assert_eq!(
+                    shift_and.find(input).map(|m| m.range()),
+                    vm.find(input).map(|m| m.range()),
+                    "{pattern:?} {input:?}"
+                );
+            }
+        }
+        assert!(matches!(
+            Engine::new(&"(a|b)".repeat(100), "shift-and"),
+            Err(Error::TooManyPositions(200, 128))
+        ));
+    }
+
+    #[test]
+    fn antimirov_nfa() {
+        for pattern in [
+            r"(a|ab)*(\w|b)(a|.)*c",
+            "a|b*",
+            "ab(cd|)",
+            "正規表現(太郎|次郎)",
+            r"\s+x?",
+        ] {
+            let antimirov = Engine::new(pattern, "antimirov").unwrap();
+            let vm = Engine::new(pattern, "vm").unwrap();
+            let mut cache = antimirov.create_cache();
+            for input in [
+                "",
+                "abc",
+                "ababac",
+                "xxabbc",
+                "abcd",
+                "bbb",
+                " x ",
+                "正規表現次郎",
+            ] {
+                assert_eq!(antimirov.is_match(input), vm.is_match(input), "{input:?}");
+                assert_eq!(
+                    antimirov.find(input).map(|m| (m.start(), m.end())),
+                    vm.find(input).map(|m| (m.start(), m.end())),
+                    "{pattern:?} {input:?}"
+                );
+                assert_eq!(
+                    antimirov.find_with(&mut cache, input).map(|m| m.range()),
+                    vm.find(input).map(|m| m.range()),
+                );
+            }
+        }
+
+        let size = |construction| {
+            EngineBuilder::new()
+                .method(Method::Dfa)
+                .nfa_construction(construction)
+                .build("(a|b)*abb")
+                .unwrap()
+                .stats()
+                .nfa()
+                .unwrap()
+        };
+        let thompson = size(NfaConstruction::Thompson);
+        let antimirov = size(NfaConstruction::Antimirov);
+        let glushkov = size(NfaConstruction::Glushkov);
+        assert_eq!(antimirov.construction, NfaConstruction::Antimirov);
+        assert_eq!(antimirov.epsilon_transitions, 0);
+        assert!(antimirov.states < thompson.states);
+        assert!(thompson.epsilon_transitions > 0);
+        assert_eq!(glushkov.construction, NfaConstruction::Glushkov);
+        assert_eq!(glushkov.epsilon_transitions, 0);
+        assert_eq!(glushkov.states, 6);
+    }
+
+    #[test]
+    fn derivative_fallback() {
+        let pattern = r"(a|ab)*(\w|b)(a|.)*c";
+        let mut lexer = lexer::Lexer::new(pattern);
+        let ast = parser::Parser::new(&mut lexer).parse().unwrap();
+        let derivative = derivative::Derivative::new(ast, &config::Config::default())
+            .unwrap()
+            .with_max_ast_size(8);
+        let vm = Engine::new(pattern, "vm").unwrap();
+        for input in [
+            "",
+            "abc",
+            "ababac",
+            "xxabbc",
+            "ab\nc",
+            "正規c",
+            "aaaaaaaaaaaaaaaaaaaab",
+        ] {
+            assert_eq!(derivative.is_match(input), vm.is_match(input), "{input:?}");
+            assert_eq!(
+                derivative.find(input),
+                vm.find(input).map(|m| (m.start(), m.end())),
+                "{input:?}"
+            );
+        }
+        let input = "ab".repeat(10_000);
+        assert!(!derivative.is_match(&input));
+        assert_eq!(derivative.find(&input), None);
+    }
+
+    #[test]
+    fn derivative_find_is_linear() {
+        let mut lexer = lexer::Lexer::new("a*b");
+        let ast = parser::Parser::new(&mut lexer).parse().unwrap();
+        let derivative = derivative::Derivative::new(ast, &config::Config::default()).unwrap();
+        let input = "a".repeat(20_000);
+        assert_eq!(derivative.find(&input), None);
+        let cache = derivative.stats().transition_cache().unwrap();
+        assert!(cache.hits + cache.misses <= 2 * input.len());
+        assert_eq!(derivative.find(&(input + "b")), Some((0, 20_001)));
+
+        for pattern in ["a*b", "(ab|b)*c?", "x(a|bc)+y|b", "a?", "正規(表|現)*"] {
+            let regex = Engine::new(pattern, "derivative").unwrap();
+            let vm = Engine::new(pattern, "vm").unwrap();
+            for input in ["", "xabcy", "zzbbc", "abab", "a正規表現b", "xbcbcay"] {
+                assert_eq!(
+                    regex.find(input).map(|m| m.range()),
+                    vm.find(input).map(|m| m.range()),
+                    "{pattern} {input:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn serialized_engines() {
+        for method in ["dfa", "vm"] {
+            let regex = Engine::new(r"(p(erl|ython|hp)|ruby)\d*|正規表現.", method).unwrap();
+            let bytes = serialize::leak_aligned(&regex.to_bytes().unwrap());
+            let loaded = Engine::from_bytes(bytes).unwrap();
+            for input in ["perl", "ruby42", "rust", "正規表現x", "正規表現", ""] {
+                assert_eq!(
+                    loaded.is_match(input),
+                    regex.is_match(input),
+                    "{method} {input}"
+                );
+            }
+        }
+
+        assert!(Engine::new("a", "lazy").unwrap().to_bytes().is_none());
+        assert!(matches!(
+            Engine::from_bytes(b"not a regex"),
+            Err(Error::Deserialize(_))
+        ));
+    }
+
+    #[test]
+    fn find() {
+        let cases = [
+            ("a+", "xxaaay", Some(2..5)),
+            ("ab|a", "xab", Some(1..3)),
+            ("b*", "abb", Some(0..0)),
+            ("a|b*", "cbb", Some(0..0)),
+            ("a+b", "aaacaab", Some(4..7)),
+            (r"\d+", "abc 123 45", Some(4..7)),
+            ("太郎|次郎", "正規表現次郎です", Some(12..18)),
+            (
+                "(p(erl|ython|hp)|ruby)",
+                "I like python and perl",
+                Some(7..13),
+            ),
+            ("abc", "ababd", None),
+            ("x.", "x\n", None),
+        ];
+        for method in ["dfa", "lazy", "vm", "derivative", "auto"] {
+            assert!(!Engine::new("a+", method).unwrap().is_match(""));
+            for (pattern, haystack, expected) in cases.clone() {
+                let regex = Engine::new(pattern, method).unwrap();
+                assert_eq!(
+                    regex.find(haystack).map(|m| m.range()),
+                    expected,
+                    "{method} {pattern} {haystack}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn captures() {
+        let spans = |regex: &Engine, haystack: &str| {
+            regex.captures(haystack).map(|caps| {
+                caps.iter()
+                    .map(|m| m.map(|m| m.range()))
+                    .collect::<Vec<_>>()
+            })
+        };
+        for method in ["dfa", "lazy", "vm", "derivative", "auto"] {
+            let regex = Engine::new(r"(\d+)-(\d+)", method).unwrap();
+            let groups = regex.groups.as_deref().unwrap();
+            assert!(!groups.is_built());
+            assert!(groups.get().unwrap().unwrap().is_one_pass());
+            assert_eq!(
+                spans(&regex, "tel 03-1234"),
+                Some(vec![Some(4..11), Some(4..6), Some(7..11)])
+            );
+            assert_eq!(spans(&regex, "tel 03-"), None);
+
+            let regex = Engine::new(r"(\w+)(\d+)", method).unwrap();
+            let groups = regex.groups.as_deref().unwrap();
+            assert!(!groups.get().unwrap().unwrap().is_one_pass());
+            assert_eq!(
+                spans(&regex, "-abc12-"),
+                Some(vec![Some(1..6), Some(1..5), Some(5..6)])
+            );
+
+            let regex = Engine::new("(a|ab)(c|bcd)(x)?", method).unwrap();
+            assert_eq!(
+                spans(&regex, "abcd"),
+                Some(vec![Some(0..4), Some(0..1), Some(1..4), None])
+            );
+
+            let regex = Engine::new("正規(表現)?", method).unwrap();
+            assert_eq!(
+                spans(&regex, "その正規表現"),
+                Some(vec![Some(6..18), Some(12..18)])
+            );
+
+            let regex = Engine::new("a+", method).unwrap();
+            assert!(regex.groups.as_deref().unwrap().get().unwrap().is_none());
+            assert_eq!(spans(&regex, "baa"), Some(vec![Some(1..3)]));
+        }
+
+        let bytes =
+            serialize::leak_aligned(&Engine::new("(a)(b)", "dfa").unwrap().to_bytes().unwrap());
+        let loaded = Engine::from_bytes(bytes).unwrap();
+        assert_eq!(loaded.find("xab").map(|m| m.range()), Some(1..3));
+        assert_eq!(spans(&loaded, "xab"), None);
+    }
+
+    #[test]
+    fn tagged_dfa_captures() {
+        let patterns = [
+            r"(\d+)-(\d+)",
+            r"(\w+)(\d+)",
+            "(a|ab)(c|bcd)(x)?",
+            "((a)|b)+c",
+            "正規(表現|.)*",
+        ];
+        let haystacks = [
+            "tel 03-1234",
+            "-abc12-",
+            "abcd",
+            "xxababc",
+            "その正規表現です",
+        ];
+        for pattern in patterns {
+            let tdfa = Engine::new(pattern, "tdfa").unwrap();
+            let vm = Engine::new(pattern, "vm").unwrap();
+            assert!(matches!(&*tdfa.regex, Regex::Tdfa { .. }));
+            assert!(tdfa.groups.is_none());
+            let loaded =
+                Engine::from_bytes(serialize::leak_aligned(&tdfa.to_bytes().unwrap())).unwrap();
+            for haystack in haystacks {
+                assert_eq!(
+                    tdfa.captures(haystack),
+                    vm.captures(haystack),
+                    "{pattern} {haystack}"
+                );
+                assert_eq!(loaded.captures(haystack), tdfa.captures(haystack));
+            }
+        }
+        assert!(matches!(
+            EngineBuilder::new()
+                .method(Method::Tdfa)
+                .dfa_state_limit(8)
+                .build("(a|b)*a(a|b)(a|b)(a|b)"),
+            Err(Error::LimitExceeded(Limit::DfaStates, 8))
+        ));
+    }
+
+    #[test]
+    fn caller_owned_caches() {
+        let patterns = ["(p(erl|ython|hp)|ruby)", r"\w+@\d+", "a*", "正規(表現)?"];
+        let haystacks = [
+            "",
+            "ruby",
+            "I like python",
+            "user@42",
+            "aaa",
+            "その正規表現です",
+        ];
+        for method in ["dfa", "lazy", "vm", "derivative", "tdfa", "auto"] {
+            for pattern in patterns {
+                let regex = Engine::new(pattern, method).unwrap();
+                let mut cache = regex.create_cache();
+                for haystack in haystacks {
+                    assert_eq!(
+                        regex.is_match_with(&mut cache, haystack),
+                        regex.is_match(haystack),
+                        "{method} {pattern} {haystack}"
+                    );
+                    assert_eq!(
+                        regex.find_with(&mut cache, haystack),
+                        regex.find(haystack),
+                        "{method} {pattern} {haystack}"
+                    );
+                }
+            }
+        }
+
+        let lazy = Engine::new("a+b", "lazy").unwrap();
+        let vm = Engine::new("(x|y)+z", "vm").unwrap();
+        let mut cache = lazy.create_cache();
+        assert!(matches!(cache.kind, CacheKind::LazyDfa(_)));
+        assert!(lazy.is_match_with(&mut cache, "aab"));
+        assert!(vm.is_match_with(&mut cache, "xz"));
+        assert!(matches!(cache.kind, CacheKind::Vm(_)));
+        assert_eq!(cache.engine, vm.id);
+        assert_eq!(vm.clone().create_cache().engine, vm.id);
+        assert_eq!(
+            lazy.find_with(&mut cache, "xaab").map(|m| m.range()),
+            Some(1..4)
+        );
+        assert!(matches!(cache.kind, CacheKind::LazyDfa(_)));
+    }
+
+    #[test]
+    fn find_without_reverse_dfa() {
+        let pattern = format!("{}a", "(a|b)".repeat(8));
+        let regex = EngineBuilder::new()
+            .method(Method::Dfa)
+            .dfa_state_limit(100)
+            .build(&pattern)
+            .unwrap();
+        assert!(matches!(&*regex.regex, Regex::Dfa { reverse: None, .. }));
+        let haystack = format!("xx{}yy", "ab".repeat(10));
+        assert_eq!(regex.find(&haystack).map(|m| m.range()), Some(2..11));
+
+        let bytes = serialize::leak_aligned(&regex.to_bytes().unwrap());
+        let loaded = Engine::from_bytes(bytes).unwrap();
+        assert_eq!(loaded.find(&haystack).map(|m| m.range()), Some(2..11));
+    }
+
+    #[test]
+    fn optional_does_not_repeat() {
+        for method in [
+            Method::Dfa,
+            Method::LazyDfa,
+            Method::Tdfa,
+            Method::Vm,
+            Method::Derivative,
+            Method::Antimirov,
+            Method::ShiftAnd,
+            Method::Auto,
+        ] {
+            let regex = EngineBuilder::new().method(method).build("a?").unwrap();
+            assert!(regex.is_match(""), "{method}");
+            assert!(regex.is_match("a"), "{method}");
+            assert!(!regex.is_match("aa"), "{method}");
+
+            let regex = EngineBuilder::new().method(method).build("(ab)?c").unwrap();
+            assert!(regex.is_match("abc"), "{method}");
+            assert!(!regex.is_match("ababc"), "{method}");
+            assert_eq!(
+                regex.find("ababc").map(|m| m.range()),
+                Some(2..5),
+                "{method}"
+            );
+        }
+    }
+
+    #[test]
+    fn prefilter() {
+        let patterns = [
+            "(p(erl|ython|hp)|ruby)",
+            r"ab\d+",
+            r"\w+needle\w+",
+            "x(a|b)*y",
+            "(ab|cd)e?",
+            "正規表現(太郎|次郎)",
+        ];
+        let haystacks = [
+            "",
+            "python",
+            "I like python and perl",
+            "ab12",
+            "xxab1 ab",
+            "aneedleb",
+            &"needle".repeat(20),
+            "xababy",
+            "zzcdeab",
+            "正規表現次郎",
+            "これは正規表現太郎です",
+        ];
+        for method in [
+            Method::Dfa,
+            Method::LazyDfa,
+            Method::Vm,
+            Method::Derivative,
+            Method::Auto,
+        ] {
+            for pattern in patterns {
+                let builder = EngineBuilder::new().method(method);
+                let filtered = builder.clone().build(pattern).unwrap();
+                let plain = builder.prefilter(false).build(pattern).unwrap();
+                assert!(plain.prefilter.is_none());
+                for haystack in haystacks {
+                    assert_eq!(
+                        filtered.is_match(haystack),
+                        plain.is_match(haystack),
+                        "{method} {pattern} {haystack}"
+                    );
+                    assert_eq!(
+                        filtered.find(haystack),
+                        plain.find(haystack),
+                        "{method} {pattern} {haystack}"
+                    );
+                }
+            }
+        }
+
+        let regex = Engine::new("(p(erl|ython|hp)|ruby)", "vm").unwrap();
+        assert_eq!(
+            regex.find("I like python and perl").map(|m| m.range()),
+            Some(7..13)
+        );
+        let regex = Engine::new(r"ab\d+", "derivative").unwrap();
+        assert_eq!(regex.find("xxab1 ab23").map(|m| m.range()), Some(2..5));
+    }
+
+    #[test]
+    fn regex_aho_corasick() {
+        let regex = Engine::new("perl|python|php|ruby", "aho-corasick").unwrap();
+        assert!(regex.is_match("python"));
+        assert!(!regex.is_match("pytho"));
+        assert_eq!(
+            regex.find("I like ruby and php").map(|m| m.range()),
+            Some(7..11)
+        );
+        assert!(matches!(
+            Engine::new("p(erl|ython)", "aho-corasick"),
+            Err(Error::NotLiteral)
+        ));
+
+        let keywords: Vec<String> = (0..3000).map(|i| format!("kw{i}")).collect();
+        let pattern = keywords.join("|");
+        let regex = Engine::new(&pattern, "auto").unwrap();
+        assert_eq!(regex.decision().unwrap().method(), Method::AhoCorasick);
+        assert!(regex.is_match("kw2999"));
+        assert_eq!(regex.find("xx kw12 kw1").map(|m| m.range()), Some(3..7));
+
+        let first = EngineBuilder::new()
+            .method(Method::AhoCorasick)
+            .match_kind(MatchKind::LeftmostFirst)
+            .build("ab|abcd")
+            .unwrap();
+        assert_eq!(first.find("xabcd").map(|m| m.range()), Some(1..3));
+        let regex = Engine::new("ab|abcd", "dfa").unwrap();
+        assert_eq!(regex.find("xabcd").map(|m| m.range()), Some(1..5));
+        assert!(matches!(
+            EngineBuilder::new()
+                .method(Method::Vm)
+                .match_kind(MatchKind::LeftmostFirst)
+                .build("ab|abcd"),
+            Err(Error::UnsupportedMatchKind(
+                MatchKind::LeftmostFirst,
+                Method::Vm
+            ))
+        ));
+
+        let pattern = keywords[..200].join("|");
+        let regex = Engine::new(&format!("x({pattern})y"), "vm").unwrap();
+        assert_eq!(regex.find("kw1y xkw199y").map(|m| m.range()), Some(5..12));
+    }
 }
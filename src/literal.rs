@@ -0,0 +1,525 @@
+const MAX_LITERALS: usize = 64;
+const MAX_EXACT_LITERALS: usize = 1 << 12;
+const MAX_LITERAL_LEN: usize = 16;
+const INNER_MIN_HAYSTACK: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Literals {
+    literals: Vec<String>,
+    exact: bool,
+}
+
+impl Literals {
+    fn exact(literals: Vec<String>) -> Self {
+        Literals {
+            literals,
+            exact: true,
+        }
+        .normalize()
+    }
+
+    fn any() -> Self {
+        Literals {
+            literals: vec![String::new()],
+            exact: false,
+        }
+    }
+
+    pub fn literals(&self) -> &[String] {
+        &self.literals
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    fn is_useful(&self) -> bool {
+        !self.literals.is_empty() && self.literals.iter().all(|literal| !literal.is_empty())
+    }
+
+    fn inexact(self) -> Self {
+        Literals {
+            exact: false,
+            ..self
+        }
+    }
+
+    fn normalize(mut self) -> Self {
+        for literal in &mut self.literals {
+            if let Some((at, _)) = literal.char_indices().nth(MAX_LITERAL_LEN) {
+                literal.truncate(at);
+                self.exact = false;
+            }
+        }
+        self.literals.sort_unstable();
+        self.literals.dedup();
+        self
+    }
+
+    fn union(self, other: Literals) -> Self {
+        let len = self.literals.len() + other.literals.len();
+        if len > MAX_EXACT_LITERALS || (!(self.exact && other.exact) && len > MAX_LITERALS) {
+            return Literals::any();
+        }
+        let mut literals = self.literals;
+        literals.extend(other.literals);
+        Literals {
+            literals,
+            exact: self.exact && other.exact,
+        }
+        .normalize()
+    }
+
+    fn concat(self, other: &Literals) -> Self {
+        if self.literals.len() * other.literals.len() > MAX_LITERALS {
+            return self.inexact();
+        }
+        let literals = self
+            .literals
+            .iter()
+            .flat_map(|left| {
+                other
+                    .literals
+                    .iter()
+                    .map(move |right| format!("{left}{right}"))
+            })
+            .collect();
+        Literals {
+            literals,
+            exact: self.exact && other.exact,
+        }
+        .normalize()
+    }
+
+    fn rconcat(self, other: &Literals) -> Self {
+        if self.literals.len() * other.literals.len() > MAX_LITERALS {
+            return self.inexact();
+        }
+        let literals = other
+            .literals
+            .iter()
+            .flat_map(|left| {
+                self.literals
+                    .iter()
+                    .map(move |right| format!("{left}{right}"))
+            })
+            .collect();
+        Literals {
+            literals,
+            exact: self.exact && other.exact,
+        }
+        .normalize_suffix()
+    }
+
+    fn normalize_suffix(mut self) -> Self {
+        for literal in &mut self.literals {
+            let len = literal.chars().count();
+            if len > MAX_LITERAL_LEN {
+                let (at, _) = literal.char_indices().nth(len - MAX_LITERAL_LEN).unwrap();
+                literal.drain(..at);
+                self.exact = false;
+            }
+        }
+        self.literals.sort_unstable();
+        self.literals.dedup();
+        self
+    }
+
+    fn better(self, other: Literals) -> Self {
+        match (self.is_useful(), other.is_useful()) {
+            (_, false) => self,
+            (false, true) => other,
+            (true, true) => {
+                if other.score() > self.score() {
+                    other
+                } else {
+                    self
+                }
+            }
+        }
+    }
+
+    fn score(&self) -> (usize, std::cmp::Reverse<usize>) {
+        let shortest = self.literals.iter().map(String::len).min().unwrap_or(0);
+        (shortest, std::cmp::Reverse(self.literals.len()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Info {
+    pub prefix: Literals,
+    pub suffix: Literals,
+    pub inner: Literals,
+}
+
+impl Info {
+    pub fn new(ast: &crate::parser::AstNode) -> Info {
+        match ast {
+            crate::parser::AstNode::Char(c) => Info::exact(vec![c.to_string()]),
+            crate::parser::AstNode::Class(class) => match class {
+                crate::charclass::CharClass::Digit | crate::charclass::CharClass::Space => {
+                    Info::exact(
+                        (0u8..128)
+                            .map(char::from)
+                            .filter(|&c| class.matches(c))
+                            .map(String::from)
+                            .collect(),
+                    )
+                }
+                crate::charclass::CharClass::Any | crate::charclass::CharClass::Word => Info::any(),
+            },
+            crate::parser::AstNode::Epsilon => Info::exact(vec![String::new()]),
+            crate::parser::AstNode::Empty => Info::exact(Vec::new()),
+            crate::parser::AstNode::Plus(node) => {
+                let node = Info::new(node);
+                Info {
+                    prefix: node.prefix.inexact(),
+                    suffix: node.suffix.inexact(),
+                    inner: node.inner,
+                }
+            }
+            crate::parser::AstNode::Star(_) => Info::any(),
+            crate::parser::AstNode::Capture(_, node) => Info::new(node),
+            crate::parser::AstNode::Question(node) => {
+                let node = Info::new(node);
+                let empty = Literals::exact(vec![String::new()]);
+                Info {
+                    prefix: node.prefix.union(empty.clone()),
+                    suffix: node.suffix.union(empty),
+                    inner: Literals::any(),
+                }
+            }
+            crate::parser::AstNode::Or(left, right) => {
+                let left = Info::new(left);
+                let right = Info::new(right);
+                let prefix = left.prefix.union(right.prefix);
+                let suffix = left.suffix.union(right.suffix);
+                let inner = left
+                    .inner
+                    .union(right.inner)
+                    .inexact()
+                    .better(prefix.clone().inexact())
+                    .better(suffix.clone().inexact());
+                Info {
+                    prefix,
+                    suffix,
+                    inner,
+                }
+            }
+            crate::parser::AstNode::Seq(left, right) => {
+                let left = Info::new(left);
+                let right = Info::new(right);
+                let prefix = if left.prefix.is_exact() {
+                    left.prefix.concat(&right.prefix)
+                } else {
+                    left.prefix
+                };
+                let suffix = if right.suffix.is_exact() {
+                    right.suffix.rconcat(&left.suffix)
+                } else {
+                    right.suffix
+                };
+                let joined = left.suffix.concat(&right.prefix).inexact();
+                let inner = left
+                    .inner
+                    .better(right.inner)
+                    .better(joined)
+                    .better(prefix.clone().inexact())
+                    .better(suffix.clone().inexact());
+                Info {
+                    prefix,
+                    suffix,
+                    inner,
+                }
+            }
+        }
+    }
+
+    fn exact(literals: Vec<String>) -> Info {
+        let prefix = Literals::exact(literals);
+        Info {
+            suffix: prefix.clone(),
+            inner: prefix.clone().inexact(),
+            prefix,
+        }
+    }
+
+    fn any() -> Info {
+        Info {
+            prefix: Literals::any(),
+            suffix: Literals::any(),
+            inner: Literals::any(),
+        }
+    }
+}
+
+pub fn required(ast: &crate::parser::AstNode) -> Option<Vec<String>> {
+    let inner = Info::new(ast).inner;
+    inner.is_useful().then_some(inner.literals)
+}
+
+#[derive(Debug)]
+enum Searcher {
+    Memmem(Box<memchr::memmem::Finder<'static>>),
+    Bytes {
+        first: [u8; 3],
+        literals: Vec<Vec<u8>>,
+    },
+    AhoCorasick(crate::automaton::aho_corasick::AhoCorasick),
+}
+
+impl Searcher {
+    fn new(literals: &Literals) -> Searcher {
+        if let [literal] = literals.literals() {
+            return Searcher::Memmem(Box::new(
+                memchr::memmem::Finder::new(literal.as_bytes()).into_owned(),
+            ));
+        }
+        let mut first: Vec<u8> = literals
+            .literals()
+            .iter()
+            .map(|literal| literal.as_bytes()[0])
+            .collect();
+        first.sort_unstable();
+        first.dedup();
+        if first.len() > 3 {
+            return Searcher::AhoCorasick(crate::automaton::aho_corasick::AhoCorasick::new(
+                literals.literals(),
+                crate::MatchKind::LeftmostLongest,
+            ));
+        }
+        Searcher::Bytes {
+            first: [0, 1, 2].map(|index| first.get(index).copied().unwrap_or(first[0])),
+            literals: literals
+                .literals()
+                .iter()
+                .map(|literal| literal.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    fn find(&self, haystack: &str) -> Option<usize> {
+        match self {
+            Searcher::Memmem(finder) => finder.find(haystack.as_bytes()),
+            Searcher::Bytes { first, literals } => {
+                let haystack = haystack.as_bytes();
+                let mut at = 0;
+                while at < haystack.len() {
+                    let rest = &haystack[at..];
+                    let found = memchr::memchr3(first[0], first[1], first[2], rest)?;
+                    let candidate = at + found;
+                    if literals
+                        .iter()
+                        .any(|literal| haystack[candidate..].starts_with(literal))
+                    {
+                        return Some(candidate);
+                    }
+                    at = candidate + 1;
+                }
+                None
+            }
+            Searcher::AhoCorasick(ac) => ac.find_start(haystack),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Search {
+    NoMatch,
+    Match(usize, usize),
+    From(usize),
+}
+
+#[derive(Debug)]
+pub struct Prefilter {
+    prefix: Option<(Literals, Searcher)>,
+    suffix: Option<Literals>,
+    inner: Option<Searcher>,
+}
+
+impl Prefilter {
+    pub fn new(ast: &crate::parser::AstNode) -> Option<Prefilter> {
+        let info = Info::new(ast);
+        let prefix = info.prefix.is_useful().then(|| {
+            let searcher = Searcher::new(&info.prefix);
+            (info.prefix, searcher)
+        });
+        let suffix = info.suffix.is_useful().then_some(info.suffix);
+        let inner = (info.inner.is_useful()
+            && prefix
+                .as_ref()
+                .is_none_or(|(prefix, _)| prefix.literals != info.inner.literals))
+        .then(|| Searcher::new(&info.inner));
+        if prefix.is_none() && suffix.is_none() && inner.is_none() {
+            return None;
+        }
+        Some(Prefilter {
+            prefix,
+            suffix,
+            inner,
+        })
+    }
+
+    pub fn is_match(&self, haystack: &str) -> Option<bool> {
+        if let Some((prefix, _)) = &self.prefix {
+            if prefix.is_exact() {
+                return Some(
+                    prefix
+                        .literals
+                        .binary_search_by(|l| l.as_str().cmp(haystack))
+                        .is_ok(),
+                );
+            }
+            if !prefix
+                .literals
+                .iter()
+                .any(|literal| haystack.starts_with(literal.as_str()))
+            {
+                return Some(false);
+            }
+        }
+        if let Some(suffix) = &self.suffix
+            && !suffix
+                .literals
+                .iter()
+                .any(|literal| haystack.ends_with(literal.as_str()))
+        {
+            return Some(false);
+        }
+        if let Some(inner) = &self.inner
+            && haystack.len() >= INNER_MIN_HAYSTACK
+            && inner.find(haystack).is_none()
+        {
+            return Some(false);
+        }
+        None
+    }
+
+    pub fn search(&self, haystack: &str) -> Search {
+        if let Some(inner) = &self.inner
+            && inner.find(haystack).is_none()
+        {
+            return Search::NoMatch;
+        }
+        if let Some((prefix, searcher)) = &self.prefix {
+            let Some(start) = searcher.find(haystack) else {
+                return Search::NoMatch;
+            };
+            if !prefix.is_exact() {
+                return Search::From(start);
+            }
+            let len = prefix
+                .literals
+                .iter()
+                .filter(|literal| haystack[start..].starts_with(literal.as_str()))
+                .map(String::len)
+                .max()
+                .unwrap();
+            return Search::Match(start, start + len);
+        }
+        Search::From(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pattern: &str) -> crate::parser::AstNode {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let mut parser = crate::parser::Parser::new(&mut lexer);
+        parser.parse().unwrap()
+    }
+
+    fn strings(literals: &[&str]) -> Vec<String> {
+        literals.iter().map(|literal| literal.to_string()).collect()
+    }
+
+    #[test]
+    fn prefix_and_suffix_sets() {
+        let info = Info::new(&parse("(p(erl|ython|hp)|ruby)"));
+        assert_eq!(
+            info.prefix.literals(),
+            strings(&["perl", "php", "python", "ruby"])
+        );
+        assert!(info.prefix.is_exact());
+        assert_eq!(info.suffix, info.prefix);
+
+        let info = Info::new(&parse(r"ab(c|d)\w*xy?"));
+        assert_eq!(info.prefix.literals(), strings(&["abc", "abd"]));
+        assert!(!info.prefix.is_exact());
+        assert_eq!(info.suffix.literals(), strings(&["x", "xy"]));
+        assert!(!info.suffix.is_exact());
+
+        let info = Info::new(&parse("a*b"));
+        assert!(!info.prefix.is_useful());
+        assert_eq!(info.suffix.literals(), strings(&["b"]));
+
+        let info = Info::new(&parse(&"a".repeat(40)));
+        assert_eq!(info.prefix.literals(), strings(&[&"a".repeat(16)]));
+        assert!(!info.prefix.is_exact());
+
+        let words = |count: usize| {
+            (0..count)
+                .map(|index| format!("w{index}"))
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        let info = Info::new(&parse(&words(MAX_EXACT_LITERALS)));
+        assert_eq!(info.prefix.literals().len(), MAX_EXACT_LITERALS);
+        assert!(info.prefix.is_exact());
+        let info = Info::new(&parse(&words(MAX_EXACT_LITERALS + 1)));
+        assert!(!info.prefix.is_useful());
+    }
+
+    #[test]
+    fn required_literals() {
+        assert_eq!(required(&parse("abc")), Some(strings(&["abc"])));
+        assert_eq!(required(&parse("a+bcd")), Some(strings(&["abcd"])));
+        assert_eq!(
+            required(&parse(r"\d+foo\w*barbaz")),
+            Some(strings(&["barbaz"]))
+        );
+        assert_eq!(
+            required(&parse("x(perl|php)y")),
+            Some(strings(&["xperly", "xphpy"]))
+        );
+        assert_eq!(required(&parse("(ab|cd)")), Some(strings(&["ab", "cd"])));
+        assert_eq!(
+            required(&parse(r"\w*(foo|bar)\w*")),
+            Some(strings(&["bar", "foo"]))
+        );
+        assert_eq!(required(&parse("a*")), None);
+        assert_eq!(required(&parse(r"\w")), None);
+        assert_eq!(
+            required(&parse("正規表現(太郎|次郎)")),
+            Some(strings(&["正規表現太郎", "正規表現次郎"]))
+        );
+    }
+
+    #[test]
+    fn prefilter() {
+        let prefilter = Prefilter::new(&parse("(p(erl|ython|hp)|ruby)")).unwrap();
+        assert_eq!(prefilter.is_match("python"), Some(true));
+        assert_eq!(prefilter.is_match("rust"), Some(false));
+        assert_eq!(
+            prefilter.search("I like python and perl"),
+            Search::Match(7, 13)
+        );
+        assert_eq!(prefilter.search("I like rust"), Search::NoMatch);
+
+        let prefilter = Prefilter::new(&parse(r"ab\d+")).unwrap();
+        assert_eq!(prefilter.is_match("ab12"), None);
+        assert_eq!(prefilter.is_match("ba12"), Some(false));
+        assert_eq!(prefilter.search("xxab1"), Search::From(2));
+
+        let prefilter = Prefilter::new(&parse(r"\w+needle\w+")).unwrap();
+        let hay = "x".repeat(INNER_MIN_HAYSTACK);
+        assert_eq!(prefilter.is_match(&hay), Some(false));
+        assert_eq!(prefilter.is_match(&format!("{hay}needle{hay}")), None);
+        assert_eq!(prefilter.search(&hay), Search::NoMatch);
+        assert_eq!(prefilter.search("aneedleb"), Search::From(0));
+
+        assert!(Prefilter::new(&parse(r"\w*")).is_none());
+    }
+}
@@ -0,0 +1,366 @@
+// Auto stops subset construction here unless `max_dfa_states` is lower.
+const DFA_STATE_BUDGET: usize = 1 << 10;
+const LAZY_MIN_STATES: usize = 64;
+const PREFILTER_MIN_HAYSTACK: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reason {
+    DfaWithinBudget { states: usize, budget: usize },
+    DfaOverBudget { limit: crate::Limit, max: usize },
+    LazyCacheTooSmall { states: usize, min: usize },
+    PrefilterFrom { bytes: usize },
+    RequiredLiterals(Vec<String>),
+    LiteralAlternation { patterns: usize },
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::DfaWithinBudget { states, budget } => {
+                write!(
+                    f,
+                    "subset construction produced {states} states (budget {budget})"
+                )
+            }
+            Reason::DfaOverBudget { limit, max } => {
+                write!(f, "subset construction exceeded the {limit} limit of {max}")
+            }
+            Reason::LazyCacheTooSmall { states, min } => {
+                write!(
+                    f,
+                    "the lazy DFA cache holds only {states} states of this pattern (minimum {min})"
+                )
+            }
+            Reason::PrefilterFrom { bytes } => {
+                write!(
+                    f,
+                    "the literal prefilter runs on haystacks of at least {bytes} bytes"
+                )
+            }
+            Reason::RequiredLiterals(literals) => {
+                write!(f, "haystacks must contain one of {literals:?}")
+            }
+            Reason::LiteralAlternation { patterns } => {
+                write!(f, "pattern is an alternation of {patterns} literals")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decision {
+    method: crate::Method,
+    reasons: Vec<Reason>,
+}
+
+impl Decision {
+    pub fn method(&self) -> crate::Method {
+        self.method
+    }
+
+    pub fn reasons(&self) -> &[Reason] {
+        &self.reasons
+    }
+}
+
+impl std::fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.method)?;
+        for reason in &self.reasons {
+            write!(f, "; {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum Strategy {
+    Dfa {
+        forward: crate::automaton::dfa::Dfa<'static>,
+        reverse: Option<Box<crate::automaton::dfa::Dfa<'static>>>,
+    },
+    LazyDfa(crate::automaton::lazy::LazyDfa),
+    Vm(crate::vm::Vm),
+    AhoCorasick(crate::automaton::aho_corasick::AhoCorasick),
+}
+
+#[derive(Debug)]
+pub struct Meta {
+    strategy: Strategy,
+    decision: Decision,
+}
+
+impl Meta {
+    pub fn new(ast: crate::parser::AstNode, config: &crate::config::Config) -> crate::Result<Meta> {
+        if let Some(literals) = literal_alternation(&ast) {
+            return Ok(Meta {
+                strategy: Strategy::AhoCorasick(crate::automaton::aho_corasick::AhoCorasick::new(
+                    &literals,
+                    config.match_kind,
+                )),
+                decision: Decision {
+                    method: crate::Method::AhoCorasick,
+                    reasons: vec![Reason::LiteralAlternation {
+                        patterns: literals.len(),
+                    }],
+                },
+            });
+        }
+        if config.match_kind != crate::config::MatchKind::LeftmostLongest {
+            return Err(crate::Error::UnsupportedMatchKind(
+                config.match_kind,
+                crate::Method::Auto,
+            ));
+        }
+
+        let mut reasons = Vec::new();
+
+        if let Some(literals) = crate::literal::required(&ast) {
+            reasons.push(Reason::RequiredLiterals(literals));
+        }
+
+        let nfa = crate::automaton::nfa::Nfa::build(ast.clone(), config)?;
+
+        let budget = crate::config::Config {
+            max_dfa_states: config.max_dfa_states.min(DFA_STATE_BUDGET),
+            ..config.clone()
+        };
+        let (method, strategy) =
+            match crate::automaton::dfa::Dfa::from_nfa_with_limits(&nfa, &budget) {
+                Ok(dfa) => {
+                    reasons.insert(
+                        0,
+                        Reason::DfaWithinBudget {
+                            states: dfa.state_count(),
+                            budget: budget.max_dfa_states,
+                        },
+                    );
+                    let reverse =
+                        crate::automaton::dfa::Dfa::reverse_from_ast(&ast, &budget)?.map(Box::new);
+                    (
+                        crate::Method::Dfa,
+                        Strategy::Dfa {
+                            forward: dfa,
+                            reverse,
+                        },
+                    )
+                }
+                Err(crate::Error::LimitExceeded(
+                    limit @ (crate::Limit::DfaStates | crate::Limit::DfaBytes),
+                    max,
+                )) => {
+                    reasons.insert(0, Reason::DfaOverBudget { limit, max });
+                    let states = crate::automaton::lazy::LazyDfa::cache_states(nfa.state_count());
+                    if states < LAZY_MIN_STATES {
+                        reasons.insert(
+                            1,
+                            Reason::LazyCacheTooSmall {
+                                states,
+                                min: LAZY_MIN_STATES,
+                            },
+                        );
+                        (
+                            crate::Method::Vm,
+                            Strategy::Vm(crate::vm::Vm::new(ast.clone(), config)?),
+                        )
+                    } else {
+                        (
+                            crate::Method::LazyDfa,
+                            Strategy::LazyDfa(crate::automaton::lazy::LazyDfa::new(
+                                ast.clone(),
+                                config,
+                            )?),
+                        )
+                    }
+                }
+                Err(err) => return Err(err),
+            };
+
+        // Scanning for literals costs about as much as running a DFA over a short haystack;
+        // the Pike VM is slow enough that the prefilter always pays off.
+        if method != crate::Method::Vm
+            && config.prefilter
+            && crate::literal::Prefilter::new(&ast).is_some()
+        {
+            reasons.push(Reason::PrefilterFrom {
+                bytes: PREFILTER_MIN_HAYSTACK,
+            });
+        }
+
+        Ok(Meta {
+            strategy,
+            decision: Decision { method, reasons },
+        })
+    }
+
+    pub fn decision(&self) -> &Decision {
+        &self.decision
+    }
+
+    pub fn stats(&self) -> crate::Stats {
+        match &self.strategy {
+            Strategy::Dfa { forward, reverse } => forward.stats(reverse.as_deref()),
+            Strategy::LazyDfa(_) | Strategy::Vm(_) | Strategy::AhoCorasick(_) => {
+                crate::Stats::default()
+            }
+        }
+    }
+
+    pub fn create_cache(&self) -> Option<crate::automaton::lazy::LazyCache> {
+        match &self.strategy {
+            Strategy::LazyDfa(dfa) => Some(dfa.create_cache()),
+            Strategy::Dfa { .. } | Strategy::Vm(_) | Strategy::AhoCorasick(_) => None,
+        }
+    }
+
+    pub fn prefilters(&self, haystack: &str) -> bool {
+        matches!(self.strategy, Strategy::Vm(_)) || haystack.len() >= PREFILTER_MIN_HAYSTACK
+    }
+
+    pub fn find(&self, input: &str) -> Option<(usize, usize)> {
+        match &self.strategy {
+            Strategy::Dfa { forward, reverse } => forward.find(reverse.as_deref(), input),
+            Strategy::LazyDfa(dfa) => dfa.find(input),
+            Strategy::Vm(vm) => vm.find(input),
+            Strategy::AhoCorasick(ac) => ac.find(input),
+        }
+    }
+
+    pub fn find_with(
+        &self,
+        cache: &mut crate::automaton::lazy::LazyCache,
+        input: &str,
+    ) -> Option<(usize, usize)> {
+        match &self.strategy {
+            Strategy::LazyDfa(dfa) => dfa.find_with(cache, input),
+            _ => self.find(input),
+        }
+    }
+
+    pub fn is_match(&self, input: &str) -> bool {
+        match &self.strategy {
+            Strategy::Dfa { forward, .. } => forward.is_match(input),
+            Strategy::LazyDfa(dfa) => dfa.is_match(input),
+            Strategy::Vm(vm) => vm.is_match(input),
+            Strategy::AhoCorasick(ac) => ac.is_match(input),
+        }
+    }
+
+    pub fn is_match_with(
+        &self,
+        cache: &mut crate::automaton::lazy::LazyCache,
+        input: &str,
+    ) -> bool {
+        match &self.strategy {
+            Strategy::LazyDfa(dfa) => dfa.is_match_with(cache, input),
+            _ => self.is_match(input),
+        }
+    }
+}
+
+pub fn literal_alternation(ast: &crate::parser::AstNode) -> Option<Vec<String>> {
+    ast.literals().filter(|literals| literals.len() > 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pattern: &str) -> crate::parser::AstNode {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let mut parser = crate::parser::Parser::new(&mut lexer);
+        parser.parse().unwrap()
+    }
+
+    #[test]
+    fn picks_dfa_within_budget() {
+        let meta = Meta::new(
+            parse("(p(erl|ython|hp)|ruby)"),
+            &crate::config::Config::default(),
+        )
+        .unwrap();
+        assert_eq!(meta.decision().method(), crate::Method::Dfa);
+        assert!(matches!(
+            meta.decision().reasons()[0],
+            Reason::DfaWithinBudget { .. }
+        ));
+        assert!(meta.is_match("python"));
+        assert!(!meta.is_match("rust"));
+    }
+
+    #[test]
+    fn picks_lazy_dfa_over_budget() {
+        let pattern = format!("(a|b)*a{}", "(a|b)".repeat(12));
+        let meta = Meta::new(parse(&pattern), &crate::config::Config::default()).unwrap();
+        assert_eq!(meta.decision().method(), crate::Method::LazyDfa);
+        assert_eq!(
+            meta.decision().reasons()[0],
+            Reason::DfaOverBudget {
+                limit: crate::Limit::DfaStates,
+                max: DFA_STATE_BUDGET
+            }
+        );
+        assert!(meta.is_match(&format!("ba{}", "b".repeat(12))));
+        assert!(!meta.is_match(&format!("bb{}", "b".repeat(12))));
+    }
+
+    #[test]
+    fn picks_vm_when_lazy_cache_is_too_small() {
+        let words: Vec<String> = (0..1000).map(|i| format!("k{i}x")).collect();
+        let pattern = format!("(a|b)*a{}({})", "(a|b)".repeat(12), words.join("|"));
+        let meta = Meta::new(parse(&pattern), &crate::config::Config::default()).unwrap();
+        assert_eq!(meta.decision().method(), crate::Method::Vm);
+        assert!(matches!(
+            meta.decision().reasons()[1],
+            Reason::LazyCacheTooSmall { states, min: LAZY_MIN_STATES } if states < LAZY_MIN_STATES
+        ));
+        assert!(meta.create_cache().is_none());
+        assert!(meta.prefilters(""));
+        assert!(meta.is_match(&format!("ba{}k999x", "b".repeat(12))));
+        assert!(!meta.is_match(&format!("bb{}k999x", "b".repeat(12))));
+    }
+
+    #[test]
+    fn reports_required_literals() {
+        let meta = Meta::new(parse(r"\w+needle\w+"), &crate::config::Config::default()).unwrap();
+        assert!(
+            meta.decision()
+                .reasons()
+                .contains(&Reason::RequiredLiterals(vec!["needle".to_string()]))
+        );
+        assert!(meta.decision().reasons().contains(&Reason::PrefilterFrom {
+            bytes: PREFILTER_MIN_HAYSTACK
+        }));
+        assert!(!meta.prefilters("aneedleb"));
+        let hay = "x".repeat(PREFILTER_MIN_HAYSTACK);
+        assert!(meta.prefilters(&hay));
+        assert!(!meta.is_match(&hay));
+        assert!(meta.is_match(&format!("{hay}needle{hay}")));
+        assert!(meta.is_match("aneedleb"));
+    }
+
+    #[test]
+    fn picks_aho_corasick_for_literal_alternations() {
+        let words: Vec<String> = (0..2000).map(|i| format!("k{i}x")).collect();
+        let meta = Meta::new(parse(&words.join("|")), &crate::config::Config::default()).unwrap();
+        assert_eq!(meta.decision().method(), crate::Method::AhoCorasick);
+        assert_eq!(
+            meta.decision().reasons(),
+            &[Reason::LiteralAlternation { patterns: 2000 }]
+        );
+        assert!(meta.is_match("k1999x"));
+        assert!(!meta.is_match("k2000x"));
+        assert_eq!(meta.find("--k12x--"), Some((2, 6)));
+
+        let config = crate::config::Config {
+            match_kind: crate::config::MatchKind::LeftmostFirst,
+            ..crate::config::Config::default()
+        };
+        let meta = Meta::new(parse("ab|abc"), &config).unwrap();
+        assert_eq!(meta.find("abc"), Some((0, 2)));
+        assert!(matches!(
+            Meta::new(parse("ab*"), &config),
+            Err(crate::Error::UnsupportedMatchKind(..))
+        ));
+    }
+}
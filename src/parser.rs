@@ -7,10 +7,94 @@ pub enum AstNode {
     Question(Box<AstNode>),
     Or(Box<AstNode>, Box<AstNode>),
     Seq(Box<AstNode>, Box<AstNode>),
+    Capture(usize, Box<AstNode>),
     Empty,
     Epsilon,
 }
 
+impl AstNode {
+    #[cfg(test)]
+    pub fn size(&self) -> usize {
+        match self {
+            AstNode::Char(_) | AstNode::Class(_) | AstNode::Empty | AstNode::Epsilon => 1,
+            AstNode::Plus(node)
+            | AstNode::Star(node)
+            | AstNode::Question(node)
+            | AstNode::Capture(_, node) => 1 + node.size(),
+            AstNode::Or(left, right) | AstNode::Seq(left, right) => 1 + left.size() + right.size(),
+        }
+    }
+
+    pub fn reverse(&self) -> AstNode {
+        match self {
+            AstNode::Plus(node) => AstNode::Plus(Box::new(node.reverse())),
+            AstNode::Star(node) => AstNode::Star(Box::new(node.reverse())),
+            AstNode::Question(node) => AstNode::Question(Box::new(node.reverse())),
+            AstNode::Or(left, right) => {
+                AstNode::Or(Box::new(left.reverse()), Box::new(right.reverse()))
+            }
+            AstNode::Seq(left, right) => {
+                AstNode::Seq(Box::new(right.reverse()), Box::new(left.reverse()))
+            }
+            AstNode::Capture(index, node) => AstNode::Capture(*index, Box::new(node.reverse())),
+            AstNode::Char(_) | AstNode::Class(_) | AstNode::Empty | AstNode::Epsilon => {
+                self.clone()
+            }
+        }
+    }
+
+    fn alternation(mut nodes: Vec<AstNode>) -> AstNode {
+        if nodes.len() == 1 {
+            return nodes.pop().unwrap();
+        }
+        let right = nodes.split_off(nodes.len() / 2);
+        AstNode::Or(
+            Box::new(AstNode::alternation(nodes)),
+            Box::new(AstNode::alternation(right)),
+        )
+    }
+
+    pub fn alternatives(&self) -> Vec<&AstNode> {
+        let mut alternatives = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            match node {
+                AstNode::Or(left, right) => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+                _ => alternatives.push(node),
+            }
+        }
+        alternatives
+    }
+
+    pub fn literal(&self) -> Option<String> {
+        let mut literal = String::new();
+        self.push_literal(&mut literal).then_some(literal)
+    }
+
+    fn push_literal(&self, literal: &mut String) -> bool {
+        match self {
+            AstNode::Char(c) => {
+                literal.push(*c);
+                true
+            }
+            AstNode::Epsilon => true,
+            AstNode::Seq(left, right) => left.push_literal(literal) && right.push_literal(literal),
+            AstNode::Capture(_, node) => node.push_literal(literal),
+            _ => false,
+        }
+    }
+
+    pub fn literals(&self) -> Option<Vec<String>> {
+        self.alternatives()
+            .into_iter()
+            .map(AstNode::literal)
+            .collect()
+    }
+}
+
 impl Clone for AstNode {
     fn clone(&self) -> Self {
         match self {
@@ -25,6 +109,7 @@ impl Clone for AstNode {
             AstNode::Seq(left, right) => {
                 AstNode::Seq(Box::new(*left.clone()), Box::new(*right.clone()))
             }
+            AstNode::Capture(index, node) => AstNode::Capture(*index, Box::new(*node.clone())),
             AstNode::Empty => AstNode::Empty,
             AstNode::Epsilon => AstNode::Epsilon,
         }
@@ -35,12 +120,36 @@ impl Clone for AstNode {
 pub struct Parser<'a> {
     lexer: &'a mut crate::lexer::Lexer<'a>,
     looking: crate::lexer::Token,
+    groups: Option<usize>,
+    nodes: usize,
+    max_nodes: usize,
 }
 
 impl Parser<'_> {
     pub fn new<'a>(lexer: &'a mut crate::lexer::Lexer<'a>) -> Parser<'a> {
         let looking = lexer.scan();
-        Parser { lexer, looking }
+        Parser {
+            lexer,
+            looking,
+            groups: None,
+            nodes: 0,
+            max_nodes: usize::MAX,
+        }
+    }
+
+    pub fn with_limit(self, max_nodes: usize) -> Self {
+        Parser { max_nodes, ..self }
+    }
+
+    pub fn with_captures<'a>(lexer: &'a mut crate::lexer::Lexer<'a>) -> Parser<'a> {
+        Parser {
+            groups: Some(0),
+            ..Parser::new(lexer)
+        }
+    }
+
+    pub fn groups(&self) -> usize {
+        self.groups.unwrap_or(0)
     }
 
     fn consume(&mut self, token: crate::lexer::Token) -> crate::Result<()> {
@@ -53,6 +162,11 @@ impl Parser<'_> {
         }
     }
 
+    fn count(&mut self, nodes: usize) -> crate::Result<()> {
+        self.nodes += nodes;
+        crate::config::Limit::AstNodes.check(self.nodes, self.max_nodes)
+    }
+
     pub fn parse(&mut self) -> crate::Result<AstNode> {
         let ast = self.parse_expr()?;
 
@@ -64,19 +178,15 @@ impl Parser<'_> {
     }
 
     fn parse_expr(&mut self) -> crate::Result<AstNode> {
-        let mut ast = if self.looking == crate::lexer::Token::RightParen {
-            AstNode::Epsilon
-        } else {
-            self.parse_term()?
-        };
+        let mut alternatives = vec![self.parse_term()?];
 
-        if self.looking == crate::lexer::Token::UnionOperator {
+        while self.looking == crate::lexer::Token::UnionOperator {
             self.consume(crate::lexer::Token::UnionOperator)?;
-            let right = self.parse_expr()?;
-            ast = AstNode::Or(Box::new(ast), Box::new(right));
+            self.count(1)?;
+            alternatives.push(self.parse_term()?);
         }
 
-        Ok(ast)
+        Ok(AstNode::alternation(alternatives))
     }
 
     fn parse_term(&mut self) -> crate::Result<AstNode> {
@@ -88,10 +198,14 @@ impl Parser<'_> {
                 | crate::lexer::Token::UnionOperator
                 | crate::lexer::Token::Empty
         ) {
+            if !nodes.is_empty() {
+                self.count(1)?;
+            }
             nodes.push(self.parse_factor()?);
         }
 
         if nodes.is_empty() {
+            self.count(1)?;
             Ok(AstNode::Epsilon)
         } else if nodes.len() == 1 {
             Ok(nodes.pop().unwrap())
@@ -116,14 +230,17 @@ impl Parser<'_> {
         match self.looking {
             crate::lexer::Token::PlusOperator => {
                 self.consume(crate::lexer::Token::PlusOperator)?;
+                self.count(1)?;
                 ast = AstNode::Plus(Box::new(ast));
             }
             crate::lexer::Token::StarOperator => {
                 self.consume(crate::lexer::Token::StarOperator)?;
+                self.count(1)?;
                 ast = AstNode::Star(Box::new(ast));
             }
             crate::lexer::Token::QuestionOperator => {
                 self.consume(crate::lexer::Token::QuestionOperator)?;
+                self.count(1)?;
                 ast = AstNode::Question(Box::new(ast));
             }
             _ => {}
@@ -136,21 +253,33 @@ impl Parser<'_> {
         match self.looking {
             crate::lexer::Token::Character(c) => {
                 self.consume(crate::lexer::Token::Character(c))?;
+                self.count(1)?;
 
                 Ok(AstNode::Char(c))
             }
             crate::lexer::Token::Class(class) => {
                 self.consume(crate::lexer::Token::Class(class))?;
+                self.count(1)?;
 
                 Ok(AstNode::Class(class))
             }
             crate::lexer::Token::InvalidEscape => Err(crate::Error::UnexpectedEnd),
             crate::lexer::Token::LeftParen => {
                 self.consume(crate::lexer::Token::LeftParen)?;
+                let index = self.groups.as_mut().map(|groups| {
+                    *groups += 1;
+                    *groups
+                });
+                if index.is_some() {
+                    self.count(1)?;
+                }
                 let ast = self.parse_expr()?;
                 self.consume(crate::lexer::Token::RightParen)?;
 
-                Ok(ast)
+                Ok(match index {
+                    Some(index) => AstNode::Capture(index, Box::new(ast)),
+                    None => ast,
+                })
             }
             _ => Err(crate::Error::UnexpectedChar(self.looking)),
         }
@@ -249,4 +378,120 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn parse_captures() {
+        let mut lexer = crate::lexer::Lexer::new("(a(b))|(c)");
+        let mut parser = Parser::with_captures(&mut lexer);
+        assert_eq!(
+            parser.parse().unwrap(),
+            AstNode::Or(
+                Box::new(AstNode::Capture(
+                    1,
+                    Box::new(AstNode::Seq(
+                        Box::new(AstNode::Char('a')),
+                        Box::new(AstNode::Capture(2, Box::new(AstNode::Char('b'))))
+                    ))
+                )),
+                Box::new(AstNode::Capture(3, Box::new(AstNode::Char('c'))))
+            )
+        );
+        assert_eq!(parser.groups(), 3);
+    }
+
+    #[test]
+    fn parse_limit() {
+        for pattern in ["a|b*|c?", "(ab)+(|c)", "x(y(z|))*w", ""] {
+            let mut lexer = crate::lexer::Lexer::new(pattern);
+            let size = Parser::with_captures(&mut lexer).parse().unwrap().size();
+            let mut lexer = crate::lexer::Lexer::new(pattern);
+            assert!(
+                Parser::with_captures(&mut lexer)
+                    .with_limit(size)
+                    .parse()
+                    .is_ok()
+            );
+            let mut lexer = crate::lexer::Lexer::new(pattern);
+            assert!(matches!(
+                Parser::with_captures(&mut lexer)
+                    .with_limit(size - 1)
+                    .parse(),
+                Err(crate::Error::LimitExceeded(
+                    crate::config::Limit::AstNodes,
+                    _
+                ))
+            ));
+        }
+
+        let pattern = "a".repeat(1 << 16) + ")";
+        let mut lexer = crate::lexer::Lexer::new(&pattern);
+        assert!(matches!(
+            Parser::new(&mut lexer).with_limit(100).parse(),
+            Err(crate::Error::LimitExceeded(
+                crate::config::Limit::AstNodes,
+                100
+            ))
+        ));
+    }
+
+    #[test]
+    fn reverse() {
+        let mut lexer = crate::lexer::Lexer::new("ab(cd|e)*");
+        let mut parser = Parser::new(&mut lexer);
+        let ast = parser.parse().unwrap();
+
+        let mut lexer = crate::lexer::Lexer::new("(dc|e)*ba");
+        let mut parser = Parser::new(&mut lexer);
+        let expected = parser.parse().unwrap();
+
+        assert_eq!(ast.reverse().size(), ast.size());
+        assert_eq!(ast.reverse().reverse(), ast);
+        let dfa = |ast: AstNode| {
+            crate::automaton::dfa::Dfa::from_nfa(
+                &crate::automaton::nfa::Nfa::new_from_node(
+                    ast,
+                    &mut crate::automaton::nfa::NfaState::new(),
+                )
+                .unwrap(),
+            )
+        };
+        let reversed = dfa(ast.reverse());
+        let expected = dfa(expected);
+        for input in ["ba", "dcba", "edcba", "abcd", "eba", "cdba"] {
+            assert_eq!(
+                reversed.is_match(input),
+                expected.is_match(input),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn large_alternation() {
+        let words: Vec<String> = (0..5000).map(|i| format!("w{i}")).collect();
+        let pattern = words.join("|");
+        let mut lexer = crate::lexer::Lexer::new(&pattern);
+        let mut parser = Parser::new(&mut lexer);
+        let ast = parser.parse().unwrap();
+
+        fn depth(ast: &AstNode) -> usize {
+            match ast {
+                AstNode::Or(left, right) => 1 + depth(left).max(depth(right)),
+                _ => 0,
+            }
+        }
+        assert!(depth(&ast) <= 13);
+        assert_eq!(ast.literals(), Some(words));
+
+        let mut lexer = crate::lexer::Lexer::new("ab||c");
+        let mut parser = Parser::new(&mut lexer);
+        assert_eq!(
+            parser.parse().unwrap().literals(),
+            Some(vec!["ab".to_string(), String::new(), "c".to_string()])
+        );
+
+        let mut lexer = crate::lexer::Lexer::new("ab|c*");
+        let mut parser = Parser::new(&mut lexer);
+        assert_eq!(parser.parse().unwrap().literals(), None);
+    }
 }
@@ -0,0 +1,98 @@
+type CreateFn<T> = Box<dyn Fn() -> T + Send + Sync>;
+
+pub struct Pool<T> {
+    stack: std::sync::Mutex<Vec<Box<T>>>,
+    create: CreateFn<T>,
+}
+
+impl<T: Send> Pool<T> {
+    pub fn new(create: impl Fn() -> T + Send + Sync + 'static) -> Self {
+        Pool {
+            stack: std::sync::Mutex::new(Vec::new()),
+            create: Box::new(create),
+        }
+    }
+
+    pub fn get(&self) -> PoolGuard<'_, T> {
+        let value = self
+            .stack
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .pop()
+            .unwrap_or_else(|| Box::new((self.create)()));
+
+        PoolGuard {
+            pool: self,
+            value: Some(value),
+        }
+    }
+
+    fn put(&self, value: Box<T>) {
+        self.stack
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push(value);
+    }
+}
+
+impl<T> std::fmt::Debug for Pool<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let idle = self
+            .stack
+            .lock()
+            .map(|stack| stack.len())
+            .unwrap_or_default();
+        f.debug_struct("Pool").field("idle", &idle).finish()
+    }
+}
+
+pub struct PoolGuard<'a, T: Send> {
+    pool: &'a Pool<T>,
+    value: Option<Box<T>>,
+}
+
+impl<T: Send> std::ops::Deref for PoolGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_deref().unwrap()
+    }
+}
+
+impl<T: Send> std::ops::DerefMut for PoolGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value.as_deref_mut().unwrap()
+    }
+}
+
+impl<T: Send> Drop for PoolGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Some(value) = self.value.take() {
+            self.pool.put(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_values() {
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = created.clone();
+        let pool = Pool::new(move || {
+            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Vec::<u32>::new()
+        });
+
+        pool.get().push(1);
+        assert_eq!(*pool.get(), vec![1]);
+        assert_eq!(created.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        let first = pool.get();
+        let second = pool.get();
+        assert_eq!(first.len() + second.len(), 1);
+        assert_eq!(created.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+}
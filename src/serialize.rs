@@ -0,0 +1,197 @@
+const MAGIC: [u8; 8] = *b"rustegex";
+const VERSION: u32 = 3;
+const ENDIANNESS: u32 = 0x0102_0304;
+const ALIGN: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Dfa = 1,
+    Program = 2,
+    Tags = 3,
+}
+
+/// # Safety
+///
+/// Every bit pattern must be a valid value and the type must have no padding.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for [u8; 8] {}
+
+/// Wraps `include_bytes!` output so it can be passed to `Engine::from_bytes`, which needs the
+/// buffer to start on an 8-byte boundary.
+///
+/// ```
+/// static DFA: &rustegex::Aligned<[u8]> = &rustegex::Aligned(*include_bytes!(concat!(
+///     env!("CARGO_MANIFEST_DIR"),
+///     "/tests/data/a_plus_b.bin"
+/// )));
+///
+/// let regex = rustegex::Engine::from_bytes(&DFA.0).unwrap();
+/// assert!(regex.is_match("aab"));
+/// assert!(!regex.is_match("ba"));
+/// ```
+#[repr(C, align(8))]
+pub struct Aligned<T: ?Sized>(pub T);
+
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new(kind: Kind) -> Self {
+        let mut writer = Writer { bytes: Vec::new() };
+        writer.bytes.extend_from_slice(&MAGIC);
+        writer.u32(VERSION);
+        writer.u32(ENDIANNESS);
+        writer.u32(kind as u32);
+        writer.u32(0);
+        writer
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_ne_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_ne_bytes());
+    }
+
+    pub fn slice<T: Pod>(&mut self, values: &[T]) {
+        self.u64(values.len() as u64);
+        let bytes = unsafe {
+            std::slice::from_raw_parts(values.as_ptr().cast::<u8>(), std::mem::size_of_val(values))
+        };
+        self.bytes.extend_from_slice(bytes);
+        self.bytes
+            .resize(self.bytes.len().next_multiple_of(ALIGN), 0);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8], kind: Kind) -> crate::Result<Self> {
+        if !(bytes.as_ptr() as usize).is_multiple_of(ALIGN) {
+            return Err(crate::Error::Deserialize("buffer is not 8-byte aligned"));
+        }
+        let mut reader = Reader { bytes, at: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(crate::Error::Deserialize("bad magic"));
+        }
+        if reader.u32()? != VERSION {
+            return Err(crate::Error::Deserialize("unsupported version"));
+        }
+        if reader.u32()? != ENDIANNESS {
+            return Err(crate::Error::Deserialize("endianness mismatch"));
+        }
+        if reader.u32()? != kind as u32 {
+            return Err(crate::Error::Deserialize("unexpected automaton kind"));
+        }
+        reader.u32()?;
+        Ok(reader)
+    }
+
+    pub fn kind(bytes: &[u8]) -> crate::Result<Kind> {
+        let at = MAGIC.len() + 8;
+        match bytes
+            .get(at..at + 4)
+            .map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+        {
+            Some(1) => Ok(Kind::Dfa),
+            Some(2) => Ok(Kind::Program),
+            Some(3) => Ok(Kind::Tags),
+            _ => Err(crate::Error::Deserialize("unknown automaton kind")),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> crate::Result<&'a [u8]> {
+        let end = self
+            .at
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or(crate::Error::Deserialize("unexpected end of data"))?;
+        let bytes = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(bytes)
+    }
+
+    pub fn u32(&mut self) -> crate::Result<u32> {
+        Ok(u32::from_ne_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> crate::Result<u64> {
+        Ok(u64::from_ne_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn slice<T: Pod>(&mut self) -> crate::Result<&'a [T]> {
+        let len = usize::try_from(self.u64()?)
+            .map_err(|_| crate::Error::Deserialize("section too large"))?;
+        let size = len
+            .checked_mul(std::mem::size_of::<T>())
+            .ok_or(crate::Error::Deserialize("section too large"))?;
+        let bytes = self.take(size)?;
+        self.take(self.at.next_multiple_of(ALIGN) - self.at)?;
+        Ok(unsafe { std::slice::from_raw_parts(bytes.as_ptr().cast::<T>(), len) })
+    }
+
+    pub fn rest(self) -> &'a [u8] {
+        &self.bytes[self.at..]
+    }
+
+    pub fn finish(self) -> crate::Result<()> {
+        if self.at == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(crate::Error::Deserialize("trailing data"))
+        }
+    }
+}
+
+#[cfg(test)]
+pub fn leak_aligned(bytes: &[u8]) -> &'static [u8] {
+    let words: &'static mut [u64] =
+        Box::leak(vec![0u64; bytes.len().div_ceil(8)].into_boxed_slice());
+    unsafe {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), words.as_mut_ptr().cast(), bytes.len());
+        std::slice::from_raw_parts(words.as_ptr().cast(), bytes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut writer = Writer::new(Kind::Dfa);
+        writer.u64(7);
+        writer.slice::<u8>(&[1, 2, 3]);
+        writer.slice::<u64>(&[u64::MAX, 5]);
+        let bytes = leak_aligned(&writer.finish());
+
+        assert_eq!(Reader::kind(bytes).unwrap(), Kind::Dfa);
+        let mut reader = Reader::new(bytes, Kind::Dfa).unwrap();
+        assert_eq!(reader.u64().unwrap(), 7);
+        assert_eq!(reader.slice::<u8>().unwrap(), &[1, 2, 3]);
+        assert_eq!(reader.slice::<u64>().unwrap(), &[u64::MAX, 5]);
+        reader.finish().unwrap();
+
+        assert!(Reader::new(bytes, Kind::Program).is_err());
+        assert!(Reader::new(&bytes[1..], Kind::Dfa).is_err());
+        let mut reader = Reader::new(&bytes[..bytes.len() - 8], Kind::Dfa).unwrap();
+        reader.u64().unwrap();
+        reader.slice::<u8>().unwrap();
+        assert!(reader.slice::<u64>().is_err());
+    }
+}
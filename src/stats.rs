@@ -0,0 +1,105 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    minimization: Option<Minimization>,
+    memory: Option<Memory>,
+    reverse_memory: Option<Memory>,
+    transition_cache: Option<TransitionCache>,
+    arena: Option<Arena>,
+    nfa: Option<NfaSize>,
+}
+
+impl Stats {
+    pub fn new(
+        minimization: Option<Minimization>,
+        memory: Option<Memory>,
+        reverse_memory: Option<Memory>,
+    ) -> Self {
+        Stats {
+            minimization,
+            memory,
+            reverse_memory,
+            transition_cache: None,
+            arena: None,
+            nfa: None,
+        }
+    }
+
+    pub fn from_derivative(transition_cache: TransitionCache, arena: Arena) -> Self {
+        Stats {
+            transition_cache: Some(transition_cache),
+            arena: Some(arena),
+            ..Stats::default()
+        }
+    }
+
+    pub fn minimization(&self) -> Option<Minimization> {
+        self.minimization
+    }
+
+    pub fn memory(&self) -> Option<Memory> {
+        self.memory
+    }
+
+    pub fn reverse_memory(&self) -> Option<Memory> {
+        self.reverse_memory
+    }
+
+    pub fn transition_cache(&self) -> Option<TransitionCache> {
+        self.transition_cache
+    }
+
+    pub fn arena(&self) -> Option<Arena> {
+        self.arena
+    }
+
+    pub fn with_nfa(self, nfa: Option<NfaSize>) -> Self {
+        Stats { nfa, ..self }
+    }
+
+    pub fn nfa(&self) -> Option<NfaSize> {
+        self.nfa
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Minimization {
+    pub states_before: usize,
+    pub states_after: usize,
+}
+
+impl Minimization {
+    pub fn states_saved(&self) -> usize {
+        self.states_before - self.states_after
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Memory {
+    pub layout: crate::config::DfaLayout,
+    pub states: usize,
+    pub alphabet_len: usize,
+    pub state_id_bytes: usize,
+    pub table_bytes: usize,
+    pub total_bytes: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransitionCache {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Arena {
+    pub nodes: usize,
+    pub compactions: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NfaSize {
+    pub construction: crate::config::NfaConstruction,
+    pub states: usize,
+    pub transitions: usize,
+    pub epsilon_transitions: usize,
+}
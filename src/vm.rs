@@ -1,23 +1,154 @@
 mod compile;
 mod eval;
 mod instruction;
+mod onepass;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+pub type Cache = eval::PikeBuffers;
+
+#[derive(Debug)]
 pub struct Vm {
-    bytecode: instruction::Program,
+    bytecode: instruction::Program<'static>,
+    buffers: crate::pool::Pool<eval::PikeBuffers>,
 }
 
 impl Vm {
-    pub fn new(ast: crate::parser::AstNode) -> Result<Vm, crate::error::Error> {
-        let mut compiler = compile::Compiler::new();
+    pub fn new(
+        ast: crate::parser::AstNode,
+        config: &crate::config::Config,
+    ) -> Result<Vm, crate::error::Error> {
+        let mut compiler = compile::Compiler::with_limit(config.max_program_len);
         compiler.compile(ast)?;
 
-        Ok(Vm {
-            bytecode: compiler.finish(),
-        })
+        Ok(Vm::from_program(compiler.finish()))
+    }
+
+    pub fn from_bytes(bytes: &'static [u8]) -> crate::Result<Vm> {
+        Ok(Vm::from_program(instruction::Program::from_bytes(bytes)?))
+    }
+
+    fn from_program(bytecode: instruction::Program<'static>) -> Vm {
+        let program_size = bytecode.len();
+
+        Vm {
+            bytecode,
+            buffers: crate::pool::Pool::new(move || eval::PikeBuffers::new(program_size)),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bytecode.to_bytes()
+    }
+
+    pub fn create_cache(&self) -> Cache {
+        eval::PikeBuffers::new(self.bytecode.len())
     }
 
     pub fn is_match(&self, input: &str) -> bool {
-        eval::eval(&self.bytecode, input, 0, 0)
+        eval::eval(&self.bytecode, input, || self.buffers.get())
+    }
+
+    pub fn is_match_with(&self, cache: &mut Cache, input: &str) -> bool {
+        eval::eval(&self.bytecode, input, || cache)
+    }
+
+    pub fn find(&self, input: &str) -> Option<(usize, usize)> {
+        eval::find(&self.bytecode, input, &mut self.buffers.get())
+    }
+
+    pub fn find_with(&self, cache: &mut Cache, input: &str) -> Option<(usize, usize)> {
+        eval::find(&self.bytecode, input, cache)
+    }
+}
+
+#[derive(Debug)]
+pub enum Groups {
+    OnePass(onepass::OnePass),
+    Pike {
+        bytecode: instruction::Program<'static>,
+        slots: usize,
+    },
+}
+
+impl Groups {
+    pub fn new(
+        ast: crate::parser::AstNode,
+        groups: usize,
+        config: &crate::config::Config,
+    ) -> crate::Result<Groups> {
+        let mut compiler = compile::Compiler::with_limit(config.max_program_len);
+        compiler.compile(ast)?;
+        let bytecode = compiler.finish();
+        let slots = 2 * (groups + 1);
+
+        Ok(match onepass::OnePass::new(&bytecode, slots) {
+            Some(onepass) => Groups::OnePass(onepass),
+            None => Groups::Pike { bytecode, slots },
+        })
+    }
+
+    #[cfg(test)]
+    pub fn is_one_pass(&self) -> bool {
+        matches!(self, Groups::OnePass(_))
+    }
+
+    pub fn captures(&self, input: &str) -> Option<Vec<Option<usize>>> {
+        match self {
+            Groups::OnePass(onepass) => onepass.captures(input),
+            Groups::Pike { bytecode, slots } => eval::captures(bytecode, input, *slots),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LazyGroups {
+    pattern: Box<str>,
+    config: crate::config::Config,
+    groups: std::sync::OnceLock<crate::Result<Option<Groups>>>,
+}
+
+impl LazyGroups {
+    pub fn new(pattern: &str, config: &crate::config::Config) -> LazyGroups {
+        LazyGroups {
+            pattern: pattern.into(),
+            config: config.clone(),
+            groups: std::sync::OnceLock::new(),
+        }
+    }
+
+    pub fn none() -> LazyGroups {
+        LazyGroups {
+            groups: std::sync::OnceLock::from(Ok(None)),
+            ..LazyGroups::new("", &crate::config::Config::default())
+        }
+    }
+
+    fn build(&self) -> crate::Result<Option<Groups>> {
+        let mut lexer = crate::lexer::Lexer::new(&self.pattern);
+        let mut parser =
+            crate::parser::Parser::with_captures(&mut lexer).with_limit(self.config.max_ast_nodes);
+        let ast = parser.parse()?;
+        match parser.groups() {
+            0 => Ok(None),
+            groups => Groups::new(ast, groups, &self.config).map(Some),
+        }
+    }
+
+    pub fn get(&self) -> crate::Result<Option<&Groups>> {
+        match self.groups.get_or_init(|| self.build()) {
+            Ok(groups) => Ok(groups.as_ref()),
+            Err(error) => Err(error.clone()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_built(&self) -> bool {
+        self.groups.get().is_some()
+    }
+
+    pub fn captures(&self, input: &str) -> Option<Vec<Option<usize>>> {
+        match self.get().ok()? {
+            Some(groups) => groups.captures(input),
+            None => Some(vec![None; 2]),
+        }
     }
 }
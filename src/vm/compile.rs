@@ -1,15 +1,22 @@
 pub struct Compiler {
     builder: crate::vm::instruction::ProgramBuilder,
+    max_len: usize,
 }
 
 impl Compiler {
+    #[cfg(test)]
     pub fn new() -> Self {
+        Compiler::with_limit(usize::MAX)
+    }
+
+    pub fn with_limit(max_len: usize) -> Self {
         Compiler {
             builder: crate::vm::instruction::ProgramBuilder::new(),
+            max_len,
         }
     }
 
-    pub fn finish(self) -> crate::vm::instruction::Program {
+    pub fn finish(self) -> crate::vm::instruction::Program<'static> {
         self.builder.build()
     }
 
@@ -22,12 +29,10 @@ impl Compiler {
                 self.builder.emit_class(class);
             }
             crate::parser::AstNode::Plus(node) => {
-                let split = self.builder.reserve_split();
                 let start = self.builder.pc();
                 self._compile(*node)?;
-                self.builder.emit_jmp(split);
-                let end = self.builder.pc();
-                self.builder.patch_split(split, start, end);
+                let split = self.builder.pc();
+                self.builder.emit_split(start, split + 1);
             }
             crate::parser::AstNode::Star(node) => {
                 let split = self.builder.pc();
@@ -66,16 +71,21 @@ impl Compiler {
                 self._compile(*left)?;
                 self._compile(*right)?;
             }
+            crate::parser::AstNode::Capture(index, node) => {
+                self.builder.emit_save(2 * index);
+                self._compile(*node)?;
+                self.builder.emit_save(2 * index + 1);
+            }
             crate::parser::AstNode::Empty | crate::parser::AstNode::Epsilon => {}
         }
 
-        Ok(())
+        crate::config::Limit::ProgramLength.check(self.builder.pc(), self.max_len)
     }
 
     pub fn compile(&mut self, ast: crate::parser::AstNode) -> crate::Result<()> {
         self._compile(ast)?;
         self.builder.emit_match();
-        Ok(())
+        crate::config::Limit::ProgramLength.check(self.builder.pc(), self.max_len)
     }
 }
 
@@ -83,7 +93,7 @@ impl Compiler {
 mod tests {
     use super::*;
 
-    fn compile_pattern(pattern: &str) -> crate::vm::instruction::Program {
+    fn compile_pattern(pattern: &str) -> crate::vm::instruction::Program<'static> {
         let mut lexer = crate::lexer::Lexer::new(pattern);
         let mut parser = crate::parser::Parser::new(&mut lexer);
         let ast = parser.parse().unwrap();
@@ -142,4 +152,18 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn program_length_limit() {
+        let mut lexer = crate::lexer::Lexer::new("abc");
+        let mut parser = crate::parser::Parser::new(&mut lexer);
+        let mut compiler = Compiler::with_limit(3);
+        assert!(matches!(
+            compiler.compile(parser.parse().unwrap()),
+            Err(crate::Error::LimitExceeded(
+                crate::config::Limit::ProgramLength,
+                3
+            ))
+        ));
+    }
 }
@@ -1,30 +1,47 @@
-fn for_each_set_bit(mut mask: u64, mut f: impl FnMut(usize)) {
-    while mask != 0 {
-        let pc = mask.trailing_zeros() as usize;
-        f(pc);
-        mask &= mask - 1;
+fn for_each_set_bit(mask: &[u64], mut f: impl FnMut(usize)) {
+    for (word, &bits) in mask.iter().enumerate() {
+        let mut bits = bits;
+        while bits != 0 {
+            f(word * 64 + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+    }
+}
+
+#[inline(always)]
+fn epsilon_mask<'a, const N: usize>(
+    inst: &'a crate::vm::instruction::Program<'_>,
+    pc: usize,
+) -> &'a [u64; N] {
+    unsafe { &*inst.epsilon_mask(pc).as_ptr().cast::<[u64; N]>() }
+}
+
+#[inline(always)]
+fn union<const N: usize>(into: &mut [u64; N], mask: &[u64; N]) {
+    for (word, &bits) in into.iter_mut().zip(mask) {
+        *word |= bits;
     }
 }
 
 #[inline(never)]
-fn pike_eval_bitmask(inst: &crate::vm::instruction::Program, input: &str) -> bool {
-    let mut current: u64 = inst.epsilon_mask(0);
+fn pike_eval_bitmask<const N: usize>(inst: &crate::vm::instruction::Program, input: &str) -> bool {
+    let mut current = *epsilon_mask::<N>(inst, 0);
 
     if input.is_ascii() {
         for &byte in input.as_bytes() {
-            if current == 0 {
+            if current == [0; N] {
                 return false;
             }
-            let mut next: u64 = 0;
-            for_each_set_bit(current, |pc| match inst.opcode(pc) {
+            let mut next = [0u64; N];
+            for_each_set_bit(&current, |pc| match inst.opcode(pc) {
                 crate::vm::instruction::OP_CHAR => {
                     let expected = inst.operand1(pc);
                     if expected <= 127 && expected as u8 == byte {
-                        next |= inst.epsilon_mask(pc + 1);
+                        union(&mut next, epsilon_mask(inst, pc + 1));
                     }
                 }
                 crate::vm::instruction::OP_CLASS if inst.char_class(pc).matches(byte as char) => {
-                    next |= inst.epsilon_mask(pc + 1);
+                    union(&mut next, epsilon_mask(inst, pc + 1));
                 }
                 _ => {}
             });
@@ -32,18 +49,18 @@ fn pike_eval_bitmask(inst: &crate::vm::instruction::Program, input: &str) -> boo
         }
     } else {
         for ch in input.chars() {
-            if current == 0 {
+            if current == [0; N] {
                 return false;
             }
-            let mut next: u64 = 0;
-            for_each_set_bit(current, |pc| match inst.opcode(pc) {
+            let mut next = [0u64; N];
+            for_each_set_bit(&current, |pc| match inst.opcode(pc) {
                 crate::vm::instruction::OP_CHAR => {
                     if inst.char_literal(pc) == ch {
-                        next |= inst.epsilon_mask(pc + 1);
+                        union(&mut next, epsilon_mask(inst, pc + 1));
                     }
                 }
                 crate::vm::instruction::OP_CLASS if inst.char_class(pc).matches(ch) => {
-                    next |= inst.epsilon_mask(pc + 1);
+                    union(&mut next, epsilon_mask(inst, pc + 1));
                 }
                 _ => {}
             });
@@ -52,7 +69,7 @@ fn pike_eval_bitmask(inst: &crate::vm::instruction::Program, input: &str) -> boo
     }
 
     let mut found = false;
-    for_each_set_bit(current, |pc| {
+    for_each_set_bit(&current, |pc| {
         if inst.opcode(pc) == crate::vm::instruction::OP_MATCH {
             found = true;
         }
@@ -60,18 +77,23 @@ fn pike_eval_bitmask(inst: &crate::vm::instruction::Program, input: &str) -> boo
     found
 }
 
-struct PikeBuffers {
+#[derive(Debug)]
+pub struct PikeBuffers {
     current: Vec<usize>,
     next: Vec<usize>,
+    current_starts: Vec<usize>,
+    next_starts: Vec<usize>,
     gen_arr: Vec<u32>,
     gen_counter: u32,
 }
 
 impl PikeBuffers {
-    fn new(cap: usize) -> Self {
+    pub fn new(cap: usize) -> Self {
         PikeBuffers {
             current: Vec::with_capacity(cap),
             next: Vec::with_capacity(cap),
+            current_starts: Vec::new(),
+            next_starts: Vec::new(),
             gen_arr: vec![0u32; cap],
             gen_counter: 0,
         }
@@ -111,108 +133,285 @@ fn extend_epsilon_list(
     }
 }
 
-thread_local! {
-    static BUFFERS: std::cell::RefCell<PikeBuffers> = std::cell::RefCell::new(PikeBuffers::new(32));
-}
-
 #[inline(never)]
-fn pike_eval_vec(inst: &crate::vm::instruction::Program, input: &str) -> bool {
+fn pike_eval_vec(
+    inst: &crate::vm::instruction::Program,
+    input: &str,
+    bufs: &mut PikeBuffers,
+) -> bool {
     let program_size = inst.len();
 
-    BUFFERS.with(|cell| {
-        let bufs = &mut *cell.borrow_mut();
-        bufs.ensure_capacity(program_size);
-        bufs.current.clear();
-        bufs.next.clear();
+    bufs.ensure_capacity(program_size);
+    bufs.current.clear();
+    bufs.next.clear();
 
-        let g = bufs.next_gen();
-        extend_epsilon_list(inst, 0, &mut bufs.current, &mut bufs.gen_arr, g);
+    let g = bufs.next_gen();
+    extend_epsilon_list(inst, 0, &mut bufs.current, &mut bufs.gen_arr, g);
 
-        if input.is_ascii() {
-            for &byte in input.as_bytes() {
-                if bufs.current.is_empty() {
-                    return false;
-                }
-                let g = bufs.next_gen();
-                let len = bufs.current.len();
-                for i in 0..len {
-                    let pc = *unsafe { bufs.current.get_unchecked(i) };
-                    match inst.opcode(pc) {
-                        crate::vm::instruction::OP_CHAR => {
-                            let expected = inst.operand1(pc);
-                            if expected <= 127 && expected as u8 == byte {
-                                extend_epsilon_list(
-                                    inst,
-                                    pc + 1,
-                                    &mut bufs.next,
-                                    &mut bufs.gen_arr,
-                                    g,
-                                );
-                            }
-                        }
-                        crate::vm::instruction::OP_CLASS
-                            if inst.char_class(pc).matches(byte as char) =>
-                        {
+    if input.is_ascii() {
+        for &byte in input.as_bytes() {
+            if bufs.current.is_empty() {
+                return false;
+            }
+            let g = bufs.next_gen();
+            let len = bufs.current.len();
+            for i in 0..len {
+                let pc = *unsafe { bufs.current.get_unchecked(i) };
+                match inst.opcode(pc) {
+                    crate::vm::instruction::OP_CHAR => {
+                        let expected = inst.operand1(pc);
+                        if expected <= 127 && expected as u8 == byte {
                             extend_epsilon_list(inst, pc + 1, &mut bufs.next, &mut bufs.gen_arr, g);
                         }
-                        _ => {}
                     }
+                    crate::vm::instruction::OP_CLASS
+                        if inst.char_class(pc).matches(byte as char) =>
+                    {
+                        extend_epsilon_list(inst, pc + 1, &mut bufs.next, &mut bufs.gen_arr, g);
+                    }
+                    _ => {}
                 }
-                std::mem::swap(&mut bufs.current, &mut bufs.next);
-                bufs.next.clear();
             }
-        } else {
-            for ch in input.chars() {
-                if bufs.current.is_empty() {
-                    return false;
-                }
-                let g = bufs.next_gen();
-                let len = bufs.current.len();
-                for i in 0..len {
-                    let pc = *unsafe { bufs.current.get_unchecked(i) };
-                    match inst.opcode(pc) {
-                        crate::vm::instruction::OP_CHAR => {
-                            if inst.char_literal(pc) == ch {
-                                extend_epsilon_list(
-                                    inst,
-                                    pc + 1,
-                                    &mut bufs.next,
-                                    &mut bufs.gen_arr,
-                                    g,
-                                );
-                            }
-                        }
-                        crate::vm::instruction::OP_CLASS if inst.char_class(pc).matches(ch) => {
+            std::mem::swap(&mut bufs.current, &mut bufs.next);
+            bufs.next.clear();
+        }
+    } else {
+        for ch in input.chars() {
+            if bufs.current.is_empty() {
+                return false;
+            }
+            let g = bufs.next_gen();
+            let len = bufs.current.len();
+            for i in 0..len {
+                let pc = *unsafe { bufs.current.get_unchecked(i) };
+                match inst.opcode(pc) {
+                    crate::vm::instruction::OP_CHAR => {
+                        if inst.char_literal(pc) == ch {
                             extend_epsilon_list(inst, pc + 1, &mut bufs.next, &mut bufs.gen_arr, g);
                         }
-                        _ => {}
                     }
+                    crate::vm::instruction::OP_CLASS if inst.char_class(pc).matches(ch) => {
+                        extend_epsilon_list(inst, pc + 1, &mut bufs.next, &mut bufs.gen_arr, g);
+                    }
+                    _ => {}
                 }
-                std::mem::swap(&mut bufs.current, &mut bufs.next);
-                bufs.next.clear();
             }
+            std::mem::swap(&mut bufs.current, &mut bufs.next);
+            bufs.next.clear();
         }
+    }
 
-        bufs.current
+    bufs.current
+        .iter()
+        .any(|&pc| inst.opcode(pc) == crate::vm::instruction::OP_MATCH)
+}
+
+fn add_thread(
+    inst: &crate::vm::instruction::Program,
+    pc: usize,
+    start: usize,
+    bufs: &mut PikeBuffers,
+    cur_gen: u32,
+) {
+    let mut push = |target: usize| {
+        if bufs.gen_arr[target] != cur_gen {
+            bufs.gen_arr[target] = cur_gen;
+            bufs.next.push(target);
+            bufs.next_starts.push(start);
+        }
+    };
+    if inst.mask_words() > 0 {
+        for_each_set_bit(inst.epsilon_mask(pc), push);
+    } else {
+        inst.epsilon_list(pc)
             .iter()
-            .any(|&pc| inst.opcode(pc) == crate::vm::instruction::OP_MATCH)
-    })
+            .for_each(|&target| push(target));
+    }
+}
+
+fn pike_find(
+    inst: &crate::vm::instruction::Program,
+    input: &str,
+    bufs: &mut PikeBuffers,
+) -> Option<(usize, usize)> {
+    bufs.ensure_capacity(inst.len());
+    bufs.next.clear();
+    bufs.next_starts.clear();
+
+    let mut found: Option<(usize, usize)> = None;
+    let mut chars = input.char_indices();
+    let mut at = 0;
+    let mut g = bufs.next_gen();
+
+    loop {
+        if found.is_none() {
+            add_thread(inst, 0, at, bufs, g);
+        }
+        std::mem::swap(&mut bufs.current, &mut bufs.next);
+        std::mem::swap(&mut bufs.current_starts, &mut bufs.next_starts);
+        bufs.next.clear();
+        bufs.next_starts.clear();
+
+        for i in 0..bufs.current.len() {
+            if inst.opcode(bufs.current[i]) == crate::vm::instruction::OP_MATCH {
+                let start = bufs.current_starts[i];
+                if found.is_none_or(|(leftmost, _)| start <= leftmost) {
+                    found = Some((start, at));
+                }
+                break;
+            }
+        }
+
+        let Some((offset, ch)) = chars.next() else {
+            break;
+        };
+        if bufs.current.is_empty() && found.is_some() {
+            break;
+        }
+
+        g = bufs.next_gen();
+        for i in 0..bufs.current.len() {
+            let pc = bufs.current[i];
+            let start = bufs.current_starts[i];
+            if found.is_some_and(|(leftmost, _)| start > leftmost) {
+                continue;
+            }
+            let step = match inst.opcode(pc) {
+                crate::vm::instruction::OP_CHAR => inst.char_literal(pc) == ch,
+                crate::vm::instruction::OP_CLASS => inst.char_class(pc).matches(ch),
+                _ => false,
+            };
+            if step {
+                add_thread(inst, pc + 1, start, bufs, g);
+            }
+        }
+        at = offset + ch.len_utf8();
+    }
+
+    found
+}
+
+pub fn find(
+    inst: &crate::vm::instruction::Program,
+    input: &str,
+    buffers: &mut PikeBuffers,
+) -> Option<(usize, usize)> {
+    if inst.len() == 0 {
+        return None;
+    }
+    pike_find(inst, input, buffers)
+}
+
+enum Frame {
+    Explore(usize),
+    Restore(usize, Option<usize>),
+}
+
+type Thread = (usize, Vec<Option<usize>>);
+
+fn follow(
+    inst: &crate::vm::instruction::Program,
+    pc: usize,
+    at: usize,
+    mut slots: Vec<Option<usize>>,
+    list: &mut Vec<Thread>,
+    visited: &mut [bool],
+    stack: &mut Vec<Frame>,
+) {
+    stack.push(Frame::Explore(pc));
+    while let Some(frame) = stack.pop() {
+        let pc = match frame {
+            Frame::Restore(slot, value) => {
+                slots[slot] = value;
+                continue;
+            }
+            Frame::Explore(pc) if pc >= inst.len() || visited[pc] => continue,
+            Frame::Explore(pc) => pc,
+        };
+        visited[pc] = true;
+        match inst.opcode(pc) {
+            crate::vm::instruction::OP_SPLIT => {
+                stack.push(Frame::Explore(inst.operand2(pc) as usize));
+                stack.push(Frame::Explore(inst.operand1(pc) as usize));
+            }
+            crate::vm::instruction::OP_JMP => {
+                stack.push(Frame::Explore(inst.operand1(pc) as usize))
+            }
+            crate::vm::instruction::OP_SAVE => {
+                let slot = inst.operand1(pc) as usize;
+                stack.push(Frame::Restore(slot, slots[slot]));
+                slots[slot] = Some(at);
+                stack.push(Frame::Explore(pc + 1));
+            }
+            _ => list.push((pc, slots.clone())),
+        }
+    }
 }
 
-pub fn eval(
+pub fn captures(
     inst: &crate::vm::instruction::Program,
     input: &str,
-    _input_looking: usize,
-    _pc: usize,
+    slots: usize,
+) -> Option<Vec<Option<usize>>> {
+    let mut current = Vec::new();
+    let mut next = Vec::new();
+    let mut visited = vec![false; inst.len()];
+    let mut stack = Vec::new();
+    follow(
+        inst,
+        0,
+        0,
+        vec![None; slots],
+        &mut current,
+        &mut visited,
+        &mut stack,
+    );
+
+    for (at, ch) in input.char_indices() {
+        visited.fill(false);
+        for (pc, thread) in current.drain(..) {
+            let step = match inst.opcode(pc) {
+                crate::vm::instruction::OP_CHAR => inst.char_literal(pc) == ch,
+                crate::vm::instruction::OP_CLASS => inst.char_class(pc).matches(ch),
+                _ => false,
+            };
+            if step {
+                let at = at + ch.len_utf8();
+                follow(
+                    inst,
+                    pc + 1,
+                    at,
+                    thread,
+                    &mut next,
+                    &mut visited,
+                    &mut stack,
+                );
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+    }
+
+    current
+        .into_iter()
+        .find(|&(pc, _)| inst.opcode(pc) == crate::vm::instruction::OP_MATCH)
+        .map(|(_, slots)| slots)
+}
+
+pub fn eval<B: std::ops::DerefMut<Target = PikeBuffers>>(
+    inst: &crate::vm::instruction::Program,
+    input: &str,
+    buffers: impl FnOnce() -> B,
 ) -> bool {
     let program_size = inst.len();
     if program_size == 0 {
         return false;
     }
-    if program_size <= 64 {
-        pike_eval_bitmask(inst, input)
-    } else {
-        pike_eval_vec(inst, input)
+    match inst.mask_words() {
+        1 => pike_eval_bitmask::<1>(inst, input),
+        2 => pike_eval_bitmask::<2>(inst, input),
+        4 => pike_eval_bitmask::<4>(inst, input),
+        8 => pike_eval_bitmask::<8>(inst, input),
+        _ => pike_eval_vec(inst, input, &mut buffers()),
     }
 }
 
@@ -227,7 +426,7 @@ mod tests {
         let mut compiler = crate::vm::compile::Compiler::new();
         compiler.compile(ast).unwrap();
         let inst = compiler.finish();
-        eval(&inst, input, 0, 0)
+        eval(&inst, input, || Box::new(PikeBuffers::new(32)))
     }
 
     #[test]
@@ -256,6 +455,15 @@ mod tests {
         assert!(!compile_and_eval("a*", "b"));
     }
 
+    #[test]
+    fn evaluation_plus() {
+        assert!(!compile_and_eval("a+", ""));
+        assert!(compile_and_eval("a+", "a"));
+        assert!(compile_and_eval("a+", "aaa"));
+        assert!(!compile_and_eval("(ab)+c", "c"));
+        assert!(compile_and_eval("(ab)+c", "ababc"));
+    }
+
     #[test]
     fn evaluation_complex() {
         assert!(compile_and_eval("ab(cd|)ef", "abcdef"));
@@ -288,4 +496,52 @@ mod tests {
         assert!(!compile_and_eval("abc", "abcd"));
         assert!(!compile_and_eval("abc", ""));
     }
+
+    #[test]
+    fn evaluation_wide_programs() {
+        for (groups, words) in [(8, 1), (20, 2), (60, 4), (100, 8), (150, 0)] {
+            let pattern = format!("{}(x|y)z", "(a|b)".repeat(groups));
+            let mut lexer = crate::lexer::Lexer::new(&pattern);
+            let mut parser = crate::parser::Parser::new(&mut lexer);
+            let mut compiler = crate::vm::compile::Compiler::new();
+            compiler.compile(parser.parse().unwrap()).unwrap();
+            assert_eq!(compiler.finish().mask_words(), words, "{groups}");
+
+            let ab = "ab".repeat(groups / 2);
+            assert!(compile_and_eval(&pattern, &format!("{ab}yz")));
+            assert!(compile_and_eval(&pattern, &format!("{ab}xz")));
+            assert!(!compile_and_eval(&pattern, &format!("{ab}az")));
+            assert!(!compile_and_eval(&pattern, &format!("{ab}a")));
+            assert!(!compile_and_eval(&pattern, &format!("b{ab}yz")));
+            assert!(compile_and_eval(
+                &pattern,
+                &format!("{}ayz", "bb".repeat(groups / 2 - 1) + "b")
+            ));
+        }
+    }
+
+    fn compile_and_find(pattern: &str, input: &str) -> Option<(usize, usize)> {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let mut parser = crate::parser::Parser::new(&mut lexer);
+        let mut compiler = crate::vm::compile::Compiler::new();
+        compiler.compile(parser.parse().unwrap()).unwrap();
+        find(&compiler.finish(), input, &mut PikeBuffers::new(32))
+    }
+
+    #[test]
+    fn find_leftmost_longest() {
+        assert_eq!(compile_and_find("a+", "xxaaay"), Some((2, 5)));
+        assert_eq!(compile_and_find("ab|a", "xab"), Some((1, 3)));
+        assert_eq!(compile_and_find("b*", "abb"), Some((0, 0)));
+        assert_eq!(
+            compile_and_find("太郎|次郎", "正規表現次郎"),
+            Some((12, 18))
+        );
+        assert_eq!(compile_and_find("abc", "ababd"), None);
+        let long = format!("{}(x|y)z", "(a|b)".repeat(40));
+        assert_eq!(
+            compile_and_find(&long, &format!("zz{}yz", "ab".repeat(20))),
+            Some((2, 44))
+        );
+    }
 }
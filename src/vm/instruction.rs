@@ -3,17 +3,21 @@ pub const OP_SPLIT: u8 = 1;
 pub const OP_JMP: u8 = 2;
 pub const OP_MATCH: u8 = 3;
 pub const OP_CLASS: u8 = 4;
+pub const OP_SAVE: u8 = 5;
+
+const MAX_MASK_WORDS: usize = 8;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Program {
-    opcodes: Vec<u8>,
-    op1: Vec<u32>,
-    op2: Vec<u32>,
+pub struct Program<'a> {
+    opcodes: std::borrow::Cow<'a, [u8]>,
+    op1: std::borrow::Cow<'a, [u32]>,
+    op2: std::borrow::Cow<'a, [u32]>,
+    mask_words: usize,
     epsilon_masks: Vec<u64>,
     epsilon_lists: Vec<Vec<usize>>,
 }
 
-impl Program {
+impl<'a> Program<'a> {
     pub fn len(&self) -> usize {
         self.opcodes.len()
     }
@@ -28,7 +32,7 @@ impl Program {
         *unsafe { self.op1.get_unchecked(pc) }
     }
 
-    #[cfg(test)]
+    #[inline(always)]
     pub fn operand2(&self, pc: usize) -> u32 {
         *unsafe { self.op2.get_unchecked(pc) }
     }
@@ -48,15 +52,79 @@ impl Program {
         }
     }
 
+    pub fn mask_words(&self) -> usize {
+        self.mask_words
+    }
+
     #[inline(always)]
-    pub fn epsilon_mask(&self, pc: usize) -> u64 {
-        *unsafe { self.epsilon_masks.get_unchecked(pc) }
+    pub fn epsilon_mask(&self, pc: usize) -> &[u64] {
+        unsafe {
+            self.epsilon_masks
+                .get_unchecked(pc * self.mask_words..(pc + 1) * self.mask_words)
+        }
     }
 
     #[inline(always)]
     pub fn epsilon_list(&self, pc: usize) -> &[usize] {
         unsafe { self.epsilon_lists.get_unchecked(pc) }
     }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = crate::serialize::Writer::new(crate::serialize::Kind::Program);
+        writer.slice(&self.opcodes);
+        writer.slice(&self.op1);
+        writer.slice(&self.op2);
+        writer.finish()
+    }
+
+    pub fn from_bytes(bytes: &'a [u8]) -> crate::Result<Program<'a>> {
+        let mut reader = crate::serialize::Reader::new(bytes, crate::serialize::Kind::Program)?;
+        let opcodes: &[u8] = reader.slice()?;
+        let op1: &[u32] = reader.slice()?;
+        let op2: &[u32] = reader.slice()?;
+        reader.finish()?;
+
+        let n = opcodes.len();
+        if op1.len() != n || op2.len() != n {
+            return Err(crate::Error::Deserialize(
+                "operand tables have the wrong length",
+            ));
+        }
+        let saves = opcodes.iter().filter(|&&opcode| opcode == OP_SAVE).count();
+        let valid = (0..n).all(|pc| match opcodes[pc] {
+            OP_CHAR => pc + 1 < n && char::from_u32(op1[pc]).is_some(),
+            OP_CLASS => {
+                pc + 1 < n
+                    && u8::try_from(op1[pc])
+                        .ok()
+                        .and_then(crate::charclass::CharClass::from_id)
+                        .is_some()
+            }
+            OP_SPLIT => (op1[pc] as usize) < n && (op2[pc] as usize) < n,
+            OP_JMP => (op1[pc] as usize) < n,
+            OP_SAVE => (op1[pc] as usize) < saves + 2,
+            OP_MATCH => true,
+            _ => false,
+        });
+        if !valid {
+            return Err(crate::Error::Deserialize("invalid instruction"));
+        }
+        if !opcodes.contains(&OP_MATCH) {
+            return Err(crate::Error::Deserialize(
+                "program has no match instruction",
+            ));
+        }
+
+        let (epsilon_masks, epsilon_lists) = epsilon_closures(opcodes, op1, op2);
+        Ok(Program {
+            opcodes: opcodes.into(),
+            op1: op1.into(),
+            op2: op2.into(),
+            mask_words: mask_words(n),
+            epsilon_masks,
+            epsilon_lists,
+        })
+    }
 }
 
 pub struct ProgramBuilder {
@@ -78,24 +146,14 @@ impl ProgramBuilder {
         self.opcodes.len()
     }
 
-    pub fn build(self) -> Program {
-        let n = self.opcodes.len();
-        let (epsilon_masks, epsilon_lists) = if n <= 64 {
-            (
-                compute_epsilon_masks(&self.opcodes, &self.op1, &self.op2, n),
-                Vec::new(),
-            )
-        } else {
-            (
-                Vec::new(),
-                compute_epsilon_lists(&self.opcodes, &self.op1, &self.op2, n),
-            )
-        };
+    pub fn build(self) -> Program<'static> {
+        let (epsilon_masks, epsilon_lists) = epsilon_closures(&self.opcodes, &self.op1, &self.op2);
 
         Program {
-            opcodes: self.opcodes,
-            op1: self.op1,
-            op2: self.op2,
+            mask_words: mask_words(self.opcodes.len()),
+            opcodes: self.opcodes.into(),
+            op1: self.op1.into(),
+            op2: self.op2.into(),
             epsilon_masks,
             epsilon_lists,
         }
@@ -117,13 +175,7 @@ impl ProgramBuilder {
     }
 
     pub fn emit_class(&mut self, class: crate::charclass::CharClass) {
-        let id = match class {
-            crate::charclass::CharClass::Any => 0,
-            crate::charclass::CharClass::Digit => 1,
-            crate::charclass::CharClass::Word => 2,
-            crate::charclass::CharClass::Space => 3,
-        };
-        self.emit(OP_CLASS, id, 0);
+        self.emit(OP_CLASS, class.id() as u32, 0);
     }
 
     pub fn emit_split(&mut self, x: usize, y: usize) {
@@ -134,6 +186,10 @@ impl ProgramBuilder {
         self.emit(OP_JMP, target as u32, 0);
     }
 
+    pub fn emit_save(&mut self, slot: usize) {
+        self.emit(OP_SAVE, slot as u32, 0);
+    }
+
     pub fn emit_match(&mut self) {
         self.emit(OP_MATCH, 0, 0);
     }
@@ -163,9 +219,33 @@ impl ProgramBuilder {
     }
 }
 
-fn compute_epsilon_masks(opcodes: &[u8], op1: &[u32], op2: &[u32], n: usize) -> Vec<u64> {
-    let mut masks = vec![0u64; n];
-    for (start, mask) in masks.iter_mut().enumerate() {
+fn mask_words(n: usize) -> usize {
+    match n.div_ceil(64).next_power_of_two() {
+        words if words <= MAX_MASK_WORDS => words,
+        _ => 0,
+    }
+}
+
+fn epsilon_closures(opcodes: &[u8], op1: &[u32], op2: &[u32]) -> (Vec<u64>, Vec<Vec<usize>>) {
+    let n = opcodes.len();
+    match mask_words(n) {
+        0 => (Vec::new(), compute_epsilon_lists(opcodes, op1, op2, n)),
+        words => (
+            compute_epsilon_masks(opcodes, op1, op2, n, words),
+            Vec::new(),
+        ),
+    }
+}
+
+fn compute_epsilon_masks(
+    opcodes: &[u8],
+    op1: &[u32],
+    op2: &[u32],
+    n: usize,
+    words: usize,
+) -> Vec<u64> {
+    let mut masks = vec![0u64; n * words];
+    for (start, mask) in masks.chunks_exact_mut(words).enumerate() {
         fill_epsilon_mask(start, mask, opcodes, op1, op2, n);
     }
     masks
@@ -173,7 +253,7 @@ fn compute_epsilon_masks(opcodes: &[u8], op1: &[u32], op2: &[u32], n: usize) ->
 
 fn fill_epsilon_mask(
     pc: usize,
-    mask: &mut u64,
+    mask: &mut [u64],
     opcodes: &[u8],
     op1: &[u32],
     op2: &[u32],
@@ -182,11 +262,11 @@ fn fill_epsilon_mask(
     if pc >= n {
         return;
     }
-    let bit = 1u64 << pc;
-    if *mask & bit != 0 {
+    let bit = 1u64 << (pc % 64);
+    if mask[pc / 64] & bit != 0 {
         return;
     }
-    *mask |= bit;
+    mask[pc / 64] |= bit;
 
     match opcodes[pc] {
         OP_SPLIT => {
@@ -199,6 +279,7 @@ fn fill_epsilon_mask(
             let x = op1[pc] as usize;
             fill_epsilon_mask(x, mask, opcodes, op1, op2, n);
         }
+        OP_SAVE => fill_epsilon_mask(pc + 1, mask, opcodes, op1, op2, n),
         _ => {}
     }
 }
@@ -243,6 +324,7 @@ fn fill_epsilon_list(
             let x = op1[pc] as usize;
             fill_epsilon_list(x, list, visited, opcodes, op1, op2, n);
         }
+        OP_SAVE => fill_epsilon_list(pc + 1, list, visited, opcodes, op1, op2, n),
         _ => {
             list.push(pc);
         }
@@ -253,7 +335,7 @@ fn fill_epsilon_list(
 mod tests {
     use super::*;
 
-    fn program_from_ops(ops: &[(u8, u32, u32)]) -> Program {
+    fn program_from_ops(ops: &[(u8, u32, u32)]) -> Program<'static> {
         let mut builder = ProgramBuilder::new();
         for &(opcode, a, b) in ops {
             match opcode {
@@ -261,6 +343,10 @@ mod tests {
                 OP_SPLIT => builder.emit_split(a as usize, b as usize),
                 OP_JMP => builder.emit_jmp(a as usize),
                 OP_MATCH => builder.emit_match(),
+                OP_CLASS => {
+                    builder.emit_class(crate::charclass::CharClass::from_id(a as u8).unwrap())
+                }
+                OP_SAVE => builder.emit_save(a as usize),
                 _ => panic!("unknown opcode"),
             }
         }
@@ -277,10 +363,82 @@ mod tests {
             (OP_MATCH, 0, 0),
         ]);
 
-        assert_eq!(program.epsilon_mask(0), 0b01011);
-        assert_eq!(program.epsilon_mask(1), 1 << 1);
-        assert_eq!(program.epsilon_mask(2), 0b10100);
-        assert_eq!(program.epsilon_mask(3), 1 << 3);
-        assert_eq!(program.epsilon_mask(4), 1 << 4);
+        assert_eq!(program.mask_words(), 1);
+        assert_eq!(program.epsilon_mask(0), [0b01011]);
+        assert_eq!(program.epsilon_mask(1), [1 << 1]);
+        assert_eq!(program.epsilon_mask(2), [0b10100]);
+        assert_eq!(program.epsilon_mask(3), [1 << 3]);
+        assert_eq!(program.epsilon_mask(4), [1 << 4]);
+
+        let mut ops = vec![(OP_SPLIT, 1, 99)];
+        ops.extend((1..99).map(|pc| (OP_JMP, pc + 1, 0)));
+        ops.push((OP_MATCH, 0, 0));
+        let program = program_from_ops(&ops);
+        assert_eq!(program.mask_words(), 2);
+        assert_eq!(program.epsilon_mask(0), [u64::MAX, (1 << 36) - 1]);
+        assert_eq!(
+            program.epsilon_mask(70),
+            [0, !((1 << 6) - 1) & ((1 << 36) - 1)]
+        );
+
+        for (len, words) in [(64, 1), (65, 2), (129, 4), (512, 8), (513, 0)] {
+            let mut ops = vec![(OP_CHAR, 'a' as u32, 0); len - 1];
+            ops.push((OP_MATCH, 0, 0));
+            assert_eq!(program_from_ops(&ops).mask_words(), words, "{len}");
+        }
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let program = program_from_ops(&[
+            (OP_SPLIT, 1, 3),
+            (OP_CHAR, 'é' as u32, 0),
+            (OP_JMP, 4, 0),
+            (OP_CHAR, 'b' as u32, 0),
+            (OP_MATCH, 0, 0),
+        ]);
+        let bytes = crate::serialize::leak_aligned(&program.to_bytes());
+        let loaded = Program::from_bytes(bytes).unwrap();
+        assert_eq!(loaded, program);
+        assert!(matches!(loaded.opcodes, std::borrow::Cow::Borrowed(_)));
+        assert!(matches!(loaded.op1, std::borrow::Cow::Borrowed(_)));
+
+        let broken = program_from_ops(&[(OP_JMP, 9, 0), (OP_MATCH, 0, 0)]);
+        let bytes = crate::serialize::leak_aligned(&broken.to_bytes());
+        assert!(matches!(
+            Program::from_bytes(bytes),
+            Err(crate::Error::Deserialize(_))
+        ));
+    }
+
+    #[test]
+    fn deserialize_rejects_invalid_data() {
+        let programs: &[&[(u8, u32, u32)]] = &[
+            &[(OP_JMP, 9, 0), (OP_MATCH, 0, 0)],
+            &[(OP_MATCH, 0, 0), (OP_CHAR, 'a' as u32, 0)],
+            &[(OP_MATCH, 0, 0), (OP_CLASS, 0, 0)],
+            &[(OP_CHAR, 'a' as u32, 0), (OP_JMP, 0, 0)],
+            &[
+                (OP_SAVE, 2, 0),
+                (OP_SAVE, 3, 0),
+                (OP_SAVE, 9, 0),
+                (OP_MATCH, 0, 0),
+            ],
+            &[],
+        ];
+        for ops in programs {
+            let bytes = crate::serialize::leak_aligned(&program_from_ops(ops).to_bytes());
+            assert!(
+                matches!(
+                    Program::from_bytes(bytes),
+                    Err(crate::Error::Deserialize(_))
+                ),
+                "{ops:?}"
+            );
+        }
+
+        let valid = program_from_ops(&[(OP_SAVE, 2, 0), (OP_SAVE, 3, 0), (OP_MATCH, 0, 0)]);
+        let bytes = crate::serialize::leak_aligned(&valid.to_bytes());
+        assert_eq!(Program::from_bytes(bytes).unwrap(), valid);
     }
 }
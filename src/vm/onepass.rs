@@ -0,0 +1,197 @@
+use foldhash::HashMapExt as _;
+
+const NONE: u32 = u32::MAX;
+
+#[derive(Debug)]
+struct Transition {
+    next: u32,
+    saves: Box<[u32]>,
+}
+
+#[derive(Debug)]
+struct State {
+    ascii: Box<[u32; 128]>,
+    chars: Vec<(char, u32)>,
+    any: u32,
+    transitions: Vec<Transition>,
+    accept: Option<Box<[u32]>>,
+}
+
+impl State {
+    #[inline]
+    fn transition(&self, ch: char) -> Option<&Transition> {
+        let index = if ch.is_ascii() {
+            self.ascii[ch as usize]
+        } else {
+            match self.chars.binary_search_by_key(&ch, |&(c, _)| c) {
+                Ok(i) => self.chars[i].1,
+                Err(_) => self.any,
+            }
+        };
+        self.transitions.get(index as usize)
+    }
+}
+
+#[derive(Debug)]
+pub struct OnePass {
+    states: Vec<State>,
+    slots: usize,
+}
+
+impl OnePass {
+    pub fn new(inst: &crate::vm::instruction::Program, slots: usize) -> Option<OnePass> {
+        let mut ids = foldhash::HashMap::new();
+        let mut queue = vec![0];
+        ids.insert(0, 0);
+        let mut states = Vec::new();
+        let mut visited = vec![false; inst.len()];
+        let mut stack = Vec::new();
+
+        while let Some(&pc) = queue.get(states.len()) {
+            let mut state = State {
+                ascii: Box::new([NONE; 128]),
+                chars: Vec::new(),
+                any: NONE,
+                transitions: Vec::new(),
+                accept: None,
+            };
+            visited.fill(false);
+            stack.push((pc, Vec::new()));
+            while let Some((pc, saves)) = stack.pop() {
+                if pc >= inst.len() {
+                    continue;
+                }
+                if std::mem::replace(&mut visited[pc], true) {
+                    return None;
+                }
+                match inst.opcode(pc) {
+                    crate::vm::instruction::OP_SPLIT => {
+                        stack.push((inst.operand2(pc) as usize, saves.clone()));
+                        stack.push((inst.operand1(pc) as usize, saves));
+                    }
+                    crate::vm::instruction::OP_JMP => {
+                        stack.push((inst.operand1(pc) as usize, saves))
+                    }
+                    crate::vm::instruction::OP_SAVE => {
+                        let mut saves = saves;
+                        saves.push(inst.operand1(pc));
+                        stack.push((pc + 1, saves));
+                    }
+                    crate::vm::instruction::OP_MATCH => state.accept = Some(saves.into()),
+                    opcode => {
+                        let next = *ids.entry(pc + 1).or_insert_with(|| {
+                            queue.push(pc + 1);
+                            queue.len() as u32 - 1
+                        });
+                        let index = state.transitions.len() as u32;
+                        state.transitions.push(Transition {
+                            next,
+                            saves: saves.into(),
+                        });
+                        let class = (opcode == crate::vm::instruction::OP_CLASS)
+                            .then(|| inst.char_class(pc));
+                        let ascii: Vec<u8> = match class {
+                            Some(class) => {
+                                (0u8..128).filter(|&b| class.matches(b as char)).collect()
+                            }
+                            None => {
+                                let ch = inst.char_literal(pc);
+                                if !ch.is_ascii() {
+                                    state.chars.push((ch, index));
+                                }
+                                ch.is_ascii().then_some(ch as u8).into_iter().collect()
+                            }
+                        };
+                        for byte in ascii {
+                            if std::mem::replace(&mut state.ascii[byte as usize], index) != NONE {
+                                return None;
+                            }
+                        }
+                        if class == Some(crate::charclass::CharClass::Any)
+                            && std::mem::replace(&mut state.any, index) != NONE
+                        {
+                            return None;
+                        }
+                    }
+                }
+            }
+
+            state.chars.sort_unstable();
+            let distinct = state.chars.windows(2).all(|pair| pair[0].0 != pair[1].0);
+            if !distinct || (state.any != NONE && !state.chars.is_empty()) {
+                return None;
+            }
+            states.push(state);
+        }
+
+        Some(OnePass { states, slots })
+    }
+
+    pub fn captures(&self, input: &str) -> Option<Vec<Option<usize>>> {
+        let mut slots = vec![None; self.slots];
+        let mut state = &self.states[0];
+        for (at, ch) in input.char_indices() {
+            let transition = state.transition(ch)?;
+            for &slot in &transition.saves {
+                slots[slot as usize] = Some(at);
+            }
+            state = &self.states[transition.next as usize];
+        }
+        for &slot in state.accept.as_deref()? {
+            slots[slot as usize] = Some(input.len());
+        }
+        Some(slots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(pattern: &str) -> (crate::vm::instruction::Program<'static>, usize) {
+        let mut lexer = crate::lexer::Lexer::new(pattern);
+        let mut parser = crate::parser::Parser::with_captures(&mut lexer);
+        let ast = parser.parse().unwrap();
+        let mut compiler = crate::vm::compile::Compiler::new();
+        compiler.compile(ast).unwrap();
+        (compiler.finish(), 2 * (parser.groups() + 1))
+    }
+
+    #[test]
+    fn one_pass_analysis() {
+        for pattern in [r"(\d+)-(\d+)", "a(b|c)*d", "(x)?y", "(é|e)(.)", r"(a|_)\w"] {
+            let (program, slots) = compile(pattern);
+            assert!(OnePass::new(&program, slots).is_some(), "{pattern}");
+        }
+        for pattern in [r"(\w+)(\d+)", "(a|ab)(c|bcd)", "(a*)*", "(.)|é", "a*a"] {
+            let (program, slots) = compile(pattern);
+            assert!(OnePass::new(&program, slots).is_none(), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn one_pass_captures() {
+        let (program, slots) = compile(r"(\d+)-(\d+)");
+        let onepass = OnePass::new(&program, slots).unwrap();
+        assert_eq!(
+            onepass.captures("12-345"),
+            Some(vec![None, None, Some(0), Some(2), Some(3), Some(6)])
+        );
+        assert_eq!(onepass.captures("12-"), None);
+        assert_eq!(onepass.captures("12-3x"), None);
+
+        let (program, slots) = compile("(x)?(正規|表現)+");
+        let onepass = OnePass::new(&program, slots).unwrap();
+        assert_eq!(
+            onepass.captures("正規表現"),
+            Some(vec![None, None, None, None, Some(6), Some(12)])
+        );
+        for input in ["x表現", "", "xx正規", "表現正規正規"] {
+            assert_eq!(
+                onepass.captures(input),
+                crate::vm::eval::captures(&program, input, slots),
+                "{input}"
+            );
+        }
+    }
+}